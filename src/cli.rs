@@ -0,0 +1,27 @@
+use std::str::FromStr;
+
+/// Returns value following option `name` in arguments, e.g. `--frames 20`.
+pub fn option_value<T: FromStr>(args: &[String], name: &str) -> Result<Option<T>, String>{
+	match args.iter().position(|arg| arg == name) {
+		Some(index) => {
+			let value = args.get(index + 1)
+				.ok_or_else(|| format!("Missing value for {}", name))?;
+			value.parse::<T>()
+				.map(Some)
+				.map_err(|_| format!("Invalid value '{}' for {}", value, name))
+		},
+		None => Ok(None)
+	}
+}
+
+pub fn has_flag(args: &[String], name: &str) -> bool{
+	args.iter().any(|arg| arg == name)
+}
+
+pub fn invalid_input(message: String) -> std::io::Error{
+	std::io::Error::new(std::io::ErrorKind::InvalidInput, message)
+}
+
+pub fn opencv_error(error: opencv::Error) -> std::io::Error{
+	std::io::Error::new(std::io::ErrorKind::Other, error.to_string())
+}
@@ -3,6 +3,7 @@
 
 use bevy::prelude::*;
 
+mod cli;
 mod entity_spawner;
 mod trackers;
 mod state;
@@ -38,6 +39,22 @@ fn spawn_basic_scene(
 }
 
 fn main() -> std::io::Result<()>{
+	let args: Vec<String> = std::env::args().collect();
+	match args.get(1).map(String::as_str) {
+		Some("cameras") => {
+			return trackers::opencv_trackers::camera::print_cameras(cli::has_flag(&args, "--json"));
+		},
+		Some("calibrate-intrinsics") => {
+			return trackers::opencv_trackers::calibration::intrinsics::run(&args[2..]);
+		},
+		Some("calibrate-extrinsics") => {
+			return trackers::opencv_trackers::calibration::extrinsics::run(&args[2..]);
+		},
+		Some("calibrate-ball") => {
+			return trackers::opencv_trackers::light_ball_trackers::ball_calibration::run(&args[2..]);
+		},
+		_ => {}
+	}
 
 	let mut app = bevy::app::App::new();
 	app
@@ -0,0 +1,646 @@
+use std::path::PathBuf;
+
+use opencv::{
+	prelude::*,
+	core,
+	calib3d,
+	imgproc,
+	highgui,
+	videoio
+};
+
+use crate::cli;
+use crate::trackers::opencv_trackers::calibration;
+use crate::trackers::opencv_trackers::calibration::intrinsics::{self, CalibrationBoard, CameraIntrinsics};
+use crate::trackers::opencv_trackers::light_ball_trackers::ball_calibration;
+use crate::trackers::opencv_trackers::light_ball_trackers::light_ball_tracker::{LightBallCalibration, LightBallTracker};
+use crate::trackers::opencv_trackers::light_ball_trackers::light_ball_definitions::LightBallDefinitions;
+use crate::trackers::opencv_trackers::tracker_definitions::DefinitionsFile;
+
+pub const FILE_NAME: &str = "extrinsics.yml";
+
+/// Rigid transform of points from reference frame into camera frame: `x_camera = rotation * x_reference + translation`.
+#[derive(Clone, Copy, Debug)]
+pub struct Pose{
+	pub rotation: [[f64; 3]; 3],
+	pub translation: [f64; 3]
+}
+
+impl Default for Pose {
+	fn default() -> Self {
+		Pose{
+			rotation: [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]],
+			translation: [0., 0., 0.]
+		}
+	}
+}
+
+impl Pose {
+	pub fn transform(&self, point: [f64; 3]) -> [f64; 3]{
+		let mut result = self.translation;
+		for row in 0..3 {
+			for column in 0..3 {
+				result[row] += self.rotation[row][column] * point[column];
+			}
+		}
+		result
+	}
+
+	pub fn inverse(&self) -> Pose{
+		let mut inverse = Pose::default();
+		for row in 0..3 {
+			for column in 0..3 {
+				inverse.rotation[row][column] = self.rotation[column][row];
+			}
+		}
+		let translation = inverse.transform(self.translation);
+		inverse.translation = [-translation[0], -translation[1], -translation[2]];
+		inverse
+	}
+
+	/// Pose which applies self first and `next` after it.
+	pub fn then(&self, next: &Pose) -> Pose{
+		let mut result = Pose::default();
+		for row in 0..3 {
+			for column in 0..3 {
+				result.rotation[row][column] = (0..3).map(|k| next.rotation[row][k] * self.rotation[k][column]).sum();
+			}
+		}
+		result.translation = next.transform(self.translation);
+		result
+	}
+
+	pub fn from_matrices(rotation: &core::Mat, translation: &core::Mat) -> opencv::Result<Pose>{
+		let mut pose = Pose::default();
+		for row in 0..3 {
+			for column in 0..3 {
+				pose.rotation[row][column] = *rotation.at_2d::<f64>(row as i32, column as i32)?;
+			}
+			pose.translation[row] = *translation.at::<f64>(row as i32)?;
+		}
+		Ok(pose)
+	}
+
+	/// Pose from rotation vector and translation vector, as returned by solve_pnp.
+	pub fn from_rvec_tvec(rvec: &core::Mat, tvec: &core::Mat) -> opencv::Result<Pose>{
+		let mut rotation = core::Mat::default();
+		calib3d::rodrigues(rvec, &mut rotation, &mut core::Mat::default())?;
+		Self::from_matrices(&rotation, tvec)
+	}
+
+	pub fn rotation_matrix(&self) -> opencv::Result<core::Mat>{
+		core::Mat::from_slice_2d(&self.rotation)
+	}
+
+	pub fn translation_matrix(&self) -> opencv::Result<core::Mat>{
+		core::Mat::from_slice_2d(&[[self.translation[0]], [self.translation[1]], [self.translation[2]]])
+	}
+
+	pub fn to_rvec_tvec(&self) -> opencv::Result<(core::Mat, core::Mat)>{
+		let mut rvec = core::Mat::default();
+		calib3d::rodrigues(&self.rotation_matrix()?, &mut rvec, &mut core::Mat::default())?;
+		Ok((rvec, self.translation_matrix()?))
+	}
+
+	/// 3x4 `[rotation | translation]` matrix.
+	pub fn projection_matrix(&self) -> opencv::Result<core::Mat>{
+		let mut rows = [[0.; 4]; 3];
+		for row in 0..3 {
+			rows[row][..3].copy_from_slice(&self.rotation[row]);
+			rows[row][3] = self.translation[row];
+		}
+		core::Mat::from_slice_2d(&rows)
+	}
+}
+
+/// Pose of the camera relative to the reference camera, all cameras calibrated together share the same reference.
+#[derive(Clone, Debug)]
+pub struct CameraExtrinsics{
+	/// transforms points from the reference camera frame into this camera frame, in metres
+	pub pose: Pose,
+	pub reference_camera: String,
+	/// RMS reprojection error in pixels
+	pub reprojection_error: f64
+}
+
+impl CameraExtrinsics {
+	pub fn path(camera_id: &str) -> PathBuf{
+		calibration::camera_config_directory(camera_id).join(FILE_NAME)
+	}
+
+	pub fn save(&self, camera_id: &str) -> opencv::Result<PathBuf>{
+		let path = Self::path(camera_id);
+		let mut storage = calibration::open_for_writing(&path)?;
+		storage.write_str("camera_id", camera_id)?;
+		storage.write_str("reference_camera", &self.reference_camera)?;
+		storage.write_mat("rotation", &self.pose.rotation_matrix()?)?;
+		storage.write_mat("translation", &self.pose.translation_matrix()?)?;
+		storage.write_f64("reprojection_error", self.reprojection_error)?;
+		storage.release()?;
+		Ok(path)
+	}
+
+	pub fn load(camera_id: &str) -> opencv::Result<Self>{
+		let mut storage = calibration::open_for_reading(&Self::path(camera_id))?;
+		let extrinsics = CameraExtrinsics{
+			pose: Pose::from_matrices(&storage.get("rotation")?.mat()?, &storage.get("translation")?.mat()?)?,
+			reference_camera: storage.get("reference_camera")?.to_string()?,
+			reprojection_error: storage.get("reprojection_error")?.real()?
+		};
+		storage.release()?;
+		Ok(extrinsics)
+	}
+}
+
+/// What is seen by all cameras at once while calibrating.
+pub enum ExtrinsicTarget{
+	/// single light ball moved through the shared view, scale comes from its size/distance calibration
+	Wand{tracker_name: String},
+	/// printed chessboard or ChArUco board
+	Marker{board: CalibrationBoard}
+}
+
+pub struct ExtrinsicCalibrationOptions{
+	/// camera nodes, video files or stream urls, first one is the reference camera
+	pub sources: Vec<String>,
+	pub camera_ids: Vec<String>,
+	pub target: ExtrinsicTarget,
+	/// number of views seen by the reference camera and at least one other camera
+	pub frames: usize,
+	/// minimum number of frames between two used views
+	pub frame_step: usize
+}
+
+impl ExtrinsicCalibrationOptions {
+	pub const USAGE: &'static str = "rtrack calibrate-extrinsics <reference source> <source>... [--tracker NAME | --board [--charuco] [--columns N] [--rows N] [--square METRES] [--marker METRES]] [--frames N] [--step N] [--camera-ids ID,ID,..]";
+
+	pub fn from_args(args: &[String]) -> Result<Self, String>{
+		let sources: Vec<String> = args.iter()
+			.take_while(|arg| !arg.starts_with("--"))
+			.cloned()
+			.collect();
+		if sources.len() < 2 {
+			return Err(format!("At least 2 cameras are needed\nUsage: {}", Self::USAGE));
+		}
+
+		let camera_ids = match cli::option_value::<String>(args, "--camera-ids")? {
+			Some(ids) => ids.split(',').map(str::to_owned).collect(),
+			None => sources.iter().map(|source| intrinsics::camera_id_of(source)).collect::<Vec<_>>()
+		};
+		if camera_ids.len() != sources.len() {
+			return Err("Number of camera ids doesn't match number of cameras".to_owned());
+		}
+
+		let (target, frames, frame_step) = if cli::has_flag(args, "--board") {
+			(ExtrinsicTarget::Marker{board: CalibrationBoard::from_args(args)?}, 20, 15)
+		}
+		else {
+			let tracker_name = cli::option_value::<String>(args, "--tracker")?
+				.ok_or_else(|| format!("Missing --tracker or --board\nUsage: {}", Self::USAGE))?;
+			if LightBallDefinitions::from_env().find(&tracker_name).is_none() {
+				return Err(format!("Unknown tracker '{}'", tracker_name));
+			}
+			(ExtrinsicTarget::Wand{tracker_name: tracker_name}, 300, 2)
+		};
+
+		Ok(ExtrinsicCalibrationOptions{
+			sources: sources,
+			camera_ids: camera_ids,
+			target: target,
+			frames: cli::option_value(args, "--frames")?.unwrap_or(frames),
+			frame_step: cli::option_value(args, "--step")?.unwrap_or(frame_step)
+		})
+	}
+}
+
+/// Detection of the target in single camera, positions are undistorted pixels.
+enum TargetView{
+	Ball{position: core::Point2f, radius: f64},
+	Board{object_points: core::Vector<core::Point3f>, image_points: core::Vector<core::Point2f>}
+}
+
+struct CalibratedCamera{
+	id: String,
+	capture: videoio::VideoCapture,
+	/// scaled to size of captured frames
+	intrinsics: CameraIntrinsics
+}
+
+/// Points in the reference frame and their undistorted pixels in calibrated camera.
+#[derive(Default)]
+struct Correspondences{
+	object_points: Vec<[f64; 3]>,
+	image_points: Vec<core::Point2f>,
+	/// pose estimate used as a starting point of refinement
+	initial_pose: Option<Pose>
+}
+
+/// Entry point of `rtrack calibrate-extrinsics`.
+pub fn run(args: &[String]) -> std::io::Result<()>{
+	let options = ExtrinsicCalibrationOptions::from_args(args).map_err(cli::invalid_input)?;
+	let extrinsics = calibrate(&options).map_err(cli::opencv_error)?;
+
+	for (camera_id, camera_extrinsics) in options.camera_ids.iter().zip(extrinsics.iter()) {
+		let path = camera_extrinsics.save(camera_id).map_err(cli::opencv_error)?;
+		println!("{}: RMS reprojection error {:.4} px, saved into {}", camera_id, camera_extrinsics.reprojection_error, path.display());
+	}
+	Ok(())
+}
+
+/// Collects views of the target seen by several cameras at once and solves pose of every camera relative to the first one.
+pub fn calibrate(options: &ExtrinsicCalibrationOptions) -> opencv::Result<Vec<CameraExtrinsics>>{
+	let mut cameras = vec![];
+	for (source, camera_id) in options.sources.iter().zip(options.camera_ids.iter()) {
+		let capture = videoio::VideoCapture::from_file(source, videoio::CAP_ANY)?;
+		if !capture.is_opened()? {
+			return Err(opencv::Error::new(core::StsError, format!("Unable to open {}", source)));
+		}
+		let intrinsics = CameraIntrinsics::load(camera_id)
+			.map_err(|_| opencv::Error::new(core::StsError, format!("Camera {} has no intrinsics, run calibrate-intrinsics first", camera_id)))?;
+		cameras.push(CalibratedCamera{id: camera_id.clone(), capture: capture, intrinsics: intrinsics});
+	}
+
+	let views = collect_views(&mut cameras, options)?;
+	let reference_id = cameras[0].id.clone();
+
+	let mut extrinsics = vec![CameraExtrinsics{pose: Pose::default(), reference_camera: reference_id.clone(), reprojection_error: 0.}];
+	for index in 1..cameras.len() {
+		let correspondences = match &options.target {
+			ExtrinsicTarget::Wand{tracker_name} => {
+				let ball_calibration = LightBallCalibration::load(&reference_id, tracker_name)
+					.map_err(|_| opencv::Error::new(core::StsError, format!("Tracker {} has no calibration for camera {}, run calibrate-ball first", tracker_name, reference_id)))?;
+				wand_correspondences(&views, &cameras, index, &ball_calibration)?
+			},
+			ExtrinsicTarget::Marker{..} => marker_correspondences(&views, &cameras, index)?
+		};
+		if correspondences.object_points.len() < 6 {
+			return Err(opencv::Error::new(core::StsError, format!("Camera {} shares too few views with {}", cameras[index].id, reference_id)));
+		}
+		let (pose, error) = refine_pose(&correspondences, &cameras[index].intrinsics)?;
+		extrinsics.push(CameraExtrinsics{pose: pose, reference_camera: reference_id.clone(), reprojection_error: error});
+	}
+	Ok(extrinsics)
+}
+
+/// Reads frames of all cameras together (grab first, so frames are as close in time as possible) and detects the target,
+///  returns views indexed [view][camera].
+fn collect_views(cameras: &mut [CalibratedCamera], options: &ExtrinsicCalibrationOptions) -> opencv::Result<Vec<Vec<Option<TargetView>>>>{
+	let mut ball_tracker = match &options.target {
+		ExtrinsicTarget::Wand{tracker_name} => LightBallDefinitions::from_env().find(tracker_name)
+			.map(|definition| LightBallTracker::from_definition(definition, definition.get_color_range())),
+		ExtrinsicTarget::Marker{..} => None
+	};
+	for camera in cameras.iter() {
+		highgui::named_window(&camera.id, highgui::WINDOW_NORMAL)?;
+	}
+
+	let mut views = vec![];
+	let mut frames = vec![core::Mat::default(); cameras.len()];
+	let mut frames_since_used = options.frame_step;
+	'capture: while views.len() < options.frames {
+		for camera in cameras.iter_mut() {
+			if !camera.capture.grab()? {
+				break 'capture;
+			}
+		}
+		for (camera, frame) in cameras.iter_mut().zip(frames.iter_mut()) {
+			camera.capture.retrieve(frame, 0)?;
+		}
+		frames_since_used += 1;
+
+		let mut view = vec![];
+		for (camera, frame) in cameras.iter_mut().zip(frames.iter_mut()) {
+			if frame.empty() {
+				view.push(None);
+				continue;
+			}
+			camera.intrinsics = camera.intrinsics.scaled_to(frame.size()?)?;
+			let intrinsics = &camera.intrinsics;
+			let detection = match (&options.target, ball_tracker.as_mut()) {
+				(ExtrinsicTarget::Wand{..}, Some(ball_tracker)) => {
+					match ball_calibration::detect_ball(frame, ball_tracker)? {
+						Some(circle) => {
+							imgproc::circle(
+								frame,
+								core::Point::new(circle.position.x as i32, circle.position.y as i32),
+								circle.radius as i32,
+								core::Scalar::new(0., 255., 0., 0.),
+								2,
+								imgproc::LINE_8,
+								0
+							)?;
+							let position = intrinsics.undistort_pixels(&core::Vector::from_iter([circle.position]))?.get(0)?;
+							Some(TargetView::Ball{position: position, radius: circle.radius as f64})
+						},
+						None => None
+					}
+				},
+				(ExtrinsicTarget::Marker{board}, _) => {
+					let mut gray = core::Mat::default();
+					imgproc::cvt_color(frame, &mut gray, imgproc::COLOR_BGR2GRAY, 0)?;
+					match board.detect(&gray)? {
+						Some(detection) => {
+							for point in detection.image_points.iter() {
+								imgproc::circle(frame, core::Point::new(point.x as i32, point.y as i32), 4, core::Scalar::new(0., 255., 0., 0.), 2, imgproc::LINE_8, 0)?;
+							}
+							Some(TargetView::Board{
+								object_points: detection.object_points,
+								image_points: intrinsics.undistort_pixels(&detection.image_points)?
+							})
+						},
+						None => None
+					}
+				},
+				_ => None
+			};
+			view.push(detection);
+		}
+
+		let seen_together = view[0].is_some() && view[1..].iter().any(Option::is_some);
+		if seen_together && frames_since_used >= options.frame_step {
+			frames_since_used = 0;
+			views.push(view);
+		}
+
+		for (camera, frame) in cameras.iter().zip(frames.iter_mut()) {
+			if frame.empty() {
+				continue;
+			}
+			imgproc::put_text(
+				frame,
+				&format!("{}/{} views, ESC to finish", views.len(), options.frames),
+				core::Point::new(20, 40),
+				imgproc::FONT_HERSHEY_SIMPLEX,
+				1.0,
+				core::Scalar::new(255., 255., 255., 255.),
+				2,
+				imgproc::LINE_8,
+				false
+			)?;
+			highgui::imshow(&camera.id, frame)?;
+		}
+		if highgui::wait_key(1)? == 27 {
+			break;
+		}
+	}
+	for camera in cameras.iter() {
+		highgui::destroy_window(&camera.id)?;
+	}
+	Ok(views)
+}
+
+/// Relative pose from essential matrix of ball positions seen by both cameras, scaled by ball distance in the reference camera.
+/// Returns triangulated ball positions in the reference frame and their undistorted pixels in camera `index`.
+fn wand_correspondences(views: &[Vec<Option<TargetView>>], cameras: &[CalibratedCamera], index: usize, ball_calibration: &LightBallCalibration) -> opencv::Result<Correspondences>{
+	let reference = &cameras[0].intrinsics;
+	let camera = &cameras[index].intrinsics;
+
+	let mut reference_points = core::Vector::<core::Point2d>::new();
+	let mut camera_points = core::Vector::<core::Point2d>::new();
+	let mut camera_pixels = vec![];
+	let mut distances = vec![];
+	for view in views {
+		if let (Some(TargetView::Ball{position: reference_position, radius}), Some(TargetView::Ball{position, ..})) = (&view[0], &view[index]) {
+			reference_points.push(normalize(reference, *reference_position)?);
+			camera_points.push(normalize(camera, *position)?);
+			camera_pixels.push(*position);
+			distances.push(ball_calibration.distance(*radius));
+		}
+	}
+	if reference_points.len() < 8 {
+		return Ok(Correspondences::default());
+	}
+
+	// in normalized coordinates camera matrix is identity, threshold is roughly one pixel
+	let identity = core::Mat::eye(3, 3, core::CV_64F)?.to_mat()?;
+	let (focal_length, _) = reference.focal_length()?;
+	let mut inliers = core::Mat::default();
+	let essential = calib3d::find_essential_mat(
+		&reference_points,
+		&camera_points,
+		&identity,
+		calib3d::RANSAC,
+		0.999,
+		1. / focal_length,
+		1000,
+		&mut inliers
+	)?;
+	let mut rotation = core::Mat::default();
+	let mut translation = core::Mat::default();
+	calib3d::recover_pose_estimated(&essential, &reference_points, &camera_points, &identity, &mut rotation, &mut translation, &mut inliers)?;
+	let mut pose = Pose::from_matrices(&rotation, &translation)?;
+
+	let mut triangulated = core::Mat::default();
+	calib3d::triangulate_points(
+		&Pose::default().projection_matrix()?,
+		&pose.projection_matrix()?,
+		&reference_points,
+		&camera_points,
+		&mut triangulated
+	)?;
+
+	// translation from essential matrix has unit length, scale is median of ball distance / triangulated distance
+	let mut points = vec![];
+	let mut scales = vec![];
+	for point_index in 0..reference_points.len() {
+		if *inliers.at::<u8>(point_index as i32)? == 0 {
+			continue;
+		}
+		let w = *triangulated.at_2d::<f64>(3, point_index as i32)?;
+		let point = [
+			*triangulated.at_2d::<f64>(0, point_index as i32)? / w,
+			*triangulated.at_2d::<f64>(1, point_index as i32)? / w,
+			*triangulated.at_2d::<f64>(2, point_index as i32)? / w
+		];
+		let length = (point[0] * point[0] + point[1] * point[1] + point[2] * point[2]).sqrt();
+		if point[2] > 0. && length.is_finite() && distances[point_index].is_finite() {
+			scales.push(distances[point_index] / length);
+			points.push((point, camera_pixels[point_index]));
+		}
+	}
+	if scales.is_empty() {
+		return Ok(Correspondences::default());
+	}
+	scales.sort_by(|a, b| a.total_cmp(b));
+	let scale = scales[scales.len() / 2];
+	pose.translation = [pose.translation[0] * scale, pose.translation[1] * scale, pose.translation[2] * scale];
+
+	let (object_points, image_points) = points.into_iter()
+		.map(|(point, pixel)| ([point[0] * scale, point[1] * scale, point[2] * scale], pixel))
+		.unzip();
+	Ok(Correspondences{object_points: object_points, image_points: image_points, initial_pose: Some(pose)})
+}
+
+/// Board corners seen by camera `index`, moved into the reference frame by board pose seen by the reference camera.
+fn marker_correspondences(views: &[Vec<Option<TargetView>>], cameras: &[CalibratedCamera], index: usize) -> opencv::Result<Correspondences>{
+	let reference = &cameras[0].intrinsics;
+	let mut object_points = vec![];
+	let mut image_points = vec![];
+	for view in views {
+		if let (Some(TargetView::Board{object_points: reference_object, image_points: reference_image}), Some(TargetView::Board{object_points: board_points, image_points: pixels})) = (&view[0], &view[index]) {
+			let mut rvec = core::Mat::default();
+			let mut tvec = core::Mat::default();
+			if !calib3d::solve_pnp(reference_object, reference_image, &reference.camera_matrix, &core::Mat::default(), &mut rvec, &mut tvec, false, calib3d::SOLVEPNP_ITERATIVE)? {
+				continue;
+			}
+			let board_pose = Pose::from_rvec_tvec(&rvec, &tvec)?;
+			for (board_point, pixel) in board_points.iter().zip(pixels.iter()) {
+				object_points.push(board_pose.transform([board_point.x as f64, board_point.y as f64, board_point.z as f64]));
+				image_points.push(pixel);
+			}
+		}
+	}
+	Ok(Correspondences{object_points: object_points, image_points: image_points, initial_pose: None})
+}
+
+/// Pose of camera from reference frame points and their undistorted pixels, refined by Levenberg-Marquardt.
+/// Without initial pose, it's estimated by RANSAC first. Returns pose with RMS reprojection error in pixels.
+fn refine_pose(correspondences: &Correspondences, intrinsics: &CameraIntrinsics) -> opencv::Result<(Pose, f64)>{
+	let object_points = &correspondences.object_points;
+	let image_points = &correspondences.image_points;
+	let objects = core::Vector::<core::Point3f>::from_iter(object_points.iter().map(|point| core::Point3f::new(point[0] as f32, point[1] as f32, point[2] as f32)));
+	let pixels = core::Vector::<core::Point2f>::from_iter(image_points.iter().copied());
+	let no_distortion = core::Mat::default();
+
+	let (mut rvec, mut tvec) = match &correspondences.initial_pose {
+		Some(pose) => pose.to_rvec_tvec()?,
+		None => {
+			let mut rvec = core::Mat::default();
+			let mut tvec = core::Mat::default();
+			calib3d::solve_pnp_ransac(
+				&objects,
+				&pixels,
+				&intrinsics.camera_matrix,
+				&no_distortion,
+				&mut rvec,
+				&mut tvec,
+				false,
+				100,
+				4.,
+				0.99,
+				&mut core::Mat::default(),
+				calib3d::SOLVEPNP_ITERATIVE
+			)?;
+			(rvec, tvec)
+		}
+	};
+	calib3d::solve_pnp_refine_lm(
+		&objects,
+		&pixels,
+		&intrinsics.camera_matrix,
+		&no_distortion,
+		&mut rvec,
+		&mut tvec,
+		core::TermCriteria::new(core::TermCriteria_COUNT + core::TermCriteria_EPS, 50, 1e-8)?
+	)?;
+	let pose = Pose::from_rvec_tvec(&rvec, &tvec)?;
+
+	let (fx, fy) = intrinsics.focal_length()?;
+	let (cx, cy) = intrinsics.principal_point()?;
+	let mut squared_error = 0.;
+	for (object, pixel) in object_points.iter().zip(image_points.iter()) {
+		let point = pose.transform(*object);
+		let dx = fx * point[0] / point[2] + cx - pixel.x as f64;
+		let dy = fy * point[1] / point[2] + cy - pixel.y as f64;
+		squared_error += dx * dx + dy * dy;
+	}
+	Ok((pose, (squared_error / object_points.len().max(1) as f64).sqrt()))
+}
+
+/// Undistorted pixel to coordinates on the normalized image plane (z = 1).
+fn normalize(intrinsics: &CameraIntrinsics, pixel: core::Point2f) -> opencv::Result<core::Point2d>{
+	let (fx, fy) = intrinsics.focal_length()?;
+	let (cx, cy) = intrinsics.principal_point()?;
+	Ok(core::Point2d::new((pixel.x as f64 - cx) / fx, (pixel.y as f64 - cy) / fy))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const FOCAL_LENGTH: f64 = 500.;
+	const BALL_RADIUS: f64 = 0.04;
+
+	fn assert_pose_near(actual: &Pose, expected: &Pose, tolerance: f64){
+		for row in 0..3 {
+			for column in 0..3 {
+				assert!((actual.rotation[row][column] - expected.rotation[row][column]).abs() < tolerance, "{:?} != {:?}", actual, expected);
+			}
+			assert!((actual.translation[row] - expected.translation[row]).abs() < tolerance, "{:?} != {:?}", actual, expected);
+		}
+	}
+
+	/// Rotation by `angle` about y and then by `angle / 2` about x, with `translation`.
+	fn pose(angle: f64, translation: [f64; 3]) -> Pose{
+		let (sin_y, cos_y) = angle.sin_cos();
+		let (sin_x, cos_x) = (angle / 2.).sin_cos();
+		let about_y = Pose{rotation: [[cos_y, 0., sin_y], [0., 1., 0.], [-sin_y, 0., cos_y]], translation: [0.; 3]};
+		let about_x = Pose{rotation: [[1., 0., 0.], [0., cos_x, -sin_x], [0., sin_x, cos_x]], translation: translation};
+		about_y.then(&about_x)
+	}
+
+	#[test]
+	fn pose_then_its_inverse_is_identity(){
+		let pose = pose(0.4, [0.3, -0.2, 1.5]);
+		assert_pose_near(&pose.then(&pose.inverse()), &Pose::default(), 1e-12);
+		assert_pose_near(&pose.inverse().then(&pose), &Pose::default(), 1e-12);
+
+		let point = [0.1, 0.2, 0.3];
+		let moved = pose.then(&pose.inverse()).transform(point);
+		for axis in 0..3 {
+			assert!((moved[axis] - point[axis]).abs() < 1e-12);
+		}
+	}
+
+	#[test]
+	fn rotation_and_translation_vectors_round_trip(){
+		let pose = pose(0.7, [-0.5, 0.1, 2.]);
+		let (rvec, tvec) = pose.to_rvec_tvec().unwrap();
+		assert_pose_near(&Pose::from_rvec_tvec(&rvec, &tvec).unwrap(), &pose, 1e-9);
+		assert_pose_near(&Pose::from_matrices(&pose.rotation_matrix().unwrap(), &pose.translation_matrix().unwrap()).unwrap(), &pose, 1e-12);
+	}
+
+	fn camera(id: &str) -> CalibratedCamera{
+		CalibratedCamera{
+			id: id.to_owned(),
+			capture: videoio::VideoCapture::default().unwrap(),
+			intrinsics: CameraIntrinsics::ideal(core::Size::new(640, 480), FOCAL_LENGTH).unwrap()
+		}
+	}
+
+	/// Ball at `point` of the camera frame as seen by ideal camera.
+	fn ball_view(point: [f64; 3]) -> TargetView{
+		let distance = (point[0] * point[0] + point[1] * point[1] + point[2] * point[2]).sqrt();
+		TargetView::Ball{
+			position: core::Point2f::new((FOCAL_LENGTH * point[0] / point[2] + 320.) as f32, (FOCAL_LENGTH * point[1] / point[2] + 240.) as f32),
+			radius: FOCAL_LENGTH * BALL_RADIUS / distance
+		}
+	}
+
+	#[test]
+	fn wand_recovers_relative_pose_and_scale(){
+		let cameras = [camera("reference"), camera("other")];
+		let relative = pose(0.3, [-0.6, 0.05, 0.1]);
+		// wand positions spread through the volume seen by both cameras
+		let points: Vec<[f64; 3]> = (0..30)
+			.map(|index| [
+				-0.6 + 0.04 * index as f64,
+				-0.3 + 0.6 * ((index * 7) % 11) as f64 / 10.,
+				2. + 2. * ((index * 5) % 13) as f64 / 12.
+			])
+			.collect();
+		let views: Vec<Vec<Option<TargetView>>> = points.iter()
+			.map(|point| vec![Some(ball_view(*point)), Some(ball_view(relative.transform(*point)))])
+			.collect();
+		let ball_calibration = LightBallCalibration::from_focal_length(&core::Size::new(640, 480), FOCAL_LENGTH, BALL_RADIUS);
+
+		let correspondences = wand_correspondences(&views, &cameras, 1, &ball_calibration).unwrap();
+		assert_pose_near(&correspondences.initial_pose.unwrap(), &relative, 5e-3);
+		assert_eq!(correspondences.object_points.len(), points.len());
+		for (object_point, point) in correspondences.object_points.iter().zip(points.iter()) {
+			for axis in 0..3 {
+				assert!((object_point[axis] - point[axis]).abs() < 1e-2, "{:?} != {:?}", object_point, point);
+			}
+		}
+	}
+}
@@ -0,0 +1,495 @@
+use std::path::PathBuf;
+
+use opencv::{
+	prelude::*,
+	core,
+	calib3d,
+	imgproc,
+	highgui,
+	videoio,
+	aruco
+};
+
+use crate::cli;
+use crate::trackers::opencv_trackers::{calibration, camera};
+use crate::trackers::opencv_trackers::calibration::undistortion::UndistortionMode;
+
+pub const FILE_NAME: &str = "intrinsics.yml";
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LensModel{
+	#[default]
+	Pinhole,
+	Fisheye
+}
+
+impl LensModel {
+	pub fn name(&self) -> &'static str{
+		match self {
+			LensModel::Pinhole => "pinhole",
+			LensModel::Fisheye => "fisheye",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<Self>{
+		match name {
+			"pinhole" => Some(LensModel::Pinhole),
+			"fisheye" => Some(LensModel::Fisheye),
+			_ => None
+		}
+	}
+}
+
+/// Camera matrix and distortion coefficients of single camera, as computed by calibrate_camera.
+#[derive(Clone)]
+pub struct CameraIntrinsics{
+	/// 3x3 matrix with focal lengths and principal point in pixels
+	pub camera_matrix: core::Mat,
+	/// k1, k2, p1, p2, k3 for pinhole model or k1, k2, k3, k4 for fisheye model
+	pub distortion: core::Mat,
+	pub image_size: core::Size,
+	pub model: LensModel,
+	/// RMS reprojection error in pixels
+	pub reprojection_error: f64,
+	/// how this camera's frames are undistorted while tracking
+	pub undistortion: UndistortionMode
+}
+
+impl CameraIntrinsics {
+	pub fn path(camera_id: &str) -> PathBuf{
+		calibration::camera_config_directory(camera_id).join(FILE_NAME)
+	}
+
+	pub fn save(&self, camera_id: &str) -> opencv::Result<PathBuf>{
+		let path = Self::path(camera_id);
+		let mut storage = calibration::open_for_writing(&path)?;
+		storage.write_str("camera_id", camera_id)?;
+		storage.write_str("model", self.model.name())?;
+		storage.write_i32("image_width", self.image_size.width)?;
+		storage.write_i32("image_height", self.image_size.height)?;
+		storage.write_mat("camera_matrix", &self.camera_matrix)?;
+		storage.write_mat("distortion", &self.distortion)?;
+		storage.write_f64("reprojection_error", self.reprojection_error)?;
+		storage.write_str("undistortion", self.undistortion.name())?;
+		storage.release()?;
+		Ok(path)
+	}
+
+	pub fn load(camera_id: &str) -> opencv::Result<Self>{
+		let mut storage = calibration::open_for_reading(&Self::path(camera_id))?;
+		let intrinsics = CameraIntrinsics{
+			camera_matrix: storage.get("camera_matrix")?.mat()?,
+			distortion: storage.get("distortion")?.mat()?,
+			image_size: core::Size::new(storage.get("image_width")?.to_i32()?, storage.get("image_height")?.to_i32()?),
+			model: LensModel::from_name(&storage.get("model")?.to_string()?).unwrap_or_default(),
+			reprojection_error: storage.get("reprojection_error")?.real()?,
+			undistortion: UndistortionMode::from_name(&storage.get("undistortion")?.to_string()?).unwrap_or_default()
+		};
+		storage.release()?;
+		Ok(intrinsics)
+	}
+
+	/// Ideal pinhole camera without distortion, with principal point in the centre of the image.
+	pub fn ideal(image_size: core::Size, focal_length: f64) -> opencv::Result<Self>{
+		Ok(CameraIntrinsics{
+			camera_matrix: core::Mat::from_slice_2d(&[
+				[focal_length, 0., image_size.width as f64 / 2.],
+				[0., focal_length, image_size.height as f64 / 2.],
+				[0., 0., 1.]
+			])?,
+			distortion: core::Mat::zeros(1, 5, core::CV_64F)?.to_mat()?,
+			image_size: image_size,
+			model: LensModel::Pinhole,
+			reprojection_error: 0.,
+			undistortion: UndistortionMode::Off
+		})
+	}
+
+	/// Back-projects undistorted pixel through the camera matrix and returns point on that ray `distance` away from the camera centre.
+	/// Result is in the camera frame (same convention as OpenCV): x points right, y down and z forward along the optical axis,
+	///  in units of `distance`.
+	pub fn back_project(&self, pixel: core::Point2f, distance: f64) -> opencv::Result<core::Point3d>{
+		let (fx, fy) = self.focal_length()?;
+		let (cx, cy) = self.principal_point()?;
+		let ray = core::Point3d::new((pixel.x as f64 - cx) / fx, (pixel.y as f64 - cy) / fy, 1.);
+		let length = (ray.x * ray.x + ray.y * ray.y + 1.).sqrt();
+		Ok(core::Point3d::new(ray.x * distance / length, ray.y * distance / length, ray.z * distance / length))
+	}
+
+	/// Removes lens distortion from pixel positions, undistorted positions stay in pixels of the same camera matrix.
+	pub fn undistort_pixels(&self, pixels: &core::Vector<core::Point2f>) -> opencv::Result<core::Vector<core::Point2f>>{
+		let mut undistorted = core::Vector::<core::Point2f>::new();
+		if pixels.is_empty() {
+			return Ok(undistorted);
+		}
+		match self.model {
+			LensModel::Pinhole => calib3d::undistort_points(
+				pixels,
+				&mut undistorted,
+				&self.camera_matrix,
+				&self.distortion,
+				&core::Mat::default(),
+				&self.camera_matrix
+			)?,
+			LensModel::Fisheye => calib3d::fisheye_undistort_points(
+				pixels,
+				&mut undistorted,
+				&self.camera_matrix,
+				&self.distortion,
+				&core::Mat::default(),
+				&self.camera_matrix
+			)?
+		}
+		Ok(undistorted)
+	}
+
+	/// Focal lengths (fx, fy) in pixels.
+	pub fn focal_length(&self) -> opencv::Result<(f64, f64)>{
+		Ok((*self.camera_matrix.at_2d::<f64>(0, 0)?, *self.camera_matrix.at_2d::<f64>(1, 1)?))
+	}
+
+	/// Principal point (cx, cy) in pixels.
+	pub fn principal_point(&self) -> opencv::Result<(f64, f64)>{
+		Ok((*self.camera_matrix.at_2d::<f64>(0, 2)?, *self.camera_matrix.at_2d::<f64>(1, 2)?))
+	}
+
+	/// Intrinsics for frames of different resolution than the calibrated one (with same field of view).
+	pub fn scaled_to(&self, size: core::Size) -> opencv::Result<CameraIntrinsics>{
+		let mut scaled = self.clone();
+		if size == self.image_size || self.image_size.width == 0 || self.image_size.height == 0 {
+			return Ok(scaled);
+		}
+		let scale_x = size.width as f64 / self.image_size.width as f64;
+		let scale_y = size.height as f64 / self.image_size.height as f64;
+		*scaled.camera_matrix.at_2d_mut::<f64>(0, 0)? *= scale_x;
+		*scaled.camera_matrix.at_2d_mut::<f64>(0, 2)? *= scale_x;
+		*scaled.camera_matrix.at_2d_mut::<f64>(1, 1)? *= scale_y;
+		*scaled.camera_matrix.at_2d_mut::<f64>(1, 2)? *= scale_y;
+		scaled.image_size = size;
+		Ok(scaled)
+	}
+}
+
+/// Printed calibration target, all sizes are in metres.
+#[derive(Clone, Copy, Debug)]
+pub enum CalibrationBoard{
+	/// `columns` x `rows` inner corners
+	Chessboard{columns: i32, rows: i32, square_size: f32},
+	/// `columns` x `rows` squares with DICT_4X4_50 markers
+	Charuco{columns: i32, rows: i32, square_size: f32, marker_size: f32}
+}
+
+/// Corners of the board found in single image, with their position on the board.
+pub struct BoardDetection{
+	pub object_points: core::Vector<core::Point3f>,
+	pub image_points: core::Vector<core::Point2f>
+}
+
+impl CalibrationBoard {
+	/// Board described by `--charuco`, `--columns`, `--rows`, `--square` and `--marker` options.
+	pub fn from_args(args: &[String]) -> Result<Self, String>{
+		if cli::has_flag(args, "--charuco") {
+			Ok(CalibrationBoard::Charuco{
+				columns: cli::option_value(args, "--columns")?.unwrap_or(5),
+				rows: cli::option_value(args, "--rows")?.unwrap_or(7),
+				square_size: cli::option_value(args, "--square")?.unwrap_or(0.04),
+				marker_size: cli::option_value(args, "--marker")?.unwrap_or(0.02)
+			})
+		}
+		else {
+			Ok(CalibrationBoard::Chessboard{
+				columns: cli::option_value(args, "--columns")?.unwrap_or(9),
+				rows: cli::option_value(args, "--rows")?.unwrap_or(6),
+				square_size: cli::option_value(args, "--square")?.unwrap_or(0.025)
+			})
+		}
+	}
+
+	pub fn detect(&self, gray: &core::Mat) -> opencv::Result<Option<BoardDetection>>{
+		match *self {
+			CalibrationBoard::Chessboard{columns, rows, square_size} => {
+				let mut corners = core::Vector::<core::Point2f>::new();
+				let found = calib3d::find_chessboard_corners(
+					gray,
+					core::Size::new(columns, rows),
+					&mut corners,
+					calib3d::CALIB_CB_ADAPTIVE_THRESH + calib3d::CALIB_CB_NORMALIZE_IMAGE + calib3d::CALIB_CB_FAST_CHECK
+				)?;
+				if !found {
+					return Ok(None);
+				}
+				imgproc::corner_sub_pix(
+					gray,
+					&mut corners,
+					core::Size::new(11, 11),
+					core::Size::new(-1, -1),
+					core::TermCriteria::new(core::TermCriteria_COUNT + core::TermCriteria_EPS, 30, 0.001)?
+				)?;
+
+				let mut object_points = core::Vector::<core::Point3f>::new();
+				for row in 0..rows {
+					for column in 0..columns {
+						object_points.push(core::Point3f::new(column as f32 * square_size, row as f32 * square_size, 0.));
+					}
+				}
+				Ok(Some(BoardDetection{object_points: object_points, image_points: corners}))
+			},
+			CalibrationBoard::Charuco{columns, rows, square_size, marker_size} => {
+				let dictionary = aruco::get_predefined_dictionary(aruco::PREDEFINED_DICTIONARY_NAME::DICT_4X4_50)?;
+				let board = aruco::CharucoBoard::create(columns, rows, square_size, marker_size, &dictionary)?;
+
+				let mut marker_corners = core::Vector::<core::Vector<core::Point2f>>::new();
+				let mut marker_ids = core::Vector::<i32>::new();
+				let mut rejected = core::Vector::<core::Vector<core::Point2f>>::new();
+				aruco::detect_markers(
+					gray,
+					&dictionary,
+					&mut marker_corners,
+					&mut marker_ids,
+					&aruco::DetectorParameters::create()?,
+					&mut rejected,
+					&core::Mat::default(),
+					&core::Mat::default()
+				)?;
+				if marker_ids.is_empty() {
+					return Ok(None);
+				}
+
+				let mut corners = core::Vector::<core::Point2f>::new();
+				let mut ids = core::Vector::<i32>::new();
+				aruco::interpolate_corners_charuco(
+					&marker_corners,
+					&marker_ids,
+					gray,
+					&board,
+					&mut corners,
+					&mut ids,
+					&core::Mat::default(),
+					&core::Mat::default(),
+					2
+				)?;
+				// too few corners make calibration unstable
+				if ids.len() < 6 {
+					return Ok(None);
+				}
+
+				// inner corners of the ChArUco board are numbered row by row, starting one square from the origin
+				let mut object_points = core::Vector::<core::Point3f>::new();
+				for id in ids.iter() {
+					let column = id % (columns - 1) + 1;
+					let row = id / (columns - 1) + 1;
+					object_points.push(core::Point3f::new(column as f32 * square_size, row as f32 * square_size, 0.));
+				}
+				Ok(Some(BoardDetection{object_points: object_points, image_points: corners}))
+			}
+		}
+	}
+}
+
+pub struct IntrinsicCalibrationOptions{
+	/// camera node, video file or stream url
+	pub source: String,
+	pub camera_id: Option<String>,
+	pub board: CalibrationBoard,
+	pub model: LensModel,
+	/// number of board views used for calibration
+	pub frames: usize,
+	/// minimum number of frames between two used views, so views differ from each other
+	pub frame_step: usize,
+	pub undistortion: UndistortionMode
+}
+
+impl IntrinsicCalibrationOptions {
+	pub const USAGE: &'static str = "rtrack calibrate-intrinsics <camera node | video file> [--charuco] [--columns N] [--rows N] [--square METRES] [--marker METRES] [--fisheye] [--frames N] [--step N] [--camera-id ID] [--undistort off|frame|points]";
+
+	pub fn from_args(args: &[String]) -> Result<Self, String>{
+		let source = args.first()
+			.filter(|arg| !arg.starts_with("--"))
+			.ok_or_else(|| format!("Missing camera or video file\nUsage: {}", Self::USAGE))?
+			.clone();
+
+		Ok(IntrinsicCalibrationOptions{
+			source: source,
+			camera_id: cli::option_value(args, "--camera-id")?,
+			board: CalibrationBoard::from_args(args)?,
+			model: if cli::has_flag(args, "--fisheye") { LensModel::Fisheye } else { LensModel::Pinhole },
+			frames: cli::option_value(args, "--frames")?.unwrap_or(25),
+			frame_step: cli::option_value(args, "--step")?.unwrap_or(15),
+			undistortion: match cli::option_value::<String>(args, "--undistort")? {
+				Some(mode) => UndistortionMode::from_name(&mode).ok_or_else(|| format!("Unknown undistortion mode '{}'", mode))?,
+				None => UndistortionMode::default()
+			}
+		})
+	}
+}
+
+/// Camera id under which calibration of `source` is saved, bus info for camera nodes so it survives node renumbering.
+pub fn camera_id_of(source: &str) -> String{
+	if source.starts_with("/dev/") {
+		if let Ok(device) = camera::CameraDevice::from_path(source) {
+			return device.bus;
+		}
+	}
+	source.to_owned()
+}
+
+/// Entry point of `rtrack calibrate-intrinsics`.
+pub fn run(args: &[String]) -> std::io::Result<()>{
+	let options = IntrinsicCalibrationOptions::from_args(args).map_err(cli::invalid_input)?;
+	let camera_id = options.camera_id.clone().unwrap_or_else(|| camera_id_of(&options.source));
+
+	let intrinsics = calibrate(&options).map_err(cli::opencv_error)?;
+	println!("RMS reprojection error: {:.4} px", intrinsics.reprojection_error);
+
+	let path = intrinsics.save(&camera_id).map_err(cli::opencv_error)?;
+	println!("Saved intrinsics of {} into {}", camera_id, path.display());
+	Ok(())
+}
+
+/// Collects board views from the source till there is enough of them (or ESC is pressed) and calibrates camera.
+pub fn calibrate(options: &IntrinsicCalibrationOptions) -> opencv::Result<CameraIntrinsics>{
+	let mut capture = videoio::VideoCapture::from_file(&options.source, videoio::CAP_ANY)?;
+	if !capture.is_opened()? {
+		return Err(opencv::Error::new(core::StsError, format!("Unable to open {}", options.source)));
+	}
+
+	let window = "Intrinsic calibration";
+	highgui::named_window(window, highgui::WINDOW_NORMAL)?;
+
+	let mut object_points = core::Vector::<core::Vector<core::Point3f>>::new();
+	let mut image_points = core::Vector::<core::Vector<core::Point2f>>::new();
+	let mut image_size = core::Size::default();
+	let mut frame = core::Mat::default();
+	let mut frames_since_used = options.frame_step;
+
+	while image_points.len() < options.frames {
+		if !capture.read(&mut frame)? || frame.empty() {
+			break;
+		}
+		image_size = frame.size()?;
+		frames_since_used += 1;
+
+		let mut gray = core::Mat::default();
+		imgproc::cvt_color(&frame, &mut gray, imgproc::COLOR_BGR2GRAY, 0)?;
+
+		if let Some(detection) = options.board.detect(&gray)? {
+			for point in detection.image_points.iter() {
+				imgproc::circle(
+					&mut frame,
+					core::Point::new(point.x as i32, point.y as i32),
+					4,
+					core::Scalar::new(0., 255., 0., 0.),
+					2,
+					imgproc::LINE_8,
+					0
+				)?;
+			}
+			if frames_since_used >= options.frame_step {
+				frames_since_used = 0;
+				object_points.push(detection.object_points);
+				image_points.push(detection.image_points);
+			}
+		}
+
+		imgproc::put_text(
+			&mut frame,
+			&format!("{}/{} views, ESC to finish", image_points.len(), options.frames),
+			core::Point::new(20, 40),
+			imgproc::FONT_HERSHEY_SIMPLEX,
+			1.0,
+			core::Scalar::new(255., 255., 255., 255.),
+			2,
+			imgproc::LINE_8,
+			false
+		)?;
+		highgui::imshow(window, &frame)?;
+		if highgui::wait_key(1)? == 27 {
+			break;
+		}
+	}
+	highgui::destroy_window(window)?;
+
+	if image_points.len() < 3 {
+		return Err(opencv::Error::new(core::StsError, format!("Only {} board views were found, at least 3 are needed", image_points.len())));
+	}
+
+	let mut camera_matrix = core::Mat::default();
+	let mut distortion = core::Mat::default();
+	let mut rotations = core::Vector::<core::Mat>::new();
+	let mut translations = core::Vector::<core::Mat>::new();
+	let criteria = core::TermCriteria::new(core::TermCriteria_COUNT + core::TermCriteria_EPS, 30, f64::EPSILON)?;
+	let reprojection_error = match options.model {
+		LensModel::Pinhole => calib3d::calibrate_camera(
+			&object_points,
+			&image_points,
+			image_size,
+			&mut camera_matrix,
+			&mut distortion,
+			&mut rotations,
+			&mut translations,
+			0,
+			criteria
+		)?,
+		LensModel::Fisheye => calib3d::fisheye_calibrate(
+			&object_points,
+			&image_points,
+			image_size,
+			&mut camera_matrix,
+			&mut distortion,
+			&mut rotations,
+			&mut translations,
+			calib3d::fisheye_CALIB_RECOMPUTE_EXTRINSIC + calib3d::fisheye_CALIB_FIX_SKEW,
+			criteria
+		)?
+	};
+
+	Ok(CameraIntrinsics{
+		camera_matrix: camera_matrix,
+		distortion: distortion,
+		image_size: image_size,
+		model: options.model,
+		reprojection_error: reprojection_error,
+		undistortion: options.undistortion
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn assert_near(actual: f64, expected: f64){
+		assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+	}
+
+	#[test]
+	fn principal_point_is_back_projected_onto_optical_axis(){
+		let intrinsics = CameraIntrinsics::ideal(core::Size::new(640, 480), 500.).unwrap();
+		let point = intrinsics.back_project(core::Point2f::new(320., 240.), 2.).unwrap();
+		assert_near(point.x, 0.);
+		assert_near(point.y, 0.);
+		assert_near(point.z, 2.);
+
+		// pixel fx to the right of the centre lies on ray at 45° from the optical axis
+		let point = intrinsics.back_project(core::Point2f::new(820., 240.), 2f64.sqrt()).unwrap();
+		assert_near(point.x, 1.);
+		assert_near(point.y, 0.);
+		assert_near(point.z, 1.);
+	}
+
+	#[test]
+	fn scaling_to_half_size_halves_camera_matrix(){
+		let mut intrinsics = CameraIntrinsics::ideal(core::Size::new(640, 480), 500.).unwrap();
+		*intrinsics.camera_matrix.at_2d_mut::<f64>(1, 1).unwrap() = 400.;
+		let scaled = intrinsics.scaled_to(core::Size::new(320, 240)).unwrap();
+
+		let (fx, fy) = scaled.focal_length().unwrap();
+		let (cx, cy) = scaled.principal_point().unwrap();
+		assert_near(fx, 250.);
+		assert_near(fy, 200.);
+		assert_near(cx, 160.);
+		assert_near(cy, 120.);
+		assert_eq!(scaled.image_size, core::Size::new(320, 240));
+		// original intrinsics are kept
+		assert_near(intrinsics.focal_length().unwrap().0, 500.);
+	}
+}
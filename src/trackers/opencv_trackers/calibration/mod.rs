@@ -0,0 +1,88 @@
+pub mod extrinsics;
+pub mod intrinsics;
+pub mod playspace;
+pub mod undistortion;
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use opencv::{
+	prelude::*,
+	core
+};
+
+pub const CONFIG_DIRECTORY_ENV_VARIABLE: &str = "RTRACK_CONFIG_DIR";
+
+/// Root of all saved calibrations, `config` by default or RTRACK_CONFIG_DIR when set.
+pub fn config_directory() -> PathBuf{
+	std::env::var_os(CONFIG_DIRECTORY_ENV_VARIABLE)
+		.map(PathBuf::from)
+		.unwrap_or_else(|| PathBuf::from("config"))
+}
+
+/// Directory holding calibration files of single camera: `<config directory>/cameras/<camera id>/`.
+pub fn camera_config_directory(camera_id: &str) -> PathBuf{
+	config_directory()
+		.join("cameras")
+		.join(crate::trackers::opencv_trackers::file_name_from_id(camera_id))
+}
+
+/// Opens OpenCV FileStorage (YAML) for writing, creating missing directories on the way.
+pub fn open_for_writing(path: &std::path::Path) -> opencv::Result<core::FileStorage>{
+	if let Some(directory) = path.parent() {
+		std::fs::create_dir_all(directory)
+			.map_err(|error| opencv::Error::new(core::StsError, error.to_string()))?;
+	}
+	core::FileStorage::new(&path.display().to_string(), core::FileStorage_Mode::WRITE as i32, "")
+}
+
+/// Opens OpenCV FileStorage for reading, fails when file doesn't exist.
+pub fn open_for_reading(path: &std::path::Path) -> opencv::Result<core::FileStorage>{
+	let storage = core::FileStorage::new(&path.display().to_string(), core::FileStorage_Mode::READ as i32, "")?;
+	if !storage.is_opened()? {
+		return Err(opencv::Error::new(core::StsError, format!("Unable to open {}", path.display())));
+	}
+	Ok(storage)
+}
+
+/// Calibrations of cameras by camera id, loaded when camera is seen for the first time.
+/// Loading of missing calibrations is retried every `retry_interval`, so cameras calibrated while running are picked up.
+pub struct CalibrationCache<T>{
+	pub retry_interval: Duration,
+	/// time of the last attempt and loaded calibration
+	entries: HashMap<String, (Instant, Option<T>)>
+}
+
+impl<T> Default for CalibrationCache<T> {
+	fn default() -> Self {
+		CalibrationCache{retry_interval: Duration::from_secs(5), entries: HashMap::new()}
+	}
+}
+
+impl<T> CalibrationCache<T> {
+	/// Calls `load` for camera which wasn't tried yet or has no calibration and wasn't tried within retry_interval.
+	pub fn load(&mut self, camera_id: &str, load: impl FnOnce(&str) -> Option<T>){
+		let due = match self.entries.get(camera_id) {
+			Some((_, Some(_))) => false,
+			Some((tried, None)) => tried.elapsed() >= self.retry_interval,
+			None => true
+		};
+		if due {
+			self.entries.insert(camera_id.to_owned(), (Instant::now(), load(camera_id)));
+		}
+	}
+
+	pub fn get(&self, camera_id: &str) -> Option<&T>{
+		self.entries.get(camera_id).and_then(|(_, calibration)| calibration.as_ref())
+	}
+
+	/// Ids of all cameras passed to load(), with or without calibration.
+	pub fn get_camera_ids(&self) -> impl Iterator<Item = &str>{
+		self.entries.keys().map(String::as_str)
+	}
+
+	pub fn has_any(&self) -> bool{
+		self.entries.values().any(|(_, calibration)| calibration.is_some())
+	}
+}
@@ -0,0 +1,271 @@
+use std::path::PathBuf;
+
+use bevy::ecs::prelude as ecs;
+use bevy::input::{Input, keyboard::KeyCode};
+
+use opencv::prelude::*;
+
+use crate::trackers::tracker;
+use crate::trackers::opencv_trackers::calibration;
+use crate::trackers::opencv_trackers::calibration::extrinsics::Pose;
+
+pub const FILE_NAME: &str = "playspace.yml";
+
+/// Number of tracked positions averaged into single floor point.
+const SAMPLES_PER_POINT: usize = 30;
+
+const POINT_NAMES: [&str; 3] = [
+	"origin of the playspace",
+	"point in front of the origin (forward direction)",
+	"any other floor point, away from the line between the first two"
+];
+
+/// Tracking frame (camera axes, y down and z forward) turned to y up and -z forward, used until the playspace is calibrated.
+const CAMERA_AXES: Pose = Pose{rotation: [[1., 0., 0.], [0., -1., 0.], [0., 0., -1.]], translation: [0.; 3]};
+
+struct PlayspaceCalibration{
+	/// ball centres placed on the floor, in the tracking frame
+	points: Vec<[f64; 3]>,
+	samples: Vec<[f64; 3]>,
+	ball_radius: f64,
+	sampling: bool
+}
+
+/// Transforms tracked positions from the tracking frame (reference camera) into the playspace frame:
+///  right-handed, in metres, with origin on the floor, y up and -z forward (same as Bevy).
+///
+/// Calibration is started by `calibration_key` (F10), then ball of `tracker_name` is placed on the floor at three points
+///  and each is confirmed by `sample_key` (Space). Escape cancels the calibration.
+#[derive(ecs::Resource)]
+pub struct Playspace{
+	pub tracker_name: String,
+	pub calibration_key: KeyCode,
+	pub sample_key: KeyCode,
+	/// pose of the tracking frame in the playspace, None until calibrated
+	alignment: Option<Pose>,
+	calibration: std::sync::Mutex<Option<PlayspaceCalibration>>
+}
+
+impl Default for Playspace {
+	fn default() -> Self {
+		Playspace{
+			tracker_name: "red".to_owned(),
+			calibration_key: KeyCode::F10,
+			sample_key: KeyCode::Space,
+			alignment: None,
+			calibration: std::sync::Mutex::new(None)
+		}
+	}
+}
+
+impl Playspace {
+	pub const TRACKER_ENV_VARIABLE: &'static str = "RTRACK_PLAYSPACE_TRACKER";
+
+	pub fn path() -> PathBuf{
+		calibration::config_directory().join(FILE_NAME)
+	}
+
+	/// Playspace with alignment loaded from configuration directory, when it was calibrated before.
+	pub fn from_env() -> Self{
+		let mut playspace = Playspace::default();
+		if let Ok(tracker_name) = std::env::var(Self::TRACKER_ENV_VARIABLE) {
+			playspace.tracker_name = tracker_name;
+		}
+		if let Ok(alignment) = Self::load() {
+			playspace.alignment = Some(alignment);
+		}
+		playspace
+	}
+
+	pub fn load() -> opencv::Result<Pose>{
+		let mut storage = calibration::open_for_reading(&Self::path())?;
+		let pose = Pose::from_matrices(&storage.get("rotation")?.mat()?, &storage.get("translation")?.mat()?)?;
+		storage.release()?;
+		Ok(pose)
+	}
+
+	pub fn save(alignment: &Pose) -> opencv::Result<PathBuf>{
+		let path = Self::path();
+		let mut storage = calibration::open_for_writing(&path)?;
+		storage.write_mat("rotation", &alignment.rotation_matrix()?)?;
+		storage.write_mat("translation", &alignment.translation_matrix()?)?;
+		storage.release()?;
+		Ok(path)
+	}
+
+	pub fn is_calibrated(&self) -> bool{
+		self.alignment.is_some()
+	}
+
+	/// Pose of the tracking frame in the playspace, without calibration only axes of the tracking frame are turned.
+	pub fn get_alignment(&self) -> &Pose{
+		self.alignment.as_ref().unwrap_or(&CAMERA_AXES)
+	}
+
+	pub fn to_playspace(&self, position: &tracker::Position) -> tracker::Position{
+		let point = self.get_alignment().transform([position.x, position.y, position.z]);
+		tracker::Position{x: point[0], y: point[1], z: point[2]}
+	}
+
+	/// Feeds position of tracker `tracker_name` in the tracking frame, used only while calibrating.
+	pub fn record(&self, tracker_name: &str, position: &tracker::Position, ball_radius: f64){
+		if tracker_name != self.tracker_name {
+			return;
+		}
+		let mut calibration = self.calibration.lock().unwrap();
+		if let Some(calibration) = calibration.as_mut() {
+			if calibration.sampling && calibration.samples.len() < SAMPLES_PER_POINT {
+				calibration.samples.push([position.x, position.y, position.z]);
+				calibration.ball_radius = ball_radius;
+			}
+		}
+	}
+
+	pub fn input_system(keys: Option<ecs::Res<Input<KeyCode>>>, mut playspace: ecs::ResMut<Playspace>){
+		let keys = match keys {
+			Some(keys) => keys,
+			None => return
+		};
+		if keys.just_pressed(playspace.calibration_key) {
+			println!("Playspace calibration: place the {} ball at the {} and press {:?}", playspace.tracker_name, POINT_NAMES[0], playspace.sample_key);
+			*playspace.calibration.get_mut().unwrap() = Some(PlayspaceCalibration{points: vec![], samples: vec![], ball_radius: 0., sampling: false});
+			return;
+		}
+
+		let sample_key = playspace.sample_key;
+		let calibration = playspace.calibration.get_mut().unwrap();
+		let active = match calibration.as_mut() {
+			Some(active) => active,
+			None => return
+		};
+		if keys.just_pressed(KeyCode::Escape) {
+			println!("Playspace calibration cancelled");
+			*calibration = None;
+			return;
+		}
+		if keys.just_pressed(sample_key) && !active.sampling {
+			active.samples.clear();
+			active.sampling = true;
+		}
+		if !active.sampling || active.samples.len() < SAMPLES_PER_POINT {
+			return;
+		}
+
+		// enough samples of current point
+		let count = active.samples.len() as f64;
+		let point = active.samples.iter().fold([0.; 3], |sum, sample| [sum[0] + sample[0] / count, sum[1] + sample[1] / count, sum[2] + sample[2] / count]);
+		active.points.push(point);
+		active.sampling = false;
+		println!("Playspace point {}: [{:.3}, {:.3}, {:.3}]", active.points.len(), point[0], point[1], point[2]);
+
+		if active.points.len() < POINT_NAMES.len() {
+			println!("Place the ball at the {} and press {:?}", POINT_NAMES[active.points.len()], sample_key);
+			return;
+		}
+
+		let alignment = from_floor_points(&active.points[0], &active.points[1], &active.points[2], active.ball_radius);
+		*calibration = None;
+		match alignment {
+			Some(alignment) => {
+				match Self::save(&alignment) {
+					Ok(path) => println!("Playspace saved into {}", path.display()),
+					Err(error) => println!("Unable to save playspace: {}", error)
+				}
+				playspace.alignment = Some(alignment);
+			},
+			None => println!("Playspace calibration failed, points must not lie on a single line")
+		}
+	}
+}
+
+/// Playspace alignment from three ball centres on the floor: `origin`, `forward` point in front of it and `other` floor point.
+/// Floor is `ball_radius` below the centres, on the opposite side than the tracking frame origin (camera).
+pub fn from_floor_points(origin: &[f64; 3], forward: &[f64; 3], other: &[f64; 3], ball_radius: f64) -> Option<Pose>{
+	let to_forward = subtract(forward, origin);
+	let to_other = subtract(other, origin);
+
+	let mut up = normalize(cross(&to_forward, &to_other))?;
+	// camera is above the floor
+	if dot(&up, &subtract(&[0., 0., 0.], origin)) < 0. {
+		up = [-up[0], -up[1], -up[2]];
+	}
+	let along_up = dot(&to_forward, &up);
+	let forward_direction = normalize([to_forward[0] - along_up * up[0], to_forward[1] - along_up * up[1], to_forward[2] - along_up * up[2]])?;
+	let back = [-forward_direction[0], -forward_direction[1], -forward_direction[2]];
+	let right = cross(&up, &back);
+
+	let floor_origin = [origin[0] - up[0] * ball_radius, origin[1] - up[1] * ball_radius, origin[2] - up[2] * ball_radius];
+	let mut alignment = Pose{rotation: [right, up, back], translation: [0.; 3]};
+	let moved = alignment.transform(floor_origin);
+	alignment.translation = [-moved[0], -moved[1], -moved[2]];
+	Some(alignment)
+}
+
+fn subtract(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3]{
+	[a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: &[f64; 3], b: &[f64; 3]) -> f64{
+	a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3]{
+	[a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+fn normalize(vector: [f64; 3]) -> Option<[f64; 3]>{
+	let length = dot(&vector, &vector).sqrt();
+	if length < 1e-9 {
+		return None;
+	}
+	Some([vector[0] / length, vector[1] / length, vector[2] / length])
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn assert_near(actual: [f64; 3], expected: [f64; 3]){
+		for axis in 0..3 {
+			assert!((actual[axis] - expected[axis]).abs() < 1e-9, "{:?} != {:?}", actual, expected);
+		}
+	}
+
+	#[test]
+	fn floor_points_define_origin_up_and_forward(){
+		// camera 1.5 m above the floor looking along it (y down, z forward), ball of 5 cm radius
+		let origin = [0., 1.45, 3.];
+		let forward = [0., 1.45, 4.];
+		let other = [1., 1.45, 3.];
+		let alignment = from_floor_points(&origin, &forward, &other, 0.05).unwrap();
+
+		assert_near(alignment.transform(origin), [0., 0.05, 0.]);
+		assert_near(alignment.transform(forward), [0., 0.05, -1.]);
+		assert_near(alignment.transform(other), [1., 0.05, 0.]);
+		assert_near(alignment.transform([0., 0., 0.]), [0., 1.5, 3.]);
+	}
+
+	#[test]
+	fn alignment_is_rotation_without_reflection(){
+		let alignment = from_floor_points(&[0.2, 1.4, 2.5], &[0.5, 1.3, 3.5], &[-0.7, 1.45, 2.8], 0.04).unwrap();
+		let [a, b, c] = alignment.rotation;
+		let determinant = dot(&a, &cross(&b, &c));
+		assert!((determinant - 1.).abs() < 1e-9);
+		// floor points stay ball radius above the floor
+		for point in [[0.2, 1.4, 2.5], [0.5, 1.3, 3.5], [-0.7, 1.45, 2.8]] {
+			assert!((alignment.transform(point)[1] - 0.04).abs() < 1e-9);
+		}
+	}
+
+	#[test]
+	fn collinear_points_fail(){
+		assert!(from_floor_points(&[0., 1., 2.], &[0., 1., 3.], &[0., 1., 5.], 0.05).is_none());
+	}
+
+	#[test]
+	fn uncalibrated_playspace_turns_camera_axes(){
+		let playspace = Playspace::default();
+		let position = playspace.to_playspace(&tracker::Position{x: 1., y: 2., z: 3.});
+		assert_near([position.x, position.y, position.z], [1., -2., -3.]);
+	}
+}
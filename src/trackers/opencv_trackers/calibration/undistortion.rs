@@ -0,0 +1,128 @@
+use bevy::ecs::prelude as ecs;
+
+use opencv::{
+	prelude::*,
+	core,
+	calib3d,
+	imgproc
+};
+
+use crate::trackers::opencv_trackers::calibration::intrinsics::{CameraIntrinsics, LensModel};
+
+/// How lens distortion of the camera is removed before blob positions are used.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UndistortionMode{
+	Off,
+	/// whole frames are remapped, precise but costs a remap per frame
+	Frame,
+	/// only detected positions are undistorted
+	#[default]
+	Points
+}
+
+impl UndistortionMode {
+	pub fn name(&self) -> &'static str{
+		match self {
+			UndistortionMode::Off => "off",
+			UndistortionMode::Frame => "frame",
+			UndistortionMode::Points => "points",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<Self>{
+		match name {
+			"off" => Some(UndistortionMode::Off),
+			"frame" => Some(UndistortionMode::Frame),
+			"points" => Some(UndistortionMode::Points),
+			_ => None
+		}
+	}
+}
+
+/// Removes lens distortion using camera intrinsics, for both pinhole and fisheye models.
+/// Undistorted coordinates stay in pixels of the same camera matrix.
+pub struct Undistortion{
+	pub mode: UndistortionMode,
+	intrinsics: CameraIntrinsics,
+	// remap maps for the last frame size
+	maps: Option<(core::Size, core::Mat, core::Mat)>
+}
+
+impl Undistortion {
+	pub fn new(intrinsics: CameraIntrinsics) -> Self{
+		Undistortion{mode: intrinsics.undistortion, intrinsics: intrinsics, maps: None}
+	}
+
+	pub fn get_intrinsics(&self) -> &CameraIntrinsics{
+		&self.intrinsics
+	}
+
+	/// Remaps whole frame, when mode isn't Frame it's copied as is.
+	pub fn undistort_frame(&mut self, dest: &mut core::Mat, src: &core::Mat) -> opencv::Result<()>{
+		if self.mode != UndistortionMode::Frame || src.empty() {
+			dest.clone_from(src);
+			return Ok(());
+		}
+
+		let size = src.size()?;
+		if self.maps.as_ref().map_or(true, |(maps_size, _, _)| *maps_size != size) {
+			self.maps = Some(Self::make_maps(&self.intrinsics.scaled_to(size)?)?);
+		}
+		let (_, map_x, map_y) = self.maps.as_ref().unwrap();
+		imgproc::remap(src, dest, map_x, map_y, imgproc::INTER_LINEAR, core::BORDER_CONSTANT, core::Scalar::default())
+	}
+
+	/// Undistorts single position in frame of `frame_size`, only in Points mode.
+	pub fn undistort_point(&self, point: core::Point2f, frame_size: core::Size) -> opencv::Result<core::Point2f>{
+		if self.mode != UndistortionMode::Points {
+			return Ok(point);
+		}
+		let intrinsics = self.intrinsics.scaled_to(frame_size)?;
+		let undistorted = intrinsics.undistort_pixels(&core::Vector::<core::Point2f>::from_iter([point]))?;
+		undistorted.get(0)
+	}
+
+	fn make_maps(intrinsics: &CameraIntrinsics) -> opencv::Result<(core::Size, core::Mat, core::Mat)>{
+		let mut map_x = core::Mat::default();
+		let mut map_y = core::Mat::default();
+		match intrinsics.model {
+			LensModel::Pinhole => calib3d::init_undistort_rectify_map(
+				&intrinsics.camera_matrix,
+				&intrinsics.distortion,
+				&core::Mat::default(),
+				&intrinsics.camera_matrix,
+				intrinsics.image_size,
+				core::CV_32FC1,
+				&mut map_x,
+				&mut map_y
+			)?,
+			LensModel::Fisheye => calib3d::fisheye_init_undistort_rectify_map(
+				&intrinsics.camera_matrix,
+				&intrinsics.distortion,
+				&core::Mat::default(),
+				&intrinsics.camera_matrix,
+				intrinsics.image_size,
+				core::CV_32FC1,
+				&mut map_x,
+				&mut map_y
+			)?
+		}
+		Ok((intrinsics.image_size, map_x, map_y))
+	}
+}
+
+/// Undistortion of the camera, which frames are processed by entity with this component.
+#[derive(ecs::Component)]
+pub struct UndistortionComponent{
+	undistortion: std::sync::Mutex<Undistortion>
+}
+
+impl UndistortionComponent {
+	pub fn new(undistortion: Undistortion) -> Self{
+		UndistortionComponent{undistortion: std::sync::Mutex::new(undistortion)}
+	}
+
+	pub fn get_undistortion(&self) -> &std::sync::Mutex<Undistortion>{
+		&self.undistortion
+	}
+}
@@ -1,69 +1,349 @@
 use linuxvideo::Device;
+use linuxvideo::format::{FrameIntervals, FrameSizes, Pixelformat};
+use linuxvideo::shared::{BufType, CapabilityFlags};
 use std::collections::hash_map;
 use std::cmp::Ordering;
 
-fn list_device(device: Device) -> std::io::Result<()> {
-    let caps = device.capabilities()?;
-	
-    println!("- {}: {}", device.path()?.display(), caps.card());
-    println!("  driver: {}", caps.driver());
-    println!("  bus info: {}", caps.bus_info());
-    println!("  all capabilities:    {:?}", caps.all_capabilities());
-    println!("  avail. capabilities: {:?}", caps.device_capabilities());
+use bevy::ecs::prelude::Resource;
+use serde::Serialize;
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FrameIntervalInfo{
+	Discrete{numerator: u32, denominator: u32},
+	Stepwise{min: String, max: String, step: String}
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FrameSizeInfo{
+	Discrete{width: u32, height: u32, intervals: Vec<FrameIntervalInfo>},
+	Stepwise{min_width: u32, max_width: u32, step_width: u32, min_height: u32, max_height: u32, step_height: u32}
+}
+
+#[derive(Serialize, Debug)]
+pub struct PixelFormatInfo{
+	pub fourcc: String,
+	pub description: String,
+	pub frame_sizes: Vec<FrameSizeInfo>
+}
+
+/// Everything camera node reports about itself, used by `rtrack cameras`.
+#[derive(Serialize, Debug)]
+pub struct CameraCapabilities{
+	pub name: String,
+	pub driver: String,
+	pub bus: String,
+	pub path: String,
+	pub capabilities: Vec<String>,
+	pub formats: Vec<PixelFormatInfo>
+}
+
+impl CameraCapabilities {
+	pub fn probe(device: &Device) -> std::io::Result<Self>{
+		let caps = device.capabilities()?;
+		let mut formats = vec![];
+		for format in device.formats(BufType::VIDEO_CAPTURE) {
+			let format = format?;
+			formats.push(PixelFormatInfo{
+				fourcc: format.pixel_format().to_string(),
+				description: format.description().to_string(),
+				frame_sizes: Self::probe_frame_sizes(device, format.pixel_format())?
+			});
+		}
+
+		Ok(CameraCapabilities{
+			name: caps.card().to_string(),
+			driver: caps.driver().to_string(),
+			bus: caps.bus_info().to_string(),
+			path: device.path()?.display().to_string(),
+			// flags are printed as "VIDEO_CAPTURE | STREAMING | ..."
+			capabilities: format!("{:?}", caps.device_capabilities()).split('|').map(|flag| flag.trim().to_owned()).collect(),
+			formats: formats
+		})
+	}
+
+	fn probe_frame_sizes(device: &Device, pixel_format: Pixelformat) -> std::io::Result<Vec<FrameSizeInfo>>{
+		let mut frame_sizes = vec![];
+		match device.frame_sizes(pixel_format)? {
+			FrameSizes::Discrete(sizes) => {
+				for size in sizes {
+					frame_sizes.push(FrameSizeInfo::Discrete{
+						width: size.width(),
+						height: size.height(),
+						intervals: Self::probe_frame_intervals(device, pixel_format, size.width(), size.height())?
+					});
+				}
+			},
+			FrameSizes::Stepwise(sizes) | FrameSizes::Continuous(sizes) => {
+				frame_sizes.push(FrameSizeInfo::Stepwise{
+					min_width: sizes.min_width(),
+					max_width: sizes.max_width(),
+					step_width: sizes.step_width(),
+					min_height: sizes.min_height(),
+					max_height: sizes.max_height(),
+					step_height: sizes.step_height()
+				});
+			}
+		}
+		Ok(frame_sizes)
+	}
+
+	fn probe_frame_intervals(device: &Device, pixel_format: Pixelformat, width: u32, height: u32) -> std::io::Result<Vec<FrameIntervalInfo>>{
+		let mut frame_intervals = vec![];
+		match device.frame_intervals(pixel_format, width, height)? {
+			FrameIntervals::Discrete(intervals) => {
+				for interval in intervals {
+					frame_intervals.push(FrameIntervalInfo::Discrete{numerator: interval.numerator(), denominator: interval.denominator()});
+				}
+			},
+			FrameIntervals::Stepwise(intervals) | FrameIntervals::Continuous(intervals) => {
+				frame_intervals.push(FrameIntervalInfo::Stepwise{
+					min: intervals.min().to_string(),
+					max: intervals.max().to_string(),
+					step: intervals.step().to_string()
+				});
+			}
+		}
+		Ok(frame_intervals)
+	}
+
+	pub fn print(&self){
+		println!("- {}: {}", self.path, self.name);
+		println!("  driver: {}", self.driver);
+		println!("  bus info: {}", self.bus);
+		println!("  capabilities: {}", self.capabilities.join(", "));
+		for format in &self.formats {
+			println!("  format {} ({}):", format.fourcc, format.description);
+			for frame_size in &format.frame_sizes {
+				match frame_size {
+					FrameSizeInfo::Discrete{width, height, intervals} => {
+						let fps: Vec<String> = intervals.iter().map(|interval| match interval {
+							FrameIntervalInfo::Discrete{numerator, denominator} => format!("{}/{}", denominator, numerator),
+							FrameIntervalInfo::Stepwise{min, max, ..} => format!("{}..{} s", min, max)
+						}).collect();
+						println!("    {}x{} fps: {}", width, height, fps.join(", "));
+					},
+					FrameSizeInfo::Stepwise{min_width, max_width, min_height, max_height, ..} => {
+						println!("    {}x{} - {}x{}", min_width, min_height, max_width, max_height);
+					}
+				}
+			}
+		}
+	}
+}
+
+/// Prints capabilities of every unique camera, as JSON array when `json` is true.
+/// Camera which is busy or can't be probed is reported on stderr and left out, so the rest is still listed.
+pub fn print_cameras(json: bool) -> std::io::Result<()>{
+	let mut cameras = vec![];
+	for (_, camera) in CameraDevice::list_unique_devices(&CameraFilter::default())? {
+		let capabilities = Device::open(std::path::Path::new(&camera.path))
+			.and_then(|device| CameraCapabilities::probe(&device));
+		match capabilities {
+			Ok(capabilities) => cameras.push(capabilities),
+			Err(error) => eprintln!("Unable to probe {}: {}", camera.path, error)
+		}
+	}
+	cameras.sort_by(|a, b| a.path.cmp(&b.path));
+
+	if json {
+		let output = serde_json::to_string_pretty(&cameras)
+			.map_err(|error| std::io::Error::new(std::io::ErrorKind::Other, error))?;
+		println!("{}", output);
+	}
+	else {
+		for camera in &cameras {
+			camera.print();
+		}
+	}
+	Ok(())
+}
+
+
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CameraFilterField{
+	Name,
+	Driver,
+	Bus,
+	Path
+}
+
+/// Single glob rule (supports `*` and `?`) matched against one field of a camera node.
+#[derive(Clone, Debug)]
+pub struct CameraFilterRule{
+	pub field: CameraFilterField,
+	pub pattern: String
+}
 
-    Ok(())
+impl CameraFilterRule {
+	pub fn new(field: CameraFilterField, pattern: &str) -> Self{
+		CameraFilterRule{field: field, pattern: pattern.to_owned()}
+	}
+
+	pub fn matches(&self, device: &CameraDevice) -> bool{
+		let value = match self.field {
+			CameraFilterField::Name => &device.name,
+			CameraFilterField::Driver => &device.driver,
+			CameraFilterField::Bus => &device.bus,
+			CameraFilterField::Path => &device.path,
+		};
+		glob_match(&self.pattern, value)
+	}
 }
 
+/// Decides which camera nodes are used by the tracking stack.
+/// Node is accepted when there are no allow rules or at least one of them matches,
+///  and none of the deny rules matches.
+/// 
+/// # Examples
+/// ```
+/// // only Logitech cameras, but never /dev/video4
+/// let filter = CameraFilter::parse("allow:name=*Logitech*;deny:path=/dev/video4").unwrap();
+/// ```
+#[derive(Resource, Default, Clone, Debug)]
+pub struct CameraFilter{
+	pub allow: Vec<CameraFilterRule>,
+	pub deny: Vec<CameraFilterRule>
+}
+
+impl CameraFilter {
+	pub const ENV_VARIABLE: &'static str = "RTRACK_CAMERA_FILTER";
+
+	/// Parses rules separated by `;` in form of `allow:<field>=<glob>` or `deny:<field>=<glob>`,
+	///  where field is one of `name`, `driver`, `bus` or `path`.
+	pub fn parse(rules: &str) -> Result<Self, String>{
+		let mut filter = CameraFilter::default();
+		for rule in rules.split(';').map(str::trim).filter(|rule| !rule.is_empty()) {
+			let (kind, condition) = rule.split_once(':')
+				.ok_or_else(|| format!("Missing 'allow:' or 'deny:' in camera filter rule '{rule}'"))?;
+			let (field, pattern) = condition.split_once('=')
+				.ok_or_else(|| format!("Missing '=' in camera filter rule '{rule}'"))?;
+			let field = match field.trim() {
+				"name" => CameraFilterField::Name,
+				"driver" => CameraFilterField::Driver,
+				"bus" => CameraFilterField::Bus,
+				"path" => CameraFilterField::Path,
+				unknown => return Err(format!("Unknown camera filter field '{unknown}'"))
+			};
+			let filter_rule = CameraFilterRule::new(field, pattern.trim());
+			match kind.trim() {
+				"allow" => filter.allow.push(filter_rule),
+				"deny" => filter.deny.push(filter_rule),
+				unknown => return Err(format!("Unknown camera filter kind '{unknown}'"))
+			}
+		}
+		Ok(filter)
+	}
 
+	/// Reads filter from RTRACK_CAMERA_FILTER environment variable, when not set or invalid everything is allowed.
+	pub fn from_env() -> Self{
+		match std::env::var(Self::ENV_VARIABLE) {
+			Ok(rules) => match Self::parse(&rules) {
+				Ok(filter) => filter,
+				Err(error) => {
+					println!("Ignoring {}: {}", Self::ENV_VARIABLE, error);
+					CameraFilter::default()
+				}
+			},
+			Err(_) => CameraFilter::default()
+		}
+	}
+
+	pub fn is_allowed(&self, device: &CameraDevice) -> bool{
+		let allowed = self.allow.is_empty() || self.allow.iter().any(|rule| rule.matches(device));
+		allowed && !self.deny.iter().any(|rule| rule.matches(device))
+	}
+}
+
+fn glob_match(pattern: &str, text: &str) -> bool{
+	let pattern: Vec<char> = pattern.chars().collect();
+	let text: Vec<char> = text.chars().collect();
+	let (mut p, mut t) = (0, 0);
+	// position of last '*' in pattern and position in text it started matching from
+	let mut backtrack: Option<(usize, usize)> = None;
+
+	while t < text.len() {
+		if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+			p += 1;
+			t += 1;
+		}
+		else if p < pattern.len() && pattern[p] == '*' {
+			backtrack = Some((p, t));
+			p += 1;
+		}
+		else if let Some((star, matched)) = backtrack {
+			p = star + 1;
+			t = matched + 1;
+			backtrack = Some((star, matched + 1));
+		}
+		else {
+			return false;
+		}
+	}
+	pattern[p..].iter().all(|c| *c == '*')
+}
 
 
+#[derive(Clone, Debug)]
 pub struct CameraDevice{
 	pub name: String,
+	pub driver: String,
+	pub bus: String,
 	pub path: String
 }
 type UniqueCameraDeviceList = hash_map::HashMap<String, CameraDevice>;
 
 impl CameraDevice {
 
+	/// Describes camera node at `path` (e.g. /dev/video0).
+	pub fn from_path(path: &str) -> std::io::Result<Self>{
+		let device = Device::open(std::path::Path::new(path))?;
+		let caps = device.capabilities()?;
+		Ok(CameraDevice{
+			name: caps.card().to_string(),
+			driver: caps.driver().to_string(),
+			bus: caps.bus_info().to_string(),
+			path: path.to_owned()
+		})
+	}
 
 //# static
-	fn assign_device(device_list: &mut UniqueCameraDeviceList, device: Device) -> std::io::Result<()> {
+	fn assign_device(device_list: &mut UniqueCameraDeviceList, device: Device, filter: &CameraFilter) -> std::io::Result<()> {
 		let caps = device.capabilities()?;
-		let path = device.path()?.display().to_string();
-		let bus = caps.bus_info().to_string();
-		let name = caps.card().to_string();
-		if let Some(device) = device_list.get_mut(&bus){
-			if device.path.cmp(&path) == Ordering::Greater{
-				device.path = path;
+		// metadata nodes share bus with the capture node, but can't provide any frames
+		if !caps.device_capabilities().contains(CapabilityFlags::VIDEO_CAPTURE) {
+			return Ok(());
+		}
+
+		let camera = CameraDevice{
+			name: caps.card().to_string(),
+			driver: caps.driver().to_string(),
+			bus: caps.bus_info().to_string(),
+			path: device.path()?.display().to_string()
+		};
+		if !filter.is_allowed(&camera) {
+			return Ok(());
+		}
+
+		if let Some(device) = device_list.get_mut(&camera.bus){
+			if device.path.cmp(&camera.path) == Ordering::Greater{
+				device.path = camera.path;
 			}
 		}
 		else {
-			device_list.insert(
-				bus,
-				CameraDevice{
-					name: name,
-					path: path
-				}
-			);
+			device_list.insert(camera.bus.clone(), camera);
 		}
 		Ok(())
-		
-		/*device.path()?.display()
-		
-		println!("- {}: {}", device.path()?.display(), caps.card());
-		println!("  driver: {}", caps.driver());
-		println!("  bus info: {}", caps.bus_info());
-		println!("  all capabilities:    {:?}", caps.all_capabilities());
-		println!("  avail. capabilities: {:?}", caps.device_capabilities());
-
-		Ok(())*/
 	}
 	
-	pub fn list_unique_devices() -> std::io::Result<UniqueCameraDeviceList>{
+	pub fn list_unique_devices(filter: &CameraFilter) -> std::io::Result<UniqueCameraDeviceList>{
 		let mut device_list = UniqueCameraDeviceList::default();
 		for res in linuxvideo::list()? {
 			if let Ok(device) = res {
-				Self::assign_device(&mut device_list, device)?;
+				Self::assign_device(&mut device_list, device, filter)?;
 			}
 		}
 
@@ -73,3 +353,51 @@ impl CameraDevice {
 	}
 }
 
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn device(name: &str, path: &str) -> CameraDevice{
+		CameraDevice{name: name.to_owned(), driver: "uvcvideo".to_owned(), bus: "usb-0000:00:14.0-1".to_owned(), path: path.to_owned()}
+	}
+
+	#[test]
+	fn glob_matches_wildcards(){
+		assert!(glob_match("*Logitech*", "HD Pro Webcam Logitech C920"));
+		assert!(glob_match("/dev/video?", "/dev/video4"));
+		assert!(glob_match("*", ""));
+		assert!(glob_match("a*b*c", "axxbyyc"));
+		assert!(glob_match("a*bc", "abcbc"));
+		assert!(!glob_match("/dev/video?", "/dev/video10"));
+		assert!(!glob_match("*Logitech", "Logitech C920"));
+		assert!(!glob_match("", "x"));
+	}
+
+	#[test]
+	fn parses_allow_and_deny_rules(){
+		let filter = CameraFilter::parse(" allow:name=*Logitech* ; deny:path=/dev/video4;").unwrap();
+		assert_eq!(filter.allow.len(), 1);
+		assert_eq!(filter.allow[0].field, CameraFilterField::Name);
+		assert_eq!(filter.allow[0].pattern, "*Logitech*");
+		assert_eq!(filter.deny.len(), 1);
+		assert_eq!(filter.deny[0].field, CameraFilterField::Path);
+
+		assert!(filter.is_allowed(&device("Logitech C920", "/dev/video0")));
+		assert!(!filter.is_allowed(&device("Logitech C920", "/dev/video4")));
+		assert!(!filter.is_allowed(&device("Integrated Camera", "/dev/video2")));
+	}
+
+	#[test]
+	fn empty_filter_allows_everything(){
+		let filter = CameraFilter::parse("").unwrap();
+		assert!(filter.is_allowed(&device("Integrated Camera", "/dev/video0")));
+	}
+
+	#[test]
+	fn rejects_malformed_rules(){
+		assert!(CameraFilter::parse("name=*").is_err());
+		assert!(CameraFilter::parse("allow:name").is_err());
+		assert!(CameraFilter::parse("allow:serial=*").is_err());
+		assert!(CameraFilter::parse("maybe:name=*").is_err());
+	}
+}
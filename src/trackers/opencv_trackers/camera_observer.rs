@@ -7,6 +7,8 @@ use bevy::ecs::world;
 use crate::entity_spawner::EntitySpawner;
 use crate::state;
 use crate::trackers::opencv_trackers::camera;
+use crate::trackers::opencv_trackers::camera_recorder;
+use crate::trackers::opencv_trackers::network_camera;
 
 
 use opencv::{
@@ -16,7 +18,8 @@ use opencv::{
 
 use crate::trackers::opencv_trackers::opencv_utilities::{
 	window_preview,
-	frame_component
+	frame_component,
+	processing_pipeline
 };
 
 
@@ -24,22 +27,89 @@ use crate::trackers::opencv_trackers::opencv_utilities::{
 #[derive(Component)]
 pub struct CameraObserverSubscriberComponent;
 
+/// Id of the camera (see CameraObserver::get_id()) which frames are received by entity.
+#[derive(Component, Clone, Debug)]
+pub struct ObservedCamera{
+	pub camera_id: String
+}
+
 #[derive(Resource)]
 pub struct CameraObservers{
-	pub list: Vec<CameraObserver>
+	pub list: Vec<CameraObserver>,
+	pub reconnect: ReconnectPolicy
+}
+
+/// Decides how failed cameras are reopened, before they are removed together with their subscribers.
+#[derive(Clone, Debug)]
+pub struct ReconnectPolicy{
+	pub max_retries: u32,
+	pub initial_backoff: std::time::Duration,
+	pub max_backoff: std::time::Duration,
+	/// camera which doesn't provide any frame for this long is treated as failed, replays never stall
+	pub stall_timeout: std::time::Duration
+}
+
+impl Default for ReconnectPolicy {
+	fn default() -> Self {
+		ReconnectPolicy{
+			max_retries: 5,
+			initial_backoff: std::time::Duration::from_millis(250),
+			max_backoff: std::time::Duration::from_secs(8),
+			stall_timeout: std::time::Duration::from_secs(5)
+		}
+	}
+}
+
+impl ReconnectPolicy {
+	pub const RETRIES_ENV_VARIABLE: &'static str = "RTRACK_CAMERA_RETRIES";
+
+	pub fn from_env() -> Self{
+		let mut policy = ReconnectPolicy::default();
+		if let Some(retries) = std::env::var(Self::RETRIES_ENV_VARIABLE).ok().and_then(|value| value.parse::<u32>().ok()) {
+			policy.max_retries = retries;
+		}
+		policy
+	}
+
+	/// Exponential backoff for given retry, starting at initial_backoff and capped by max_backoff.
+	pub fn backoff(&self, retry: u32) -> std::time::Duration{
+		let backoff = self.initial_backoff.saturating_mul(2u32.saturating_pow(retry));
+		backoff.min(self.max_backoff)
+	}
 }
 
 
 
 impl CameraObservers{
-	pub fn update_system( camera_observers: Option<ResMut<CameraObservers>>, mut query: Query<&mut frame_component::FrameComponent, With<CameraObserverSubscriberComponent>>){
-		if camera_observers.is_some(){
-			
+	pub const REPLAY_ENV_VARIABLE: &'static str = "RTRACK_REPLAY";
+	pub const NETWORK_ENV_VARIABLE: &'static str = "RTRACK_NETWORK_CAMERAS";
+
+	pub fn update_system(
+		camera_observers: Option<ResMut<CameraObservers>>,
+		mut camera_recorder: Option<ResMut<camera_recorder::CameraRecorder>>,
+		mut query: Query<&mut frame_component::FrameComponent, With<CameraObserverSubscriberComponent>>
+	){
+		if let Some(mut camera_observers) = camera_observers{
+			let camera_observers = camera_observers.as_mut();
 			//query.get(entity)
 
-			for camera_observer in  &mut camera_observers.unwrap().as_mut().list {
+			// all cameras grab their frames first, so timestamps aren't delayed by decoding frames of other cameras
+			let reconnect = &camera_observers.reconnect;
+			let grabbed: Vec<bool> = camera_observers.list.iter_mut()
+				.map(|camera_observer| camera_observer.grab(reconnect))
+				.collect();
+
+			for (camera_observer, grabbed) in camera_observers.list.iter_mut().zip(grabbed) {
 				let mut new_frame = Mat::default();
-				camera_observer.update(&mut new_frame);
+				if !grabbed || !camera_observer.retrieve(&mut new_frame){
+					continue;
+				}
+
+				if let (Some(recorder), Some(timestamp)) = (camera_recorder.as_mut(), camera_observer.frame_timestamp) {
+					if let Err(error) = recorder.record(camera_observer.get_id(), &new_frame, timestamp){
+						println!("While recording {}: {}", camera_observer.get_id(), error);
+					}
+				}
 
 				for entity in &camera_observer.subscribed_entities {
 					let frame_component_result = query.get_mut(*entity);
@@ -47,6 +117,7 @@ impl CameraObservers{
 						if let Ok(_) = frame_component.apply(&new_frame){
 							// get rid of warning(maybe handle this error)					
 						}
+						frame_component.set_timestamp(camera_observer.frame_timestamp);
 					} 
 				}
 				
@@ -57,21 +128,30 @@ impl CameraObservers{
 		
 	}
 
-	pub fn assignment_system(mut commands:  Commands, camera_observers: Option<ResMut<CameraObservers>>){
+	pub fn assignment_system(mut commands:  Commands, camera_observers: Option<ResMut<CameraObservers>>, camera_filter: Option<Res<camera::CameraFilter>>){
 		if let Some(mut observers) =  camera_observers {
-			let mut  camera_list = camera::CameraDevice::list_unique_devices().unwrap();
+			let default_filter = camera::CameraFilter::default();
+			let camera_filter = camera_filter.as_deref().unwrap_or(&default_filter);
+			let mut  camera_list = camera::CameraDevice::list_unique_devices(camera_filter).unwrap();
 			for camera_observer in &mut observers.list{
-				if camera_list.contains_key(&camera_observer.bus){
-					camera_list.remove(&camera_observer.bus);
+				if camera_observer.source != CameraSource::Device {
+					// replays and network cameras are not tied to any device
+					continue;
+				}
+				if let Some(device) = camera_list.remove(&camera_observer.bus){
+					// node might change after device was reconnected
+					camera_observer.path = device.path;
 				}
 				else if !camera_observer.state.is_done() {
 					let old_state= std::mem::replace(&mut camera_observer.state, state::State::None);
+					// disconnected camera is stopped with error, so it gets a chance to reconnect
+					let disconnected = || Err(opencv::Error::new(opencv::core::StsError, "Camera disconnected".to_owned()));
 					match old_state {
-						state::State::Start(result) => {
-							camera_observer.state = state::State::Stop(result);
+						state::State::Start(_) => {
+							camera_observer.state = state::State::Stop(disconnected());
 						},
-						state::State::Run(result) => {
-							camera_observer.state = state::State::Stop(result);
+						state::State::Run(_) => {
+							camera_observer.state = state::State::Stop(disconnected());
 						},
 						state::State::Stop(result) => {
 							camera_observer.state = state::State::Stop(result);
@@ -120,27 +200,101 @@ impl CameraObservers{
 }
 
 impl world::FromWorld for CameraObservers{
-	fn from_world(_world: &mut World) -> Self {
-		
-		CameraObservers{list: vec![]}
+	fn from_world(world: &mut World) -> Self {
+		let mut list = vec![];
+		// recordings passed in RTRACK_REPLAY (separated same way as PATH) are observed like cameras,
+		//  they share one clock so cameras recorded together are replayed with their recorded skew
+		if let Some(replays) = std::env::var_os(Self::REPLAY_ENV_VARIABLE) {
+			let replay_clock = std::sync::Arc::new(camera_recorder::ReplayClock::default());
+			for replay_path in std::env::split_paths(&replays) {
+				let mut observer = CameraObserver::new_with_source(CameraSource::Replay, &replay_path.display().to_string());
+				observer.replay_clock = Some(replay_clock.clone());
+				list.push(observer);
+			}
+		}
+		// MJPEG stream urls passed in RTRACK_NETWORK_CAMERAS separated by ','
+		if let Ok(urls) = std::env::var(Self::NETWORK_ENV_VARIABLE) {
+			for url in urls.split(',').map(str::trim).filter(|url| !url.is_empty()) {
+				list.push(CameraObserver::new_with_source(CameraSource::Network, url));
+			}
+		}
+		for observer in &mut list {
+			observer.subscribe(crate::entity_spawner::spawn_from_world(world, &CameraPreviewBuilder{}));
+		}
+		CameraObservers{list: list, reconnect: ReconnectPolicy::from_env()}
+	}
+}
+
+/// Source of frames which is polled every update of CameraObserver.
+/// Frames are taken in two steps, all cameras grab their frames before any of them is decoded.
+pub trait FrameSource: Send {
+	/// Takes next frame without decoding it, returns the time it was taken or None when no new frame is avaliable yet.
+	fn grab_frame(&mut self) -> opencv::Result<Option<std::time::Instant>>;
+	/// Decodes frame taken by the last grab_frame into `frame`.
+	fn retrieve_frame(&mut self, frame: &mut Mat) -> opencv::Result<bool>;
+}
+
+impl FrameSource for videoio::VideoCapture {
+	fn grab_frame(&mut self) -> opencv::Result<Option<std::time::Instant>> {
+		// grab only dequeues the buffer, so time after it is close to the time frame was delivered by driver
+		Ok(self.grab()?.then(std::time::Instant::now))
+	}
+
+	fn retrieve_frame(&mut self, frame: &mut Mat) -> opencv::Result<bool> {
+		self.retrieve(frame, 0)
 	}
 }
 
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CameraSource{
+	#[default]
+	Device,
+	Replay,
+	Network
+}
+
 /**
  * 
  */
 #[derive(Default)]
 pub struct CameraObserver {
 	state: state::State<OpencvCameraObserver, opencv::Error>,
+	source: CameraSource,
+	// bus info for devices, recording path for replays, url for network cameras
 	bus: String,
 	path: String,
-	subscribed_entities: std::collections::HashSet<Entity>
+	subscribed_entities: std::collections::HashSet<Entity>,
+	/// time the last frame was grabbed, for network cameras time it was received
+	pub frame_timestamp: Option<std::time::Instant>,
+	last_activity: Option<std::time::Instant>,
+	/// clock shared by replays opened together
+	replay_clock: Option<std::sync::Arc<camera_recorder::ReplayClock>>,
+	retries: u32,
+	retry_at: Option<std::time::Instant>
 }
 
 
 
 impl CameraObserver{
 
+	/// Observer of camera which isn't found by device enumeration, `path` is recording path or stream url.
+	pub fn new_with_source(source: CameraSource, path: &str) -> Self{
+		let mut observer = CameraObserver::default();
+		observer.source = source;
+		observer.bus = path.to_owned();
+		observer.path = path.to_owned();
+		observer
+	}
+
+	/// Unique identifier of observed camera, bus info for devices, file path for replays and url for network cameras.
+	pub fn get_id(&self) -> &str{
+		&self.bus
+	}
+
+	pub fn get_source(&self) -> CameraSource{
+		self.source
+	}
+
 	pub fn subscribe(&mut self, entity: Entity){
 		self.subscribed_entities.insert(entity);
 	}
@@ -155,7 +309,18 @@ impl CameraObserver{
 
 // private:
 
-	fn init_opencv_observer(filepath: &str) -> opencv::Result<OpencvCameraObserver>{		
+	fn init_opencv_observer(source: CameraSource, filepath: &str, replay_clock: Option<std::sync::Arc<camera_recorder::ReplayClock>>) -> opencv::Result<OpencvCameraObserver>{		
+		match source {
+			CameraSource::Replay => {
+				let replay = camera_recorder::ReplaySource::open(filepath, replay_clock.unwrap_or_default())?;
+				return Ok(OpencvCameraObserver::new(Box::new(replay)));
+			},
+			CameraSource::Network => {
+				let stream = network_camera::NetworkCameraSource::connect(filepath)?;
+				return Ok(OpencvCameraObserver::new(Box::new(stream)));
+			},
+			CameraSource::Device => {}
+		}
 		// Open the web-camera (assuming you have one)
 		let mut camera = videoio::VideoCapture::from_file(filepath, videoio::CAP_ANY)?; //videoio::VideoCapture::new(0, videoio::CAP_ANY)?;
 		camera.set(videoio::CAP_PROP_FRAME_WIDTH, 1024.)?;
@@ -163,30 +328,60 @@ impl CameraObserver{
 		camera.set(videoio::CAP_PROP_FPS, 30.)?;
 		
 
-		let opencv_observer = OpencvCameraObserver::new(camera);
+		let opencv_observer = OpencvCameraObserver::new(Box::new(camera));
 		Ok(opencv_observer)
 	}
 
-	fn update_frame(observer: &mut OpencvCameraObserver, frame: &mut Mat) -> opencv::Result<()>{
-		let mut cam = observer.video_capture.lock().unwrap();
-		cam.read(frame)?;
+	fn grab_frame(observer: &mut OpencvCameraObserver) -> opencv::Result<Option<std::time::Instant>>{
+		let mut source = observer.frame_source.lock().unwrap();
+		source.grab_frame()
+	}
 
-		Ok(())
+	/// Decodes frame grabbed by the last successful grab() into `frame`, returns true when it was decoded.
+	fn retrieve(&mut self, frame: &mut Mat) -> bool{
+		if let state::State::Run(Ok(observer)) = &mut self.state {
+			let result = observer.frame_source.lock().unwrap().retrieve_frame(frame);
+			match result {
+				Ok(retrieved) => return retrieved,
+				Err(error) => self.state.failed(error)
+			}
+		}
+		false
 	}
 
-	fn update(&mut self, frame: &mut Mat){
+	/// Progresses state of the observer, returns true when new frame was grabbed.
+	fn grab(&mut self, reconnect: &ReconnectPolicy) -> bool{
 		use state::*;
 		match &mut self.state {
 			State::None => {
 				self.state.restart_with(Ok(OpencvCameraObserver::default()));
 			},
 			State::Start(_) => {
-				self.state.pass(CameraObserver::init_opencv_observer(&self.path));
+				match CameraObserver::init_opencv_observer(self.source, &self.path, self.replay_clock.clone()) {
+					Ok(observer) => {
+						self.last_activity = Some(std::time::Instant::now());
+						self.state.successfull(observer);
+					},
+					Err(error) => {
+						self.state.failed(error);
+					}
+				}
 			},
 			State::Run(opencv_result) => {
-				let result = Self::update_frame(opencv_result.as_mut().unwrap(), frame);
+				let result = Self::grab_frame(opencv_result.as_mut().unwrap());
 				match result {
-					Ok(_) => {},
+					Ok(Some(timestamp)) => {
+						self.frame_timestamp = Some(timestamp);
+						self.last_activity = Some(std::time::Instant::now());
+						self.retries = 0;
+						return true;
+					},
+					Ok(None) => {
+						// replays wait for gaps in the recording, which can be longer than the timeout
+						if self.source != CameraSource::Replay && self.last_activity.map_or(false, |last_activity| last_activity.elapsed() > reconnect.stall_timeout) {
+							self.state.failed(opencv::Error::new(opencv::core::StsError, "Camera stopped providing frames".to_owned()));
+						}
+					},
 					Err(err) => {
 						self.state.failed(err);
 					}
@@ -194,13 +389,31 @@ impl CameraObserver{
 			},
 			State::Stop(opencv_result) => {
 				if let Err(error) = opencv_result{
-					println!("Error: {}", error);
+					if self.retries < reconnect.max_retries {
+						// subscribers are kept alive while camera is being reconnected
+						match self.retry_at {
+							None => {
+								let backoff = reconnect.backoff(self.retries);
+								println!("Error: {}, reconnecting {} in {:?}", error, self.path, backoff);
+								self.retry_at = Some(std::time::Instant::now() + backoff);
+							},
+							Some(retry_at) => {
+								if std::time::Instant::now() >= retry_at {
+									self.retries += 1;
+									self.retry_at = None;
+									self.state.restart();
+								}
+							}
+						}
+						return false;
+					}
+					println!("Error: {}, giving up on {} after {} retries", error, self.path, self.retries);
 				}				
 				self.state.skip();
 			}
 			_ => {}
 		}
-		
+		false
 	}
 
 	
@@ -209,18 +422,18 @@ impl CameraObserver{
 
  
 struct OpencvCameraObserver {
-	video_capture: std::sync::Mutex<videoio::VideoCapture>
+	frame_source: std::sync::Mutex<Box<dyn FrameSource>>
 }
 
 impl OpencvCameraObserver {
-	fn new(video_capture: videoio::VideoCapture) -> Self {
-		OpencvCameraObserver{video_capture: std::sync::Mutex::new(video_capture)}
+	fn new(frame_source: Box<dyn FrameSource>) -> Self {
+		OpencvCameraObserver{frame_source: std::sync::Mutex::new(frame_source)}
 	}
 }
 
 impl Default for OpencvCameraObserver {
 	fn default() -> Self {
-		OpencvCameraObserver{video_capture: std::sync::Mutex::new(videoio::VideoCapture::default().unwrap())}
+		OpencvCameraObserver::new(Box::new(videoio::VideoCapture::default().unwrap()))
 	}
 }
 
@@ -237,7 +450,7 @@ impl EntitySpawner for CameraPreviewBuilder{
 		commands.spawn(
 			(
 				frame_component::FrameComponent::default(),
-				window_preview::WindowPreviewComponent{window: window_builder.build(), processing_function: None},
+				window_preview::WindowPreviewComponent::new(window_builder.build(), processing_pipeline::ProcessingPipeline::default()),
 				window_preview::WindowInLayout,
 				CameraObserverSubscriberComponent
 			)
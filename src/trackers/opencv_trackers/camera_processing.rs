@@ -0,0 +1,78 @@
+use bevy::ecs::prelude as ecs;
+use bevy::ecs::query::With;
+
+use crate::trackers::opencv_trackers::camera_observer::*;
+use crate::trackers::opencv_trackers::frame_synchroniser;
+use crate::trackers::opencv_trackers::calibration::{intrinsics, undistortion};
+use crate::trackers::opencv_trackers::opencv_utilities::{
+	frame_component,
+	processing_pipeline,
+	window_preview
+};
+
+use std::any::type_name;
+
+/// Preprocessing of frames of single camera for one kind of trackers, spawned for every camera by `subscribe_system`.
+/// Preprocessed frames are kept in Synchronised FrameComponent of the entity, so trackers get them in framesets.
+pub trait CameraProcessing: ecs::Component + Default {
+	/// Whole frames of cameras with `UndistortionMode::Frame` are undistorted before the pipeline,
+	///  otherwise only points are undistorted, by UndistortionComponent of the entity.
+	const UNDISTORT_FRAMES: bool = true;
+
+	fn preprocess_pipeline() -> processing_pipeline::ProcessingPipeline;
+
+	/// Applied to preprocessed frames before they are displayed, empty pipeline displays them as they are.
+	fn preview_pipeline() -> processing_pipeline::ProcessingPipeline{
+		processing_pipeline::ProcessingPipeline::default()
+	}
+}
+
+/// Subscribes new processing `P` with preview window to every camera which has none yet.
+pub fn subscribe_system<P: CameraProcessing>(
+	mut commands: ecs::Commands,
+	camera_observers: Option<ecs::ResMut<CameraObservers>>,
+	query: ecs::Query<ecs::Entity, With<P>>
+){
+	if let Some(mut observers) = camera_observers {
+		for camera_observer in &mut observers.list {
+			if query.iter().any(|entity| camera_observer.is_subscribed(&entity)) {
+				continue;
+			}
+			let new_entity = spawn::<P>(&mut commands, camera_observer.get_id());
+			camera_observer.subscribe(new_entity);
+		}
+	}
+}
+
+fn spawn<P: CameraProcessing>(commands: &mut ecs::Commands, camera_id: &str) -> ecs::Entity{
+	let camera_intrinsics = intrinsics::CameraIntrinsics::load(camera_id).ok();
+	let mut pipeline = P::preprocess_pipeline();
+	if let Some(camera_intrinsics) = camera_intrinsics.as_ref() {
+		// whole frames are undistorted by the pipeline, before anything else
+		if P::UNDISTORT_FRAMES && camera_intrinsics.undistortion == undistortion::UndistortionMode::Frame {
+			pipeline.insert(0, Box::new(processing_pipeline::Undistort{undistortion: undistortion::Undistortion::new(camera_intrinsics.clone())}));
+		}
+	}
+
+	let mut window_component = window_preview::WindowPreviewComponent::default();
+	window_component.pipeline = std::sync::Mutex::new(P::preview_pipeline());
+	window_component.window.set_title(type_name::<P>());
+
+	let mut processing = commands.spawn(
+		(
+			P::default(),
+			CameraObserverSubscriberComponent,
+			ObservedCamera{camera_id: camera_id.to_owned()},
+			frame_component::FrameComponent::new(pipeline),
+			frame_synchroniser::Synchronised,
+
+			// debug components
+			window_component,
+			window_preview::WindowInLayout
+		)
+	);
+	if let Some(camera_intrinsics) = camera_intrinsics {
+		processing.insert(undistortion::UndistortionComponent::new(undistortion::Undistortion::new(camera_intrinsics)));
+	}
+	processing.id()
+}
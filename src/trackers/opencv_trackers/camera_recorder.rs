@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use bevy::ecs::prelude as ecs;
+use bevy::input::{Input, keyboard::KeyCode};
+use bevy::utils::synccell::SyncCell;
+
+use opencv::{
+    prelude::*,
+    videoio
+};
+
+use crate::trackers::opencv_trackers::camera_observer::FrameSource;
+
+/// Extension of the sidecar file, which holds timestamp of each recorded frame
+/// as milliseconds since start of the recording session (one per line), shared by all cameras recorded together.
+pub const TIMESTAMPS_EXTENSION: &str = "timestamps";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordingCodec{
+	Mjpeg,
+	Lossless
+}
+
+impl RecordingCodec {
+	fn fourcc(&self) -> i32{
+		match self {
+			RecordingCodec::Mjpeg => i32::from_le_bytes(*b"MJPG"),
+			RecordingCodec::Lossless => i32::from_le_bytes(*b"FFV1"),
+		}
+	}
+}
+
+struct Recording{
+	writer: videoio::VideoWriter,
+	timestamps: BufWriter<File>
+}
+
+/// Records raw frames of every CameraObserver into separate video files with sidecar timestamp files.
+/// Recording is toggled at runtime by `toggle_key` (F9 by default),
+///  recorded file can be later passed to RTRACK_REPLAY to be observed as a camera with original timing.
+#[derive(ecs::Resource)]
+pub struct CameraRecorder{
+	pub output_directory: PathBuf,
+	pub codec: RecordingCodec,
+	pub toggle_key: KeyCode,
+	/// start of the current recording session, None when not recording
+	session_started: Option<Instant>,
+	/// only accessed through &mut self, SyncCell makes the Send-only video writers shareable without locking
+	recordings: SyncCell<HashMap<String, Recording>>
+}
+
+impl Default for CameraRecorder {
+	fn default() -> Self {
+		CameraRecorder{
+			output_directory: PathBuf::from("recordings"),
+			codec: RecordingCodec::Mjpeg,
+			toggle_key: KeyCode::F9,
+			session_started: None,
+			recordings: SyncCell::new(HashMap::new())
+		}
+	}
+}
+
+impl CameraRecorder {
+	pub const DIRECTORY_ENV_VARIABLE: &'static str = "RTRACK_RECORDING_DIR";
+	pub const LOSSLESS_ENV_VARIABLE: &'static str = "RTRACK_RECORDING_LOSSLESS";
+
+	pub fn from_env() -> Self{
+		let mut recorder = CameraRecorder::default();
+		if let Some(directory) = std::env::var_os(Self::DIRECTORY_ENV_VARIABLE) {
+			recorder.output_directory = PathBuf::from(directory);
+		}
+		if std::env::var_os(Self::LOSSLESS_ENV_VARIABLE).is_some() {
+			recorder.codec = RecordingCodec::Lossless;
+		}
+		recorder
+	}
+
+	pub fn toggle_system(keys: Option<ecs::Res<Input<KeyCode>>>, mut recorder: ecs::ResMut<CameraRecorder>){
+		if let Some(keys) = keys {
+			if keys.just_pressed(recorder.toggle_key) {
+				recorder.toggle();
+			}
+		}
+	}
+
+	pub fn is_recording(&self) -> bool{
+		self.session_started.is_some()
+	}
+
+	/// Starts new session, timestamps of all cameras count from this moment so their skew is kept.
+	pub fn start(&mut self){
+		println!("Recording cameras into: {}", self.output_directory.display());
+		self.session_started = Some(Instant::now());
+	}
+
+	/// Stops recording and closes all opened files, next start will create new files.
+	pub fn stop(&mut self){
+		self.session_started = None;
+		self.recordings.get().clear();
+		println!("Recording stopped");
+	}
+
+	pub fn toggle(&mut self){
+		if self.is_recording() {
+			self.stop();
+		}
+		else {
+			self.start();
+		}
+	}
+
+	/// Writes frame of camera with `camera_id` when recording is enabled,
+	///  files for the camera are created with first recorded frame.
+	pub fn record(&mut self, camera_id: &str, frame: &Mat, timestamp: Instant) -> opencv::Result<()>{
+		let session_started = match self.session_started {
+			Some(session_started) => session_started,
+			None => return Ok(())
+		};
+		if frame.empty() {
+			return Ok(());
+		}
+
+		let recordings = self.recordings.get();
+		if !recordings.contains_key(camera_id) {
+			let recording = Self::open_recording(&self.output_directory, self.codec, camera_id, frame)?;
+			recordings.insert(camera_id.to_owned(), recording);
+		}
+
+		let recording = recordings.get_mut(camera_id).unwrap();
+		recording.writer.write(frame)?;
+		let elapsed = timestamp.saturating_duration_since(session_started).as_secs_f64() * 1000.;
+		writeln!(recording.timestamps, "{elapsed:.3}").map_err(io_error)?;
+		Ok(())
+	}
+
+	fn open_recording(directory: &Path, codec: RecordingCodec, camera_id: &str, frame: &Mat) -> opencv::Result<Recording>{
+		std::fs::create_dir_all(directory).map_err(io_error)?;
+
+		let unix_time = std::time::SystemTime::now()
+			.duration_since(std::time::UNIX_EPOCH)
+			.map(|time| time.as_secs())
+			.unwrap_or_default();
+		let file_name = crate::trackers::opencv_trackers::file_name_from_id(camera_id);
+		let video_path = directory.join(format!("{file_name}_{unix_time}.avi"));
+
+		let writer = videoio::VideoWriter::new(&video_path.display().to_string(), codec.fourcc(), 30., frame.size()?, true)?;
+		if !writer.is_opened()? {
+			return Err(opencv::Error::new(opencv::core::StsError, format!("Unable to open {} for writing", video_path.display())));
+		}
+		let timestamps = File::create(video_path.with_extension(TIMESTAMPS_EXTENSION)).map_err(io_error)?;
+		println!("Recording {} into: {}", camera_id, video_path.display());
+
+		Ok(Recording{writer: writer, timestamps: BufWriter::new(timestamps)})
+	}
+}
+
+fn io_error(error: std::io::Error) -> opencv::Error{
+	opencv::Error::new(opencv::core::StsError, error.to_string())
+}
+
+/// Time shared by replays opened together, it starts with the first frame grabbed by any of them.
+#[derive(Default)]
+pub struct ReplayClock{
+	started: Mutex<Option<Instant>>
+}
+
+impl ReplayClock {
+	/// Milliseconds since the first call.
+	fn elapsed(&self) -> f64{
+		let mut started = self.started.lock().unwrap();
+		started.get_or_insert_with(Instant::now).elapsed().as_secs_f64() * 1000.
+	}
+
+	fn get_started(&self) -> Option<Instant>{
+		*self.started.lock().unwrap()
+	}
+}
+
+/// Replays recording made by CameraRecorder, frames are released with their original timing
+///  and when recording ends it starts again from the beginning.
+/// Replays sharing `ReplayClock` keep the skew between cameras recorded in the same session.
+/// When sidecar timestamp file is missing, FPS of the video is used instead.
+pub struct ReplaySource{
+	video_capture: videoio::VideoCapture,
+	timestamps: Vec<f64>,
+	frame_interval: f64,
+	frame_index: usize,
+	clock: Arc<ReplayClock>,
+	/// time of the clock when the current pass of the recording started, in milliseconds
+	offset: f64
+}
+
+impl ReplaySource {
+	/// Opens recording at `path`, replay reopened later continues from the beginning at the current time of `clock`.
+	pub fn open(path: &str, clock: Arc<ReplayClock>) -> opencv::Result<Self>{
+		let video_capture = videoio::VideoCapture::from_file(path, videoio::CAP_ANY)?;
+		if !video_capture.is_opened()? {
+			return Err(opencv::Error::new(opencv::core::StsError, format!("Unable to open replay {}", path)));
+		}
+		let fps = video_capture.get(videoio::CAP_PROP_FPS)?;
+		let timestamps = Self::read_timestamps(&Path::new(path).with_extension(TIMESTAMPS_EXTENSION))
+			.unwrap_or_default();
+
+		Ok(ReplaySource{
+			video_capture: video_capture,
+			timestamps: timestamps,
+			frame_interval: if fps > 0. { 1000. / fps } else { 1000. / 30. },
+			frame_index: 0,
+			offset: clock.get_started().map_or(0., |_| clock.elapsed()),
+			clock: clock
+		})
+	}
+
+	fn read_timestamps(path: &Path) -> std::io::Result<Vec<f64>>{
+		let reader = BufReader::new(File::open(path)?);
+		let mut timestamps = vec![];
+		for line in reader.lines() {
+			if let Ok(timestamp) = line?.trim().parse::<f64>() {
+				timestamps.push(timestamp);
+			}
+		}
+		Ok(timestamps)
+	}
+
+	/// Time of the clock when the next frame is due, in milliseconds.
+	fn frame_due(&self) -> f64{
+		self.offset + match self.timestamps.get(self.frame_index) {
+			Some(timestamp) => *timestamp,
+			None => self.frame_index as f64 * self.frame_interval
+		}
+	}
+}
+
+impl FrameSource for ReplaySource {
+	fn grab_frame(&mut self) -> opencv::Result<Option<Instant>> {
+		let elapsed = self.clock.elapsed();
+		let started = self.clock.get_started().unwrap();
+		if elapsed < self.frame_due() {
+			return Ok(None);
+		}
+
+		// skip frames which are already late, so replay keeps up with recorded time
+		loop {
+			let due = self.frame_due();
+			if !self.video_capture.grab()? {
+				self.video_capture.set(videoio::CAP_PROP_POS_FRAMES, 0.)?;
+				self.frame_index = 0;
+				self.offset = elapsed;
+				return Ok(None);
+			}
+			self.frame_index += 1;
+			if elapsed < self.frame_due() {
+				// recorded timing is kept, so replayed cameras keep their original skew
+				return Ok(Some(started + std::time::Duration::from_secs_f64(due.max(0.) / 1000.)));
+			}
+		}
+	}
+
+	fn retrieve_frame(&mut self, frame: &mut Mat) -> opencv::Result<bool> {
+		self.video_capture.retrieve(frame, 0)
+	}
+}
@@ -0,0 +1,311 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use bevy::ecs::prelude as ecs;
+use opencv::prelude as cv;
+
+use crate::trackers::opencv_trackers::opencv_utilities::frame_component;
+
+/// Marks entity with FrameComponent, which frames should be grouped into FrameSets with frames of other cameras.
+#[derive(ecs::Component, Default)]
+pub struct Synchronised;
+
+pub struct SynchronisedFrame{
+	/// entity with Synchronised FrameComponent from which frame was taken
+	pub source: ecs::Entity,
+	pub timestamp: Instant,
+	pub frame: cv::Mat
+}
+
+/// Frames of different cameras captured within tolerance of each other.
+pub struct FrameSet{
+	pub timestamp: Instant,
+	pub frames: Vec<SynchronisedFrame>
+}
+
+impl FrameSet {
+	/// Difference between oldest and newest frame in the set.
+	pub fn skew(&self) -> Duration{
+		let oldest = self.frames.iter().map(|frame| frame.timestamp).min();
+		let newest = self.frames.iter().map(|frame| frame.timestamp).max();
+		match (oldest, newest) {
+			(Some(oldest), Some(newest)) => newest.duration_since(oldest),
+			_ => Duration::ZERO
+		}
+	}
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct SkewStatistics{
+	pub framesets: u64,
+	pub dropped_frames: u64,
+	pub mean_skew: Duration,
+	pub max_skew: Duration
+}
+
+impl SkewStatistics {
+	fn add(&mut self, skew: Duration){
+		self.framesets += 1;
+		// running mean, so statistics don't need to keep history
+		let mean = self.mean_skew.as_secs_f64();
+		self.mean_skew = Duration::from_secs_f64(mean + (skew.as_secs_f64() - mean) / self.framesets as f64);
+		self.max_skew = self.max_skew.max(skew);
+	}
+}
+
+struct CameraBuffer{
+	frames: VecDeque<(Instant, cv::Mat)>,
+	last_seen: Instant
+}
+
+/// Groups frames of all Synchronised entities into FrameSets, where every frame was captured
+///  within `tolerance` of the set timestamp. Cameras with lower frame rate drive the rate of FrameSets
+///  and frames of faster cameras which can't be paired are dropped.
+/// Camera which didn't deliver any frame for `stale_timeout` isn't waited for.
+#[derive(ecs::Resource)]
+pub struct FrameSynchroniser{
+	pub tolerance: Duration,
+	pub stale_timeout: Duration,
+	pub max_buffered: usize,
+	buffers: std::sync::Mutex<HashMap<ecs::Entity, CameraBuffer>>,
+	latest: std::sync::Mutex<Option<FrameSet>>,
+	generation: u64,
+	statistics: SkewStatistics
+}
+
+impl Default for FrameSynchroniser {
+	fn default() -> Self {
+		FrameSynchroniser{
+			tolerance: Duration::from_millis(17),
+			stale_timeout: Duration::from_millis(500),
+			max_buffered: 8,
+			buffers: std::sync::Mutex::new(HashMap::new()),
+			latest: std::sync::Mutex::new(None),
+			generation: 0,
+			statistics: SkewStatistics::default()
+		}
+	}
+}
+
+impl FrameSynchroniser {
+	pub const TOLERANCE_ENV_VARIABLE: &'static str = "RTRACK_SYNC_TOLERANCE_MS";
+
+	pub fn from_env() -> Self{
+		let mut synchroniser = FrameSynchroniser::default();
+		if let Some(tolerance) = std::env::var(Self::TOLERANCE_ENV_VARIABLE).ok().and_then(|value| value.parse::<u64>().ok()) {
+			synchroniser.tolerance = Duration::from_millis(tolerance);
+		}
+		synchroniser
+	}
+
+	/// Latest complete FrameSet.
+	pub fn get_latest(&self) -> &std::sync::Mutex<Option<FrameSet>>{
+		&self.latest
+	}
+
+	/// Increased every time new FrameSet is made, can be used to check if latest FrameSet changed.
+	pub fn get_generation(&self) -> u64{
+		self.generation
+	}
+
+	/// Latest FrameSet when it's newer than `last_generation` seen by the caller, which is updated.
+	/// Trackers process only frames captured at the same time, and each FrameSet once.
+	pub fn lock_new(&self, last_generation: &mut u64) -> Option<std::sync::MutexGuard<Option<FrameSet>>>{
+		if self.generation == *last_generation {
+			return None;
+		}
+		*last_generation = self.generation;
+		Some(self.latest.lock().unwrap())
+	}
+
+	pub fn get_statistics(&self) -> &SkewStatistics{
+		&self.statistics
+	}
+
+	pub fn collect_system(
+		mut synchroniser: ecs::ResMut<FrameSynchroniser>,
+		changed_query: ecs::Query<(ecs::Entity, &frame_component::FrameComponent), (ecs::With<Synchronised>, ecs::Changed<frame_component::FrameComponent>)>,
+		synchronised_query: ecs::Query<ecs::Entity, ecs::With<Synchronised>>
+	){
+		let synchroniser = synchroniser.as_mut();
+		let max_buffered = synchroniser.max_buffered;
+		let buffers = synchroniser.buffers.get_mut().unwrap();
+
+		buffers.retain(|entity, _| synchronised_query.contains(*entity));
+		for (entity, frame_component) in changed_query.iter() {
+			if let Some(timestamp) = frame_component.get_timestamp() {
+				let frame = frame_component.get_frame().lock().unwrap().clone();
+				let buffer = buffers.entry(entity).or_insert_with(|| CameraBuffer{frames: VecDeque::new(), last_seen: timestamp});
+				buffer.last_seen = timestamp;
+				buffer.frames.push_back((timestamp, frame));
+				while buffer.frames.len() > max_buffered {
+					buffer.frames.pop_front();
+					synchroniser.statistics.dropped_frames += 1;
+				}
+			}
+		}
+
+		while let Some(frameset) = Self::make_frameset(buffers, synchroniser.tolerance, synchroniser.stale_timeout, &mut synchroniser.statistics) {
+			synchroniser.statistics.add(frameset.skew());
+			*synchroniser.latest.get_mut().unwrap() = Some(frameset);
+			synchroniser.generation += 1;
+		}
+	}
+
+	pub fn report_system(synchroniser: ecs::Res<FrameSynchroniser>, mut last_report: ecs::Local<Option<Instant>>){
+		let last = *last_report.get_or_insert_with(Instant::now);
+		if last.elapsed() < Duration::from_secs(10) {
+			return;
+		}
+		*last_report = Some(Instant::now());
+
+		let statistics = synchroniser.get_statistics();
+		if statistics.framesets > 0 {
+			println!(
+				"Frame sync: {} framesets, {} dropped frames, skew mean: {:.2} ms max: {:.2} ms",
+				statistics.framesets,
+				statistics.dropped_frames,
+				statistics.mean_skew.as_secs_f64() * 1000.,
+				statistics.max_skew.as_secs_f64() * 1000.
+			);
+		}
+	}
+
+	fn make_frameset(buffers: &mut HashMap<ecs::Entity, CameraBuffer>, tolerance: Duration, stale_timeout: Duration, statistics: &mut SkewStatistics) -> Option<FrameSet>{
+		let newest = buffers.values().map(|buffer| buffer.last_seen).max()?;
+		let active: Vec<ecs::Entity> = buffers.iter()
+			.filter(|(_, buffer)| newest.duration_since(buffer.last_seen) <= stale_timeout)
+			.map(|(entity, _)| *entity)
+			.collect();
+
+		loop {
+			// the latest of the oldest frames is the earliest moment all cameras could have frame for
+			let mut reference: Option<Instant> = None;
+			for entity in &active {
+				let (timestamp, _) = buffers[entity].frames.front()?;
+				reference = Some(reference.map_or(*timestamp, |reference: Instant| reference.max(*timestamp)));
+			}
+			let reference = reference?;
+
+			let mut dropped_any = false;
+			for entity in &active {
+				let frames = &mut buffers.get_mut(entity).unwrap().frames;
+				while frames.front().map_or(false, |(timestamp, _)| reference.duration_since(*timestamp) > tolerance) {
+					frames.pop_front();
+					statistics.dropped_frames += 1;
+					dropped_any = true;
+				}
+			}
+			if dropped_any {
+				continue;
+			}
+
+			let mut frames = vec![];
+			for entity in &active {
+				let buffer_frames = &mut buffers.get_mut(entity).unwrap().frames;
+				let distance = |timestamp: &Instant| if *timestamp > reference { *timestamp - reference } else { reference - *timestamp };
+				// faster cameras might have frame closer to reference right after the first one
+				while buffer_frames.len() > 1 && distance(&buffer_frames[1].0) < distance(&buffer_frames[0].0) {
+					buffer_frames.pop_front();
+					statistics.dropped_frames += 1;
+				}
+				let (timestamp, frame) = buffer_frames.pop_front().unwrap();
+				frames.push(SynchronisedFrame{source: *entity, timestamp: timestamp, frame: frame});
+			}
+			return Some(FrameSet{timestamp: reference, frames: frames});
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn at(start: Instant, milliseconds: u64) -> Instant{
+		start + Duration::from_millis(milliseconds)
+	}
+
+	/// Buffer with frames grabbed at `milliseconds` after `start`, last one is when the camera was last seen.
+	fn buffer(start: Instant, milliseconds: &[u64]) -> CameraBuffer{
+		CameraBuffer{
+			frames: milliseconds.iter().map(|milliseconds| (at(start, *milliseconds), cv::Mat::default())).collect(),
+			last_seen: at(start, *milliseconds.last().unwrap())
+		}
+	}
+
+	fn all_framesets(buffers: &mut HashMap<ecs::Entity, CameraBuffer>, statistics: &mut SkewStatistics) -> Vec<FrameSet>{
+		let mut framesets = vec![];
+		while let Some(frameset) = FrameSynchroniser::make_frameset(buffers, Duration::from_millis(17), Duration::from_millis(500), statistics) {
+			framesets.push(frameset);
+		}
+		framesets
+	}
+
+	#[test]
+	fn slower_camera_drives_framesets_and_extra_frames_are_dropped(){
+		let start = Instant::now();
+		let (slow, fast) = (ecs::Entity::from_raw(0), ecs::Entity::from_raw(1));
+		let mut buffers = HashMap::from([
+			(slow, buffer(start, &[0, 33, 67, 100])),
+			(fast, buffer(start, &[0, 17, 33, 50, 67, 83, 100]))
+		]);
+		let mut statistics = SkewStatistics::default();
+
+		let framesets = all_framesets(&mut buffers, &mut statistics);
+		let timestamps: Vec<Instant> = framesets.iter().map(|frameset| frameset.timestamp).collect();
+		assert_eq!(timestamps, [0, 33, 67, 100].map(|milliseconds| at(start, milliseconds)));
+		for frameset in &framesets {
+			assert_eq!(frameset.frames.len(), 2);
+			assert_eq!(frameset.skew(), Duration::ZERO);
+		}
+		assert_eq!(statistics.dropped_frames, 3);
+	}
+
+	#[test]
+	fn frame_outside_tolerance_is_discarded(){
+		let start = Instant::now();
+		let (first, second) = (ecs::Entity::from_raw(0), ecs::Entity::from_raw(1));
+		let mut buffers = HashMap::from([
+			(first, buffer(start, &[0, 40])),
+			(second, buffer(start, &[40]))
+		]);
+		let mut statistics = SkewStatistics::default();
+
+		let framesets = all_framesets(&mut buffers, &mut statistics);
+		assert_eq!(framesets.len(), 1);
+		assert!(framesets[0].frames.iter().all(|frame| frame.timestamp == at(start, 40)));
+		assert_eq!(statistics.dropped_frames, 1);
+	}
+
+	#[test]
+	fn stale_camera_is_not_waited_for(){
+		let start = Instant::now();
+		let (active, silent) = (ecs::Entity::from_raw(0), ecs::Entity::from_raw(1));
+		let mut statistics = SkewStatistics::default();
+
+		// silent camera was seen recently, so the frameset waits for its next frame
+		let mut buffers = HashMap::from([
+			(active, buffer(start, &[1000])),
+			(silent, CameraBuffer{frames: VecDeque::new(), last_seen: at(start, 800)})
+		]);
+		assert!(all_framesets(&mut buffers, &mut statistics).is_empty());
+
+		buffers.get_mut(&silent).unwrap().last_seen = at(start, 400);
+		let framesets = all_framesets(&mut buffers, &mut statistics);
+		assert_eq!(framesets.len(), 1);
+		assert_eq!(framesets[0].frames.len(), 1);
+		assert_eq!(framesets[0].frames[0].source, active);
+	}
+
+	#[test]
+	fn statistics_keep_running_mean_and_maximum(){
+		let mut statistics = SkewStatistics::default();
+		for milliseconds in [10, 20, 30, 40] {
+			statistics.add(Duration::from_millis(milliseconds));
+		}
+		assert_eq!(statistics.framesets, 4);
+		assert!((statistics.mean_skew.as_secs_f64() - 0.025).abs() < 1e-9);
+		assert_eq!(statistics.max_skew, Duration::from_millis(40));
+	}
+}
@@ -0,0 +1,58 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::trackers::tracker::TrackerRole;
+use crate::trackers::opencv_trackers::tracker_definitions::DefinitionsFile;
+use crate::trackers::opencv_trackers::light_ball_trackers::light_ball_definitions::Smoothing;
+use crate::trackers::opencv_trackers::ir_trackers::ir_detection::{IrDetection, IrDetector};
+
+/// Single IR LED (or IR reflective ball) tracker.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct IrTrackerDefinition{
+	pub name: String,
+	#[serde(default)]
+	pub role: TrackerRole,
+	/// diameter of the glowing blob in metres, used for distance until the tracker is calibrated
+	pub diameter: f64,
+	#[serde(default)]
+	pub smoothing: Smoothing
+}
+
+/// IR trackers with their detection, read from `config/ir_trackers.ron` or file in `RTRACK_IR_TRACKERS`.
+/// There are no IR trackers without the file.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct IrTrackerDefinitions{
+	#[serde(default)]
+	pub detection: IrDetection,
+	pub trackers: Vec<IrTrackerDefinition>
+}
+
+impl DefinitionsFile for IrTrackerDefinitions {
+	const FILE_NAME: &'static str = "ir_trackers.ron";
+	const ENV_VARIABLE: &'static str = "RTRACK_IR_TRACKERS";
+	const FALLBACK: &'static str = "IR trackers are disabled";
+
+	fn validate(&self, path: &Path) -> Result<(), String>{
+		for (index, definition) in self.trackers.iter().enumerate() {
+			if definition.name.is_empty() {
+				return Err(format!("IR tracker {} in {} has no name", index, path.display()));
+			}
+			if self.trackers[..index].iter().any(|other| other.name == definition.name) {
+				return Err(format!("IR tracker '{}' is defined twice in {}", definition.name, path.display()));
+			}
+			if !(definition.diameter > 0.) {
+				return Err(format!("IR tracker '{}' needs positive diameter", definition.name));
+			}
+			if !(definition.smoothing.position > 0. && definition.smoothing.position <= 1.) {
+				return Err(format!("Smoothing of IR tracker '{}' must be in (0, 1]", definition.name));
+			}
+		}
+		if let IrDetector::AdaptiveThreshold{block_size, ..} = self.detection.detector {
+			if block_size < 3 || block_size % 2 == 0 {
+				return Err(format!("block_size in {} must be odd and at least 3", path.display()));
+			}
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,217 @@
+use bevy::ecs::prelude as ecs;
+
+use opencv::core;
+use opencv::prelude::*;
+
+use serde::{Deserialize, Serialize};
+
+use crate::trackers::opencv_trackers::light_ball_trackers::light_ball_tracker::EnclosingCircle;
+use crate::trackers::opencv_trackers::light_ball_trackers::blob_assignment::BlobCandidate;
+use crate::trackers::opencv_trackers::light_ball_trackers::blob_quality::BlobQuality;
+
+/// How bright blobs are found in grayscale frames.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum IrDetector{
+	/// pixels brighter than mean of their `block_size` neighbourhood by `offset`, copes with uneven background
+	AdaptiveThreshold{block_size: i32, offset: f64},
+	/// OpenCV SimpleBlobDetector, thresholds the frame in steps between `min_threshold` and `max_threshold`
+	SimpleBlobDetector{min_threshold: f32, max_threshold: f32, max_area: f32, min_circularity: f32}
+}
+
+impl Default for IrDetector {
+	fn default() -> Self {
+		IrDetector::AdaptiveThreshold{block_size: 31, offset: 20.}
+	}
+}
+
+/// Detection of IR LEDs, shared by all IR trackers as the LEDs differ only in position.
+#[derive(ecs::Resource, Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct IrDetection{
+	pub detector: IrDetector,
+	/// pixels darker than this are never part of a LED
+	pub min_brightness: f64,
+	/// in pixels
+	pub min_area: f64
+}
+
+impl Default for IrDetection {
+	fn default() -> Self {
+		IrDetection{detector: IrDetector::default(), min_brightness: 200., min_area: 2.}
+	}
+}
+
+impl IrDetection {
+	/// Mask of bright pixels and candidate blobs in preprocessed grayscale `frame`,
+	///  blob centres are intensity weighted centroids with sub-pixel precision.
+	pub fn detect(&self, frame: &core::Mat) -> opencv::Result<(core::Mat, Vec<BlobCandidate>)>{
+		let mut bright = core::Mat::default();
+		opencv::imgproc::threshold(frame, &mut bright, self.min_brightness, 255., opencv::imgproc::THRESH_BINARY)?;
+
+		let circles = match &self.detector {
+			IrDetector::AdaptiveThreshold{block_size, offset} => {
+				let mut adaptive = core::Mat::default();
+				// negative constant, so pixels have to be brighter than their neighbourhood
+				opencv::imgproc::adaptive_threshold(
+					frame,
+					&mut adaptive,
+					255.,
+					opencv::imgproc::ADAPTIVE_THRESH_MEAN_C,
+					opencv::imgproc::THRESH_BINARY,
+					*block_size,
+					-*offset
+				)?;
+				let mut mask = core::Mat::default();
+				core::bitwise_and(&adaptive, &bright, &mut mask, &core::Mat::default())?;
+				bright = mask;
+				self.contour_circles(&bright)?
+			},
+			IrDetector::SimpleBlobDetector{min_threshold, max_threshold, max_area, min_circularity} => {
+				self.keypoint_circles(frame, *min_threshold, *max_threshold, *max_area, *min_circularity)?
+			}
+		};
+
+		let mut candidates = vec![];
+		for (circle, area) in circles {
+			let position = Self::centroid(frame, &bright, &circle)?.unwrap_or(circle.position);
+			candidates.push(BlobCandidate{
+				circle: EnclosingCircle{position: position, radius: circle.radius},
+				area: area,
+				// shape of few pixels large LEDs says nothing
+				quality: BlobQuality{score: 1., ..Default::default()},
+				visible_fraction: 1.
+			});
+		}
+		Ok((bright, candidates))
+	}
+
+	fn contour_circles(&self, mask: &core::Mat) -> opencv::Result<Vec<(EnclosingCircle, f64)>>{
+		let mut contours = core::Vector::<core::Vector<core::Point>>::new();
+		opencv::imgproc::find_contours(
+			mask,
+			&mut contours,
+			opencv::imgproc::RETR_EXTERNAL,
+			opencv::imgproc::CHAIN_APPROX_SIMPLE,
+			core::Point::default()
+		)?;
+		let mut circles = vec![];
+		for contour in contours.iter() {
+			let area = opencv::imgproc::contour_area(&contour, false)?;
+			if area < self.min_area {
+				continue;
+			}
+			let mut circle = EnclosingCircle::default();
+			opencv::imgproc::min_enclosing_circle(&contour, &mut circle.position, &mut circle.radius)?;
+			circles.push((circle, area));
+		}
+		Ok(circles)
+	}
+
+	fn keypoint_circles(&self, frame: &core::Mat, min_threshold: f32, max_threshold: f32, max_area: f32, min_circularity: f32) -> opencv::Result<Vec<(EnclosingCircle, f64)>>{
+		let mut parameters = opencv::features2d::SimpleBlobDetector_Params::default()?;
+		parameters.min_threshold = min_threshold;
+		parameters.max_threshold = max_threshold;
+		// LEDs are bright on dark background
+		parameters.filter_by_color = true;
+		parameters.blob_color = 255;
+		parameters.filter_by_area = true;
+		parameters.min_area = self.min_area as f32;
+		parameters.max_area = max_area;
+		parameters.filter_by_circularity = true;
+		parameters.min_circularity = min_circularity;
+		parameters.filter_by_inertia = false;
+		parameters.filter_by_convexity = false;
+
+		let mut detector = opencv::features2d::SimpleBlobDetector::create(parameters)?;
+		let mut keypoints = core::Vector::<core::KeyPoint>::new();
+		detector.detect(frame, &mut keypoints, &core::Mat::default())?;
+
+		Ok(keypoints.iter()
+			.map(|keypoint| {
+				let radius = keypoint.size() / 2.;
+				let area = std::f64::consts::PI * (radius * radius) as f64;
+				(EnclosingCircle{position: keypoint.pt(), radius: radius}, area)
+			})
+			.collect())
+	}
+
+	/// Centroid of `frame` intensity under `mask` within `circle`, None when there is no bright pixel.
+	fn centroid(frame: &core::Mat, mask: &core::Mat, circle: &EnclosingCircle) -> opencv::Result<Option<core::Point2f>>{
+		let frame_size = frame.size()?;
+		let radius = circle.radius.max(1.);
+		let left = ((circle.position.x - radius) as i32).max(0);
+		let top = ((circle.position.y - radius) as i32).max(0);
+		let right = ((circle.position.x + radius).ceil() as i32 + 1).min(frame_size.width);
+		let bottom = ((circle.position.y + radius).ceil() as i32 + 1).min(frame_size.height);
+		if right <= left || bottom <= top {
+			return Ok(None);
+		}
+		let region = core::Rect::new(left, top, right - left, bottom - top);
+
+		let mut masked = core::Mat::zeros(region.height, region.width, frame.typ())?.to_mat()?;
+		core::Mat::roi(frame, region)?.copy_to_masked(&mut masked, &core::Mat::roi(mask, region)?)?;
+		let moments = opencv::imgproc::moments(&masked, false)?;
+		if moments.m00 <= 0. {
+			return Ok(None);
+		}
+		Ok(Some(core::Point2f::new(
+			(left as f64 + moments.m10 / moments.m00) as f32,
+			(top as f64 + moments.m01 / moments.m00) as f32
+		)))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const SPOTS: [(f32, f32); 2] = [(30.4, 40.6), (70.6, 55.5)];
+
+	/// Dark grayscale frame with Gaussian spots of `sigma` pixels centred between pixels.
+	fn frame_with_spots(sigma: f32) -> core::Mat{
+		let mut frame = core::Mat::new_rows_cols_with_default(100, 100, core::CV_8UC1, core::Scalar::all(10.)).unwrap();
+		for row in 0..100 {
+			for column in 0..100 {
+				let intensity: f32 = SPOTS.iter()
+					.map(|(x, y)| {
+						let distance_squared = (column as f32 - x).powi(2) + (row as f32 - y).powi(2);
+						240. * (-distance_squared / (2. * sigma * sigma)).exp()
+					})
+					.sum();
+				*frame.at_2d_mut::<u8>(row, column).unwrap() = (10. + intensity).round().min(255.) as u8;
+			}
+		}
+		frame
+	}
+
+	fn assert_finds_spots(detection: &IrDetection){
+		let frame = frame_with_spots(3.);
+		let (mask, mut candidates) = detection.detect(&frame).unwrap();
+		assert_eq!(mask.size().unwrap(), frame.size().unwrap());
+		assert_eq!(candidates.len(), SPOTS.len());
+		candidates.sort_by(|a, b| a.circle.position.x.total_cmp(&b.circle.position.x));
+		for (candidate, (x, y)) in candidates.iter().zip(SPOTS) {
+			// whole pixels would be at least 0.4 off
+			let position = candidate.circle.position;
+			assert!((position.x - x).abs() < 0.2 && (position.y - y).abs() < 0.2, "{:?} for ({}, {})", position, x, y);
+		}
+	}
+
+	#[test]
+	fn adaptive_threshold_finds_sub_pixel_centroids(){
+		assert_finds_spots(&IrDetection{
+			detector: IrDetector::AdaptiveThreshold{block_size: 31, offset: 20.},
+			min_brightness: 60.,
+			min_area: 2.
+		});
+	}
+
+	#[test]
+	fn simple_blob_detector_finds_sub_pixel_centroids(){
+		assert_finds_spots(&IrDetection{
+			detector: IrDetector::SimpleBlobDetector{min_threshold: 50., max_threshold: 220., max_area: 500., min_circularity: 0.5},
+			min_brightness: 60.,
+			min_area: 2.
+		});
+	}
+}
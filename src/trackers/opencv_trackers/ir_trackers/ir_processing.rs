@@ -0,0 +1,28 @@
+use bevy::ecs::prelude as ecs;
+
+use crate::trackers::opencv_trackers::OpencvTrackers;
+use crate::trackers::opencv_trackers::camera_processing::{self, CameraProcessing};
+use crate::trackers::opencv_trackers::opencv_utilities::processing_pipeline;
+
+// ------- IR Tracker Processing ------- //
+pub struct IrTrackerProcessingBuilder;
+
+impl bevy::app::Plugin for IrTrackerProcessingBuilder{
+	fn build(&self, app: &mut bevy::prelude::App) {
+		OpencvTrackers::init_schedule(app)
+			.add_system(camera_processing::subscribe_system::<IrTrackerProcessing>);
+	}
+}
+
+/// Grayscale frames of single camera for IR trackers.
+#[derive(ecs::Component, Default)]
+pub struct IrTrackerProcessing;
+
+impl CameraProcessing for IrTrackerProcessing {
+	/// Intensity with light blur, which spreads single pixel noise below LED brightness.
+	fn preprocess_pipeline() -> processing_pipeline::ProcessingPipeline{
+		processing_pipeline::ProcessingPipeline::new()
+			.with(processing_pipeline::Grayscale)
+			.with(processing_pipeline::GaussianBlur{kernel_size: 3})
+	}
+}
@@ -0,0 +1,202 @@
+use bevy::ecs::prelude as ecs;
+
+use opencv::prelude::MatTraitConst;
+
+use crate::entity_spawner;
+use crate::entity_spawner::EntitySpawner;
+use crate::trackers::tracker;
+use crate::trackers::opencv_trackers::OpencvTrackers;
+use crate::trackers::opencv_trackers::frame_synchroniser::FrameSynchroniser;
+use crate::trackers::opencv_trackers::triangulation::{Observation, Triangulator};
+use crate::trackers::opencv_trackers::calibration::playspace::Playspace;
+use crate::trackers::opencv_trackers::calibration::undistortion::UndistortionComponent;
+use crate::trackers::opencv_trackers::calibration::intrinsics::CameraIntrinsics;
+use crate::trackers::opencv_trackers::camera_observer::ObservedCamera;
+use crate::trackers::opencv_trackers::light_ball_trackers::light_ball_tracker::{LightBallCalibration, LightBallTracker};
+use crate::trackers::opencv_trackers::light_ball_trackers::light_ball_definitions::Smoothing;
+use crate::trackers::opencv_trackers::light_ball_trackers::blob_assignment::{self, BlobTrack, BlobTracks};
+use crate::trackers::opencv_trackers::ir_trackers::ir_definitions::{IrTrackerDefinition, IrTrackerDefinitions};
+use crate::trackers::opencv_trackers::ir_trackers::ir_detection::IrDetection;
+use crate::trackers::opencv_trackers::ir_trackers::ir_processing::IrTrackerProcessing;
+use crate::trackers::opencv_trackers::opencv_utilities::{
+	frame_component,
+	window_preview
+};
+
+use std::any::type_name;
+use std::collections::HashMap;
+// ------- IR Tracker ------- //
+
+/// Spawns IR trackers from their definitions and registers their systems.
+pub struct IrTrackerBuilder{
+	definitions: IrTrackerDefinitions
+}
+impl IrTrackerBuilder {
+	pub fn new(definitions: IrTrackerDefinitions) -> Self{
+		IrTrackerBuilder{definitions: definitions}
+	}
+}
+
+impl EntitySpawner for IrTrackerDefinition{
+	fn spawn(&self, commands: &mut ecs::Commands) -> ecs::Entity{
+		let mut window_component = window_preview::WindowPreviewComponent::default();
+		window_component.window.set_title(&format!("{} {}", type_name::<IrTracker>(), self.name));
+
+		commands.spawn((
+			tracker::TrackerData::default(),
+			self.role,
+			IrTracker::from_definition(self),
+			frame_component::FrameComponent::default(),
+			window_component,
+			window_preview::WindowInLayout
+		)).id()
+	}
+}
+
+impl bevy::app::Plugin for IrTrackerBuilder{
+	fn build(&self, app: &mut bevy::prelude::App) {
+		let world = &mut app.world;
+		for definition in &self.definitions.trackers {
+			entity_spawner::spawn_from_world(world, definition);
+		}
+		app.insert_resource(self.definitions.detection.clone());
+
+		OpencvTrackers::init_schedule(app)
+			.add_system(IrTracker::update_system);
+	}
+}
+
+/// Bright blob in grayscale frames, e.g. IR LED seen by camera with IR-pass filter.
+/// All IR blobs look the same, so they are told apart only by assignment to predicted positions,
+///  which come from the other cameras when a camera lost the LED (see `BlobTracks`).
+#[derive(ecs::Component, Default)]
+pub struct IrTracker{
+	name: String,
+	/// in metres
+	real_radius: f64,
+	smoothing: Smoothing,
+	smoothed_position: Option<[f64; 3]>,
+	/// last blob seen by each camera
+	blob_tracks: BlobTracks,
+	/// size/distance calibration for each camera id, loaded when camera is seen for the first time
+	calibrations: HashMap<String, LightBallCalibration>
+}
+
+impl IrTracker {
+	pub fn from_definition(definition: &IrTrackerDefinition) -> Self{
+		IrTracker{
+			name: definition.name.clone(),
+			real_radius: definition.diameter / 2.,
+			smoothing: definition.smoothing.clone(),
+			..Default::default()
+		}
+	}
+
+	pub fn get_name(&self) -> &str{
+		&self.name
+	}
+
+	fn update_system(
+		mut commands: ecs::Commands,
+		synchroniser: ecs::Res<FrameSynchroniser>,
+		triangulator: ecs::Res<Triangulator>,
+		playspace: ecs::Res<Playspace>,
+		detection: ecs::Res<IrDetection>,
+		processing_query: ecs::Query<(&ObservedCamera, Option<&UndistortionComponent>), ecs::With<IrTrackerProcessing>>,
+		mut tracker_query: ecs::Query<(ecs::Entity, &mut IrTracker, &mut frame_component::FrameComponent, Option<&window_preview::WindowPreviewComponent>), ecs::Without<IrTrackerProcessing>>,
+		mut last_generation: ecs::Local<u64>
+	){
+		// only frames captured at the same time are processed together
+		let latest_frameset = match synchroniser.lock_new(&mut last_generation) {
+			Some(latest_frameset) => latest_frameset,
+			None => return
+		};
+		let synchronised_frames = latest_frameset.as_ref().map_or(&[][..], |frameset| &frameset.frames[..]);
+
+		let mut trackers: Vec<_> = tracker_query.iter_mut().collect();
+		let mut observations: Vec<Vec<Observation>> = trackers.iter().map(|_| vec![]).collect();
+		for synchronised_frame in synchronised_frames {
+			let (camera, undistortion) = match processing_query.get(synchronised_frame.source) {
+				Ok(found) => found,
+				Err(_) => continue
+			};
+			let (mask, candidates) = match detection.detect(&synchronised_frame.frame) {
+				Ok(detected) => detected,
+				Err(_) => continue
+			};
+			let mask_size = match mask.size() {
+				Ok(size) => size,
+				Err(_) => continue
+			};
+			// mask is kept only for preview windows, copied into their buffers which are reused for frames of the same size
+			for (_, _, frame_mask, _) in trackers.iter_mut().filter(|(_, _, _, preview)| preview.is_some()) {
+				mask.copy_to(frame_mask.get_frame_mut()).unwrap_or_default();
+			}
+
+			let tracks: Vec<Option<BlobTrack>> = trackers.iter()
+				.map(|(_, tracker, _, _)| tracker.blob_tracks.get_or_seed(&camera.camera_id, mask_size, &triangulator, tracker.real_radius))
+				.collect();
+			let assignment = blob_assignment::assign(&tracks, &candidates);
+			for (index, ((_, tracker, _, _), assigned)) in trackers.iter_mut().zip(assignment).enumerate() {
+				let circle = match assigned {
+					Some(assigned) => candidates[assigned].circle,
+					None => {
+						tracker.blob_tracks.remove(&camera.camera_id);
+						continue;
+					}
+				};
+				tracker.blob_tracks.follow(&camera.camera_id, &circle);
+
+				let mut undistorted_screen_space = circle;
+				if let Some(component) = undistortion {
+					if let Ok(position) = component.get_undistortion().lock().unwrap().undistort_point(circle.position, mask_size) {
+						undistorted_screen_space.position = position;
+					}
+				}
+				let intrinsics = undistortion.map(|component| component.get_undistortion().lock().unwrap().get_intrinsics().clone());
+				let calibration = tracker.get_calibration(&camera.camera_id, &mask_size, intrinsics.as_ref()).clone();
+				if let Some(position) = LightBallTracker::compute_position(&calibration, &undistorted_screen_space, &mask_size, intrinsics.as_ref()){
+					observations[index].push(Observation{
+						camera_id: camera.camera_id.clone(),
+						pixel: undistorted_screen_space.position,
+						frame_size: mask_size,
+						radius_position: position
+					});
+				}
+			}
+		}
+
+		for (index, (entity, tracker, _, _)) in trackers.iter_mut().enumerate() {
+			let entity = *entity;
+			// views of all cameras are combined into single position
+			let located = triangulator.locate(&observations[index]);
+			tracker.blob_tracks.locate(located.as_ref(), &observations[index], &triangulator, tracker.real_radius);
+			let found = located.map(|tracking_position| {
+				playspace.record(&tracker.name, &tracking_position, tracker.real_radius);
+				tracker::TrackerData{
+					position: tracker.smooth(playspace.to_playspace(&tracking_position)),
+					confidence: 1.,
+					..Default::default()
+				}
+			});
+			// lost LED keeps its last position, only confidence drops
+			tracker::TrackerData::report(&mut commands, entity, found);
+		}
+	}
+
+	fn smooth(&mut self, position: tracker::Position) -> tracker::Position{
+		self.smoothing.smooth(&mut self.smoothed_position, position)
+	}
+
+	/// Size/distance calibration of this tracker for camera with `camera_id`, scaled to `frame_size`.
+	fn get_calibration(&mut self, camera_id: &str, frame_size: &opencv::core::Size, intrinsics: Option<&CameraIntrinsics>) -> &LightBallCalibration{
+		let name = &self.name;
+		let real_radius = self.real_radius;
+		let calibration = self.calibrations.entry(camera_id.to_owned())
+			.or_insert_with(|| LightBallCalibration::load_or_guess(camera_id, name, real_radius, frame_size, intrinsics));
+		if calibration.get_frame_size() != frame_size {
+			*calibration = calibration.scaled_to(frame_size);
+		}
+		calibration
+	}
+}
@@ -0,0 +1,23 @@
+pub mod ir_tracker;
+pub mod ir_definitions;
+pub mod ir_detection;
+mod ir_processing;
+use bevy::app::Plugin;
+
+use crate::trackers::opencv_trackers::tracker_definitions::DefinitionsFile;
+
+pub fn setup_entities(app: &mut bevy::prelude::App){
+	// grayscale processing of every camera is needed only when some IR tracker is defined
+	let definitions = ir_definitions::IrTrackerDefinitions::from_env();
+	if definitions.trackers.is_empty() {
+		return;
+	}
+	let setup_list: Vec<Box<dyn Plugin>> = vec![
+		Box::new(ir_tracker::IrTrackerBuilder::new(definitions)),
+		Box::new(ir_processing::IrTrackerProcessingBuilder),
+	];
+
+	for entity_builder in setup_list{
+		entity_builder.build(app);
+	}
+}
@@ -0,0 +1,318 @@
+use std::io::{BufRead, Write};
+
+use opencv::{
+	prelude::*,
+	core,
+	highgui,
+	imgproc,
+	videoio
+};
+
+use crate::cli;
+use crate::state::*;
+use crate::trackers::opencv_trackers::calibration::{intrinsics, undistortion};
+use crate::trackers::opencv_trackers::camera_processing::CameraProcessing;
+use crate::trackers::opencv_trackers::tracker_definitions::DefinitionsFile;
+use crate::trackers::opencv_trackers::light_ball_trackers::distance_model::{DistanceModel, DistanceModelKind, DistanceSample};
+use crate::trackers::opencv_trackers::light_ball_trackers::light_ball_processing::LightBallTrackerProcessing;
+use crate::trackers::opencv_trackers::light_ball_trackers::light_ball_tracker::{
+	EnclosingCircle,
+	LightBallCalibration,
+	LightBallTracker
+};
+use crate::trackers::opencv_trackers::light_ball_trackers::blob_assignment;
+use crate::trackers::opencv_trackers::light_ball_trackers::light_ball_definitions::LightBallDefinitions;
+
+/// Number of frames with detected ball, which radius is averaged (median) into single sample.
+const SAMPLE_FRAMES: usize = 30;
+/// Frames skipped before sampling, camera may hold frames buffered while waiting for input.
+const SKIPPED_FRAMES: usize = 5;
+
+pub struct BallCalibrationOptions{
+	/// camera node, video file or stream url
+	pub source: String,
+	pub camera_id: Option<String>,
+	pub tracker_name: String,
+	/// ball diameter in metres, asked for when not set
+	pub diameter: Option<f64>,
+	pub model: DistanceModelKind
+}
+
+impl BallCalibrationOptions {
+	pub const USAGE: &'static str = "rtrack calibrate-ball <camera node | video file> --tracker NAME [--diameter METRES] [--model inverse|polynomial|table] [--camera-id ID]";
+
+	pub fn from_args(args: &[String]) -> Result<Self, String>{
+		let source = args.first()
+			.filter(|arg| !arg.starts_with("--"))
+			.ok_or_else(|| format!("Missing camera or video file\nUsage: {}", Self::USAGE))?
+			.clone();
+		let tracker_name = cli::option_value::<String>(args, "--tracker")?
+			.ok_or_else(|| format!("Missing tracker, one of: {}\nUsage: {}", LightBallDefinitions::from_env().get_names().join(", "), Self::USAGE))?;
+
+		Ok(BallCalibrationOptions{
+			source: source,
+			camera_id: cli::option_value(args, "--camera-id")?,
+			tracker_name: tracker_name,
+			diameter: cli::option_value(args, "--diameter")?,
+			model: match cli::option_value::<String>(args, "--model")? {
+				Some(model) => DistanceModelKind::from_name(&model).ok_or_else(|| format!("Unknown distance model '{}'", model))?,
+				None => DistanceModelKind::Polynomial
+			}
+		})
+	}
+}
+
+/// Guided size/distance calibration of single light ball tracker seen by single camera.
+///
+/// Goes through State phases:
+/// Start - real ball diameter is entered,
+/// Run - ball is held at known distances and its apparent radius is sampled, once for each entered distance,
+/// Stop - radius to distance model is fitted and saved for the tracker and camera.
+pub struct BallCalibrationWizard{
+	state: State<String, String>,
+	model: DistanceModelKind,
+	capture: videoio::VideoCapture,
+	undistortion: Option<undistortion::Undistortion>,
+	/// detects the ball with mask pipeline, blob filter and radius estimator of its definition
+	tracker: LightBallTracker,
+	camera_id: String,
+	tracker_name: String,
+	real_radius: f64,
+	frame_size: core::Size,
+	samples: Vec<DistanceSample>
+}
+
+impl BallCalibrationWizard {
+	const WINDOW: &'static str = "Light ball calibration";
+
+	pub fn new(options: &BallCalibrationOptions) -> opencv::Result<Self>{
+		let tracker = LightBallDefinitions::from_env().find(&options.tracker_name)
+			.map(|definition| LightBallTracker::from_definition(definition, definition.get_color_range()))
+			.ok_or_else(|| opencv::Error::new(core::StsBadArg, format!("Unknown tracker '{}'", options.tracker_name)))?;
+		let capture = videoio::VideoCapture::from_file(&options.source, videoio::CAP_ANY)?;
+		if !capture.is_opened()? {
+			return Err(opencv::Error::new(core::StsError, format!("Unable to open {}", options.source)));
+		}
+		let camera_id = options.camera_id.clone().unwrap_or_else(|| intrinsics::camera_id_of(&options.source));
+
+		Ok(BallCalibrationWizard{
+			state: State::new(Ok(options.diameter.map(|diameter| diameter.to_string()).unwrap_or_default())),
+			model: options.model,
+			capture: capture,
+			undistortion: intrinsics::CameraIntrinsics::load(&camera_id).ok().map(undistortion::Undistortion::new),
+			tracker: tracker,
+			camera_id: camera_id,
+			tracker_name: options.tracker_name.clone(),
+			real_radius: 0.,
+			frame_size: core::Size::default(),
+			samples: vec![]
+		})
+	}
+
+	/// Runs all phases, returns message of the last phase.
+	pub fn run(&mut self) -> Result<String, String>{
+		while !self.state.is_done() {
+			self.update();
+		}
+		let _ = highgui::destroy_window(Self::WINDOW);
+		self.state.replace(Some(Ok(String::new()))).unwrap()
+	}
+
+	fn update(&mut self){
+		match &self.state {
+			State::Start(diameter) => {
+				let diameter = match diameter {
+					Ok(diameter) if !diameter.is_empty() => diameter.clone(),
+					_ => match read_line("Real ball diameter in metres: ") {
+						Some(diameter) => diameter,
+						None => {
+							self.state.failed("Calibration cancelled".to_owned());
+							return;
+						}
+					}
+				};
+				match diameter.trim().parse::<f64>() {
+					Ok(diameter) if diameter > 0. => {
+						self.real_radius = diameter / 2.;
+						self.state.successfull(format!("Ball diameter {} m", diameter));
+					},
+					_ => self.state.failed(format!("Invalid diameter '{}'", diameter.trim()))
+				}
+			},
+			State::Run(_) => {
+				let input = match read_line(&format!(
+					"Hold the ball in front of the camera and enter its distance in metres ({} samples, empty to finish, q to cancel): ",
+					self.samples.len()
+				)) {
+					Some(input) => input,
+					None => {
+						self.state.failed("Calibration cancelled".to_owned());
+						return;
+					}
+				};
+				let input = input.trim();
+				if input == "q" {
+					self.state.failed("Calibration cancelled".to_owned());
+				}
+				else if input.is_empty() {
+					if self.samples.len() >= 2 {
+						self.state.successfull(format!("Collected {} samples", self.samples.len()));
+					}
+					else {
+						println!("At least 2 samples at different distances are needed");
+					}
+				}
+				else {
+					match input.parse::<f64>() {
+						Ok(distance) if distance > 0. => match self.sample_radius() {
+							Ok(Some(radius)) => {
+								match self.samples.iter().find(|sample| (sample.radius - radius).abs() < DistanceModel::MIN_RADIUS_DIFFERENCE) {
+									Some(other) => println!("Radius {:.2} px is the same as at {} m, sample discarded", radius, other.distance),
+									None => {
+										println!("Radius {:.2} px at {} m", radius, distance);
+										self.samples.push(DistanceSample{radius: radius, distance: distance});
+									}
+								}
+							},
+							Ok(None) => println!("Sample discarded"),
+							Err(error) => self.state.failed(error.to_string())
+						},
+						_ => println!("Invalid distance '{}'", input)
+					}
+				}
+			},
+			State::Stop(_) => {
+				// errors of previous phases are propagated into Done
+				if self.state.check_and_propagate_error() && !self.state.is_done() {
+					let fit = match DistanceModel::fit(self.model, &self.samples) {
+						Ok(fit) => fit,
+						Err(error) => {
+							self.state.failed(error.to_string());
+							return;
+						}
+					};
+					match (&fit.residuals, fit.rms(), fit.max()) {
+						(Some(residuals), Some(rms), Some(max)) => {
+							for (sample, residual) in self.samples.iter().zip(residuals.iter()) {
+								println!("{:.3} m: radius {:.2} px, residual {:+.4} m", sample.distance, sample.radius, residual);
+							}
+							println!("{} model, RMS distance error: {:.4} m, max: {:.4} m", self.model.name(), rms, max);
+						},
+						_ => println!("{} model passes through all samples, {} samples are needed to measure its error", self.model.name(), fit.samples_for_residuals)
+					}
+
+					let calibration = focal_length_calibration(&self.samples, self.real_radius, &self.frame_size)
+						.with_model(fit.model);
+					match calibration.save(&self.camera_id, &self.tracker_name) {
+						Ok(path) => self.state.successfull(format!("Saved {} calibration of {} into {}", self.tracker_name, self.camera_id, path.display())),
+						Err(error) => self.state.failed(error.to_string())
+					}
+				}
+			},
+			_ => {}
+		}
+	}
+
+	/// Median radius of the ball over SAMPLE_FRAMES frames, None when cancelled by ESC.
+	fn sample_radius(&mut self) -> opencv::Result<Option<f64>>{
+		highgui::named_window(Self::WINDOW, highgui::WINDOW_NORMAL)?;
+		let mut radii = vec![];
+		let mut frame = core::Mat::default();
+		let mut skipped = 0;
+		let mut rewound = false;
+
+		while radii.len() < SAMPLE_FRAMES {
+			if !self.capture.read(&mut frame)? || frame.empty() {
+				if rewound {
+					return Err(opencv::Error::new(core::StsError, "Unable to read frames".to_owned()));
+				}
+				// video files are sampled again from the beginning
+				self.capture.set(videoio::CAP_PROP_POS_FRAMES, 0.)?;
+				rewound = true;
+				continue;
+			}
+			rewound = false;
+			if skipped < SKIPPED_FRAMES {
+				skipped += 1;
+				continue;
+			}
+
+			let mut undistorted = core::Mat::default();
+			match self.undistortion.as_mut() {
+				Some(undistortion) => undistortion.undistort_frame(&mut undistorted, &frame)?,
+				None => undistorted.clone_from(&frame)
+			}
+			self.frame_size = undistorted.size()?;
+
+			if let Some(circle) = detect_ball(&undistorted, &mut self.tracker)? {
+				radii.push(circle.radius as f64);
+				imgproc::circle(
+					&mut undistorted,
+					core::Point::new(circle.position.x as i32, circle.position.y as i32),
+					circle.radius as i32,
+					core::Scalar::new(0., 255., 0., 0.),
+					2,
+					imgproc::LINE_8,
+					0
+				)?;
+			}
+			imgproc::put_text(
+				&mut undistorted,
+				&format!("{}/{} frames, ESC to discard", radii.len(), SAMPLE_FRAMES),
+				core::Point::new(20, 40),
+				imgproc::FONT_HERSHEY_SIMPLEX,
+				1.0,
+				core::Scalar::new(255., 255., 255., 255.),
+				2,
+				imgproc::LINE_8,
+				false
+			)?;
+			highgui::imshow(Self::WINDOW, &undistorted)?;
+			if highgui::wait_key(1)? == 27 {
+				return Ok(None);
+			}
+		}
+
+		radii.sort_by(|a, b| a.total_cmp(b));
+		Ok(Some(radii[radii.len() / 2]))
+	}
+}
+
+/// Calibration with focal length of the ideal pinhole camera, fitted as `distance = real_radius * focal_length / radius`.
+pub fn focal_length_calibration(samples: &[DistanceSample], real_radius: f64, frame_size: &core::Size) -> LightBallCalibration{
+	LightBallCalibration::from_focal_length(frame_size, DistanceModel::inverse_coefficient(samples) / real_radius, real_radius)
+}
+
+/// Finds ball of `tracker` in BGR frame the same way as LightBallTracker does when the ball isn't followed yet:
+///  blob taken by `blob_assignment::assign` from candidates of its mask pipeline, blob filter and radius estimator.
+pub fn detect_ball(frame: &core::Mat, tracker: &mut LightBallTracker) -> opencv::Result<Option<EnclosingCircle>>{
+	let mut preprocessed = core::Mat::default();
+	LightBallTrackerProcessing::preprocess_pipeline().process(&mut preprocessed, frame)?;
+	let mask = tracker.make_mask(&preprocessed)?;
+	let candidates = tracker.find_candidates(&mask, &preprocessed, core::Point::default())?;
+	Ok(blob_assignment::assign(&[None], &candidates)[0].map(|index| candidates[index].circle))
+}
+
+/// Line entered after `prompt`, None when stdin is closed or can't be read.
+fn read_line(prompt: &str) -> Option<String>{
+	print!("{}", prompt);
+	let _ = std::io::stdout().flush();
+	let mut line = String::new();
+	match std::io::stdin().lock().read_line(&mut line) {
+		Ok(0) | Err(_) => None,
+		Ok(_) => Some(line)
+	}
+}
+
+/// Entry point of `rtrack calibrate-ball`.
+pub fn run(args: &[String]) -> std::io::Result<()>{
+	let options = BallCalibrationOptions::from_args(args).map_err(cli::invalid_input)?;
+	let mut wizard = BallCalibrationWizard::new(&options).map_err(cli::opencv_error)?;
+	match wizard.run() {
+		Ok(message) => {
+			println!("{}", message);
+			Ok(())
+		},
+		Err(error) => Err(std::io::Error::new(std::io::ErrorKind::Other, error))
+	}
+}
@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+
+use opencv::core::{Point2f, Rect, Size};
+
+use crate::trackers::tracker;
+use crate::trackers::opencv_trackers::triangulation::{Observation, Triangulator};
+use crate::trackers::opencv_trackers::light_ball_trackers::light_ball_tracker::EnclosingCircle;
+use crate::trackers::opencv_trackers::light_ball_trackers::blob_quality::BlobQuality;
+
+/// Cost of pairs outside the gate, such pairs are never assigned.
+const GATED: f64 = 1e9;
+
+/// Blob of the ball in the last frame of single camera, in pixels.
+#[derive(Clone, Copy)]
+pub struct BlobTrack{
+	pub position: Point2f,
+	/// per frame
+	pub velocity: Point2f,
+	pub radius: f32
+}
+
+impl BlobTrack {
+	/// Gate in multiples of blob radius and of its speed.
+	const RADIUS_SCALE: f32 = 3.;
+	const SPEED_SCALE: f32 = 2.;
+	/// in pixels, so small or still blobs have room to move
+	const MARGIN: f32 = 16.;
+
+	pub fn new(circle: &EnclosingCircle) -> Self{
+		BlobTrack{position: circle.position, velocity: Point2f::default(), radius: circle.radius}
+	}
+
+	/// Track continued by `circle` in the next frame.
+	pub fn followed_by(&self, circle: &EnclosingCircle) -> Self{
+		BlobTrack{position: circle.position, velocity: circle.position - self.position, radius: circle.radius}
+	}
+
+	pub fn predicted(&self) -> Point2f{
+		self.position + self.velocity
+	}
+
+	/// Distance from predicted position within which blobs can belong to this track, grows with size and speed of the blob.
+	pub fn gate(&self) -> f32{
+		let speed = (self.velocity.x * self.velocity.x + self.velocity.y * self.velocity.y).sqrt();
+		self.radius * Self::RADIUS_SCALE + speed * Self::SPEED_SCALE + Self::MARGIN
+	}
+
+	/// Square window of the gate around predicted position, clipped by frame of `frame_size`.
+	pub fn search_window(&self, frame_size: Size) -> Rect{
+		let predicted = self.predicted();
+		let half_size = self.gate();
+
+		let left = ((predicted.x - half_size) as i32).max(0);
+		let top = ((predicted.y - half_size) as i32).max(0);
+		let right = ((predicted.x + half_size).ceil() as i32).min(frame_size.width);
+		let bottom = ((predicted.y + half_size).ceil() as i32).min(frame_size.height);
+		Rect::new(left, top, (right - left).max(0), (bottom - top).max(0))
+	}
+}
+
+/// Blob tracks of one ball in all cameras, kept consistent with its located position,
+///  so the ball has the same identity in every camera even when other balls look the same.
+///
+/// Camera which lost the ball is given a track reprojected from the last located position, instead of taking the largest blob left.
+/// Once views of at least two cameras agree on the position, tracks of cameras which don't (e.g. following the other ball) are dropped
+///  and seeded again from the position. Reprojected tracks ignore lens distortion, which the gate of the track has to cover.
+/// Two cameras following different balls can't agree, so they are left as they are.
+/// Without calibrated cameras (see `Triangulator`) tracks of each camera are independent.
+#[derive(Default)]
+pub struct BlobTracks{
+	/// by camera id
+	tracks: HashMap<String, BlobTrack>,
+	/// last position of the ball in the reference frame, None when lost
+	located: Option<tracker::Position>
+}
+
+impl BlobTracks {
+	pub fn get(&self, camera_id: &str) -> Option<BlobTrack>{
+		self.tracks.get(camera_id).copied()
+	}
+
+	/// Track of the ball in camera `camera_id`, reprojected from the located position when the camera lost the ball.
+	pub fn get_or_seed(&self, camera_id: &str, frame_size: Size, triangulator: &Triangulator, real_radius: f64) -> Option<BlobTrack>{
+		self.get(camera_id).or_else(|| {
+			let located = self.located.as_ref()?;
+			let (pixel, radius) = triangulator.project(camera_id, frame_size, located, real_radius)?;
+			let inside = pixel.x >= 0. && pixel.y >= 0. && pixel.x < frame_size.width as f32 && pixel.y < frame_size.height as f32;
+			inside.then(|| BlobTrack{position: pixel, velocity: Point2f::default(), radius: radius})
+		})
+	}
+
+	/// Continues track of camera `camera_id` by `circle`, reprojected tracks are started anew.
+	pub fn follow(&mut self, camera_id: &str, circle: &EnclosingCircle){
+		let track = match self.tracks.get(camera_id) {
+			Some(track) => track.followed_by(circle),
+			None => BlobTrack::new(circle)
+		};
+		self.tracks.insert(camera_id.to_owned(), track);
+	}
+
+	pub fn remove(&mut self, camera_id: &str){
+		self.tracks.remove(camera_id);
+	}
+
+	/// Records `located` position of the ball seen in `observations`, drops tracks of cameras which disagree with it.
+	pub fn locate(&mut self, located: Option<&tracker::Position>, observations: &[Observation], triangulator: &Triangulator, real_radius: f64){
+		self.located = located.map(|position| tracker::Position{x: position.x, y: position.y, z: position.z});
+		let located = match located {
+			Some(located) => located,
+			None => return
+		};
+		let errors: Vec<(&str, f32)> = observations.iter()
+			.filter_map(|observation| {
+				let (pixel, _) = triangulator.project(&observation.camera_id, observation.frame_size, located, real_radius)?;
+				let offset = observation.pixel - pixel;
+				Some((observation.camera_id.as_str(), (offset.x * offset.x + offset.y * offset.y).sqrt()))
+			})
+			.collect();
+		// single view always agrees with position derived from its own blob
+		let agreeing = errors.iter().filter(|(_, error)| *error as f64 <= triangulator.max_reprojection_error).count();
+		if agreeing < 2 {
+			return;
+		}
+		for (camera_id, error) in errors {
+			if self.tracks.get(camera_id).map_or(false, |track| error > track.gate()) {
+				self.tracks.remove(camera_id);
+			}
+		}
+	}
+}
+
+/// Blob in the mask which may be a ball.
+#[derive(Clone)]
+pub struct BlobCandidate{
+	pub circle: EnclosingCircle,
+	/// contour area in pixels
+	pub area: f64,
+	pub quality: BlobQuality,
+	/// fraction of the circle perimeter seen as blob edge, low when the ball is partly covered
+	pub visible_fraction: f64
+}
+
+/// Index of the candidate assigned to each track, None when the ball wasn't found.
+///
+/// Followed balls (with track) are matched by Hungarian algorithm, cost is distance from predicted position plus change of radius,
+///  poor blobs (low quality score) cost up to one more radius.
+/// Candidates outside the gate of a track are never assigned to it, so reflections far from the ball don't steal the track.
+/// Balls without track take the largest candidates left, weighted by score.
+pub fn assign(tracks: &[Option<BlobTrack>], candidates: &[BlobCandidate]) -> Vec<Option<usize>>{
+	let mut assignment = vec![None; tracks.len()];
+
+	let followed: Vec<usize> = (0..tracks.len()).filter(|index| tracks[*index].is_some()).collect();
+	if !followed.is_empty() && !candidates.is_empty() {
+		let size = followed.len().max(candidates.len());
+		let mut costs = vec![vec![GATED; size]; size];
+		for (row, track) in followed.iter().map(|index| tracks[*index].as_ref().unwrap()).enumerate() {
+			let predicted = track.predicted();
+			for (column, candidate) in candidates.iter().enumerate() {
+				let offset = candidate.circle.position - predicted;
+				let distance = ((offset.x * offset.x + offset.y * offset.y) as f64).sqrt();
+				if distance <= track.gate() as f64 {
+					costs[row][column] = distance
+						+ (candidate.circle.radius - track.radius).abs() as f64
+						+ (1. - candidate.quality.score) * track.radius as f64;
+				}
+			}
+		}
+		for (row, column) in hungarian(&costs).into_iter().enumerate() {
+			if row < followed.len() && column < candidates.len() && costs[row][column] < GATED {
+				assignment[followed[row]] = Some(column);
+			}
+		}
+	}
+
+	let mut free: Vec<usize> = (0..candidates.len()).filter(|index| !assignment.contains(&Some(*index))).collect();
+	let weight = |index: &usize| candidates[*index].area * candidates[*index].quality.score;
+	free.sort_by(|a, b| weight(b).total_cmp(&weight(a)));
+	let mut free = free.into_iter();
+	for (track, assigned) in tracks.iter().zip(assignment.iter_mut()) {
+		if track.is_none() {
+			*assigned = free.next();
+		}
+	}
+	assignment
+}
+
+/// Column assigned to each row with minimal total cost (Hungarian algorithm), `costs` is square.
+pub fn hungarian(costs: &[Vec<f64>]) -> Vec<usize>{
+	// https://cp-algorithms.com/graph/hungarian-algorithm.html, rows and columns are indexed from 1
+	let size = costs.len();
+	let mut row_potential = vec![0.; size + 1];
+	let mut column_potential = vec![0.; size + 1];
+	// row matched to each column, 0 when none
+	let mut matched = vec![0; size + 1];
+	let mut way = vec![0; size + 1];
+
+	for row in 1..=size {
+		matched[0] = row;
+		let mut column = 0;
+		let mut min_slack = vec![f64::INFINITY; size + 1];
+		let mut used = vec![false; size + 1];
+		loop {
+			used[column] = true;
+			let current_row = matched[column];
+			let mut delta = f64::INFINITY;
+			let mut next_column = 0;
+			for other in 1..=size {
+				if used[other] {
+					continue;
+				}
+				let slack = costs[current_row - 1][other - 1] - row_potential[current_row] - column_potential[other];
+				if slack < min_slack[other] {
+					min_slack[other] = slack;
+					way[other] = column;
+				}
+				if min_slack[other] < delta {
+					delta = min_slack[other];
+					next_column = other;
+				}
+			}
+			for other in 0..=size {
+				if used[other] {
+					row_potential[matched[other]] += delta;
+					column_potential[other] -= delta;
+				}
+				else {
+					min_slack[other] -= delta;
+				}
+			}
+			column = next_column;
+			if matched[column] == 0 {
+				break;
+			}
+		}
+		loop {
+			let previous = way[column];
+			matched[column] = matched[previous];
+			column = previous;
+			if column == 0 {
+				break;
+			}
+		}
+	}
+
+	let mut assignment = vec![0; size];
+	for column in 1..=size {
+		if matched[column] != 0 {
+			assignment[matched[column] - 1] = column - 1;
+		}
+	}
+	assignment
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn circle(x: f32, y: f32, radius: f32) -> EnclosingCircle{
+		EnclosingCircle{position: Point2f::new(x, y), radius: radius}
+	}
+
+	fn candidate(x: f32, y: f32, radius: f32) -> BlobCandidate{
+		BlobCandidate{
+			circle: circle(x, y, radius),
+			area: std::f64::consts::PI * (radius * radius) as f64,
+			quality: BlobQuality{score: 1., ..Default::default()},
+			visible_fraction: 1.
+		}
+	}
+
+	#[test]
+	fn hungarian_minimises_total_cost(){
+		let costs = vec![
+			vec![4., 1., 3.],
+			vec![2., 0., 5.],
+			vec![3., 2., 2.]
+		];
+		// column 1 is the cheapest for row 1, yet the minimum gives it to row 0
+		assert_eq!(hungarian(&costs), vec![1, 0, 2]);
+		assert!(hungarian(&[]).is_empty());
+	}
+
+	#[test]
+	fn followed_balls_keep_identity_when_candidates_are_reordered(){
+		let tracks = [
+			Some(BlobTrack::new(&circle(100., 100., 10.))),
+			Some(BlobTrack::new(&circle(200., 100., 10.)))
+		];
+		let candidates = [candidate(205., 102., 10.), candidate(98., 101., 10.)];
+		assert_eq!(assign(&tracks, &candidates), vec![Some(1), Some(0)]);
+	}
+
+	#[test]
+	fn prediction_follows_velocity(){
+		let track = BlobTrack::new(&circle(100., 100., 10.)).followed_by(&circle(130., 100., 10.));
+		assert_eq!(track.predicted(), Point2f::new(160., 100.));
+		// the blob left behind is closer to the last position, but farther from the predicted one
+		let candidates = [candidate(125., 100., 10.), candidate(158., 100., 10.)];
+		assert_eq!(assign(&[Some(track)], &candidates), vec![Some(1)]);
+	}
+
+	#[test]
+	fn blobs_outside_gate_are_never_assigned(){
+		let track = BlobTrack::new(&circle(100., 100., 10.));
+		let far = 100. + track.gate() + 1.;
+		assert_eq!(assign(&[Some(track)], &[candidate(far, 100., 10.)]), vec![None]);
+	}
+
+	#[test]
+	fn unfollowed_balls_take_largest_blobs_left(){
+		let tracks = [None, Some(BlobTrack::new(&circle(100., 100., 10.))), None];
+		let candidates = [candidate(300., 300., 5.), candidate(101., 100., 10.), candidate(500., 500., 20.)];
+		assert_eq!(assign(&tracks, &candidates), vec![Some(2), Some(1), Some(0)]);
+		assert_eq!(assign(&tracks, &candidates[..2]), vec![Some(0), Some(1), None]);
+	}
+
+	#[test]
+	fn search_window_is_clipped_by_frame(){
+		let track = BlobTrack::new(&circle(5., 5., 2.));
+		let window = track.search_window(Size::new(640, 480));
+		assert_eq!((window.x, window.y), (0, 0));
+		assert!(window.width > 5 && window.height > 5);
+		let outside = BlobTrack::new(&circle(-100., -100., 2.));
+		let window = outside.search_window(Size::new(640, 480));
+		assert!(window.width == 0 || window.height == 0);
+	}
+}
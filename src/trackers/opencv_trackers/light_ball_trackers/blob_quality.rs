@@ -0,0 +1,210 @@
+use opencv::core;
+use opencv::prelude::*;
+
+use serde::{Deserialize, Serialize};
+
+use crate::trackers::opencv_trackers::light_ball_trackers::light_ball_tracker::EnclosingCircle;
+
+/// Shape and brightness metrics of a candidate blob, all of them 0 (poor) to 1 (ideal ball).
+#[derive(Clone, Copy, Default, Debug)]
+pub struct BlobQuality{
+	/// 4π·area / perimeter², 1 for a disc
+	pub circularity: f64,
+	/// contour area / area of the enclosing circle
+	pub fill_ratio: f64,
+	/// contour area / area of its convex hull
+	pub convexity: f64,
+	/// how much darker the surroundings are than the blob, lamps and screens are bright around as well
+	pub brightness: f64,
+	pub score: f64
+}
+
+impl BlobQuality {
+	/// Index of the value channel in preprocessed HSV frames.
+	const VALUE_CHANNEL: i32 = 2;
+	/// Outer radius of the ring around the blob compared in brightness, in multiples of blob radius.
+	const RING_SCALE: f32 = 1.5;
+
+	/// Metrics of `contour` with its enclosing `circle`, brightness is measured in `frame` (preprocessed HSV),
+	///  `offset` is position of the contour's mask in the frame.
+	pub fn measure(contour: &core::Vector<core::Point>, area: f64, circle: &EnclosingCircle, frame: &core::Mat, offset: core::Point) -> opencv::Result<Self>{
+		let perimeter = opencv::imgproc::arc_length(contour, true)?;
+		let circularity = if perimeter > 0. { 4. * std::f64::consts::PI * area / (perimeter * perimeter) } else { 0. };
+
+		let circle_area = std::f64::consts::PI * (circle.radius * circle.radius) as f64;
+		let fill_ratio = if circle_area > 0. { area / circle_area } else { 0. };
+
+		let mut hull = core::Vector::<core::Point>::new();
+		opencv::imgproc::convex_hull(contour, &mut hull, false, true)?;
+		let hull_area = opencv::imgproc::contour_area(&hull, false)?;
+		let convexity = if hull_area > 0. { area / hull_area } else { 0. };
+
+		let brightness = Self::brightness_contrast(circle, frame, offset)?;
+
+		let mut quality = BlobQuality{
+			circularity: circularity.clamp(0., 1.),
+			fill_ratio: fill_ratio.clamp(0., 1.),
+			convexity: convexity.clamp(0., 1.),
+			brightness: brightness,
+			score: 0.
+		};
+		// brightness only down-weights, halo of the ball may be bright as well
+		quality.score = (quality.circularity * quality.fill_ratio * quality.convexity).cbrt() * (0.5 + 0.5 * quality.brightness);
+		Ok(quality)
+	}
+
+	/// (blob - ring) / blob of mean value, blob circle is relative to the frame region at `offset`.
+	fn brightness_contrast(circle: &EnclosingCircle, frame: &core::Mat, offset: core::Point) -> opencv::Result<f64>{
+		if frame.empty() || frame.channels() <= Self::VALUE_CHANNEL {
+			return Ok(0.);
+		}
+		let frame_size = frame.size()?;
+		let centre = circle.position - core::Point2f::new(offset.x as f32, offset.y as f32);
+		let outer_radius = circle.radius * Self::RING_SCALE;
+
+		let left = ((centre.x - outer_radius) as i32).max(0);
+		let top = ((centre.y - outer_radius) as i32).max(0);
+		let right = ((centre.x + outer_radius).ceil() as i32).min(frame_size.width);
+		let bottom = ((centre.y + outer_radius).ceil() as i32).min(frame_size.height);
+		if right <= left || bottom <= top {
+			return Ok(0.);
+		}
+		let region = core::Rect::new(left, top, right - left, bottom - top);
+
+		let mut value = core::Mat::default();
+		core::extract_channel(&core::Mat::roi(frame, region)?, &mut value, Self::VALUE_CHANNEL)?;
+
+		let local_centre = core::Point::new((centre.x - left as f32) as i32, (centre.y - top as f32) as i32);
+		let mut inner = core::Mat::zeros(region.height, region.width, core::CV_8UC1)?.to_mat()?;
+		opencv::imgproc::circle(&mut inner, local_centre, circle.radius as i32, core::Scalar::all(255.), opencv::imgproc::FILLED, opencv::imgproc::LINE_8, 0)?;
+		let mut ring = core::Mat::zeros(region.height, region.width, core::CV_8UC1)?.to_mat()?;
+		opencv::imgproc::circle(&mut ring, local_centre, outer_radius as i32, core::Scalar::all(255.), opencv::imgproc::FILLED, opencv::imgproc::LINE_8, 0)?;
+		opencv::imgproc::circle(&mut ring, local_centre, circle.radius as i32, core::Scalar::all(0.), opencv::imgproc::FILLED, opencv::imgproc::LINE_8, 0)?;
+
+		let inner_value = core::mean(&value, &inner)?.0[0];
+		if inner_value <= 0. {
+			return Ok(0.);
+		}
+		// ring clipped away by the frame border is dark
+		let ring_value = if core::count_non_zero(&ring)? > 0 { core::mean(&value, &ring)?.0[0] } else { 0. };
+		Ok(((inner_value - ring_value) / inner_value).clamp(0., 1.))
+	}
+}
+
+/// Limits of candidate blobs of light ball, blobs outside them are rejected.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct BlobFilter{
+	/// radius of the circle from the radius estimator, in pixels
+	pub min_radius: f32,
+	pub max_radius: f32,
+	pub min_circularity: f64,
+	pub min_fill_ratio: f64,
+	pub min_convexity: f64,
+	pub min_score: f64
+}
+
+impl Default for BlobFilter {
+	fn default() -> Self {
+		// loose enough for motion blurred and partially covered balls
+		BlobFilter{
+			min_radius: 2.,
+			max_radius: 250.,
+			min_circularity: 0.4,
+			min_fill_ratio: 0.3,
+			min_convexity: 0.7,
+			min_score: 0.3
+		}
+	}
+}
+
+impl BlobFilter {
+	pub fn accepts(&self, circle: &EnclosingCircle, quality: &BlobQuality) -> bool{
+		circle.radius >= self.min_radius
+			&& circle.radius <= self.max_radius
+			&& quality.circularity >= self.min_circularity
+			&& quality.fill_ratio >= self.min_fill_ratio
+			&& quality.convexity >= self.min_convexity
+			&& quality.score >= self.min_score
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const SIZE: i32 = 100;
+
+	/// Mask with a shape drawn by `draw` in white.
+	fn mask(draw: impl Fn(&mut core::Mat)) -> core::Mat{
+		let mut mask = core::Mat::zeros(SIZE, SIZE, core::CV_8UC1).unwrap().to_mat().unwrap();
+		draw(&mut mask);
+		mask
+	}
+
+	/// HSV frame whose value channel is `value`.
+	fn hsv_frame(value: &core::Mat) -> core::Mat{
+		let zeros = core::Mat::zeros(SIZE, SIZE, core::CV_8UC1).unwrap().to_mat().unwrap();
+		let mut frame = core::Mat::default();
+		core::merge(&core::Vector::<core::Mat>::from_iter([zeros.clone(), zeros, value.clone()]), &mut frame).unwrap();
+		frame
+	}
+
+	/// Quality and enclosing circle of the largest blob of `mask`, measured in `frame`.
+	fn measure(mask: &core::Mat, frame: &core::Mat) -> (BlobQuality, EnclosingCircle){
+		let mut contours = core::Vector::<core::Vector<core::Point>>::new();
+		opencv::imgproc::find_contours(mask, &mut contours, opencv::imgproc::RETR_EXTERNAL, opencv::imgproc::CHAIN_APPROX_NONE, core::Point::default()).unwrap();
+		let contour = contours.iter()
+			.max_by(|a, b| opencv::imgproc::contour_area(a, false).unwrap().total_cmp(&opencv::imgproc::contour_area(b, false).unwrap()))
+			.unwrap();
+		let area = opencv::imgproc::contour_area(&contour, false).unwrap();
+		let mut circle = EnclosingCircle::default();
+		opencv::imgproc::min_enclosing_circle(&contour, &mut circle.position, &mut circle.radius).unwrap();
+		(BlobQuality::measure(&contour, area, &circle, frame, core::Point::default()).unwrap(), circle)
+	}
+
+	fn disc(mask: &mut core::Mat, centre: core::Point, radius: i32, color: f64){
+		opencv::imgproc::circle(mask, centre, radius, core::Scalar::all(color), opencv::imgproc::FILLED, opencv::imgproc::LINE_8, 0).unwrap();
+	}
+
+	#[test]
+	fn bright_disc_is_accepted(){
+		let mask = mask(|mask| disc(mask, core::Point::new(50, 50), 20, 255.));
+		let (quality, circle) = measure(&mask, &hsv_frame(&mask));
+		assert!(quality.circularity > 0.8, "{:?}", quality);
+		assert!(quality.fill_ratio > 0.85, "{:?}", quality);
+		assert!(quality.convexity > 0.95, "{:?}", quality);
+		assert!(quality.brightness > 0.95, "{:?}", quality);
+		assert!(quality.score > 0.8, "{:?}", quality);
+		assert!(BlobFilter::default().accepts(&circle, &quality));
+	}
+
+	#[test]
+	fn thin_rectangle_is_rejected(){
+		let mask = mask(|mask| {
+			opencv::imgproc::rectangle(mask, core::Rect::new(20, 48, 60, 4), core::Scalar::all(255.), opencv::imgproc::FILLED, opencv::imgproc::LINE_8, 0).unwrap();
+		});
+		let (quality, circle) = measure(&mask, &hsv_frame(&mask));
+		assert!(quality.fill_ratio < 0.3, "{:?}", quality);
+		assert!(!BlobFilter::default().accepts(&circle, &quality));
+	}
+
+	#[test]
+	fn crescent_is_rejected(){
+		let mask = mask(|mask| {
+			disc(mask, core::Point::new(50, 50), 20, 255.);
+			disc(mask, core::Point::new(60, 50), 20, 0.);
+		});
+		let (quality, circle) = measure(&mask, &hsv_frame(&mask));
+		assert!(quality.convexity < 0.7, "{:?}", quality);
+		assert!(!BlobFilter::default().accepts(&circle, &quality));
+	}
+
+	#[test]
+	fn uniformly_bright_surroundings_give_no_brightness(){
+		let mask = mask(|mask| disc(mask, core::Point::new(50, 50), 20, 255.));
+		let bright = core::Mat::new_rows_cols_with_default(SIZE, SIZE, core::CV_8UC1, core::Scalar::all(255.)).unwrap();
+		let (quality, _) = measure(&mask, &hsv_frame(&bright));
+		assert!(quality.brightness.abs() < 1e-9, "{:?}", quality);
+	}
+}
@@ -0,0 +1,86 @@
+use bevy::ecs::prelude as ecs;
+use bevy::input::{Input, keyboard::KeyCode};
+
+use crate::trackers::opencv_trackers::opencv_utilities::{frame_component, window_preview};
+use crate::trackers::opencv_trackers::light_ball_trackers::light_ball_processing::LightBallTrackerProcessing;
+use crate::trackers::opencv_trackers::light_ball_trackers::light_ball_tracker::{ColorRangeHSV, LightBallTracker};
+
+/// Colour calibration by selecting the ball in a processing preview window.
+///
+/// `next_tracker_key` (F8) chooses tracker which gets the colour, then dragging a rectangle over the ball
+///  in any LightBallTrackerProcessing window proposes new colour range from selected pixels.
+/// Range is applied to the tracker straight away and saved.
+#[derive(ecs::Resource)]
+pub struct ColorCalibration{
+	pub next_tracker_key: KeyCode,
+	tracker_name: Option<String>
+}
+
+impl Default for ColorCalibration {
+	fn default() -> Self {
+		ColorCalibration{next_tracker_key: KeyCode::F8, tracker_name: None}
+	}
+}
+
+impl ColorCalibration {
+	pub fn get_tracker_name(&self) -> Option<&str>{
+		self.tracker_name.as_deref()
+	}
+
+	pub fn selection_system(
+		keys: Option<ecs::Res<Input<KeyCode>>>,
+		mut color_calibration: ecs::ResMut<ColorCalibration>,
+		processing_query: ecs::Query<(&window_preview::WindowPreviewComponent, &frame_component::FrameComponent), ecs::With<LightBallTrackerProcessing>>,
+		mut tracker_query: ecs::Query<&mut LightBallTracker>
+	){
+		if let Some(keys) = keys {
+			if keys.just_pressed(color_calibration.next_tracker_key) {
+				let mut names: Vec<String> = tracker_query.iter().map(|tracker| tracker.get_name().to_owned()).collect();
+				names.sort();
+				let next = match &color_calibration.tracker_name {
+					Some(current) => names.iter().position(|name| name == current).map_or(0, |index| (index + 1) % names.len().max(1)),
+					None => 0
+				};
+				color_calibration.tracker_name = names.get(next).cloned();
+				match &color_calibration.tracker_name {
+					Some(name) => println!("Colour calibration of {}: drag a rectangle over the ball in a processing window", name),
+					None => println!("There is no light ball tracker to calibrate")
+				}
+			}
+		}
+
+		for (window_component, frame_component) in processing_query.iter() {
+			let region = match window_component.window.take_selected_region() {
+				Some(region) => region,
+				None => continue
+			};
+			let tracker_name = match &color_calibration.tracker_name {
+				Some(name) => name,
+				None => {
+					println!("Press {:?} to choose tracker before selecting its colour", color_calibration.next_tracker_key);
+					continue;
+				}
+			};
+
+			// processing frames are already in HSV
+			let frame = frame_component.get_frame().lock().unwrap();
+			let color_range = match ColorRangeHSV::from_hsv_region(&frame, region) {
+				Ok(Some(color_range)) => color_range,
+				Ok(None) => continue,
+				Err(error) => {
+					println!("While computing colour range: {}", error);
+					continue;
+				}
+			};
+
+			for mut tracker in tracker_query.iter_mut().filter(|tracker| tracker.get_name() == tracker_name) {
+				println!("Colour range of {}: {}", tracker_name, color_range);
+				tracker.set_color_range(color_range.clone());
+			}
+			match color_range.save(tracker_name) {
+				Ok(path) => println!("Saved into {}", path.display()),
+				Err(error) => println!("Unable to save colour range: {}", error)
+			}
+		}
+	}
+}
@@ -0,0 +1,322 @@
+use opencv::{
+	prelude::*,
+	core
+};
+
+/// Apparent radius of the ball at known distance.
+#[derive(Clone, Copy, Debug)]
+pub struct DistanceSample{
+	/// in pixels
+	pub radius: f64,
+	/// in metres
+	pub distance: f64
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DistanceModelKind{
+	Inverse,
+	Polynomial,
+	LookupTable
+}
+
+impl DistanceModelKind {
+	pub fn name(&self) -> &'static str{
+		match self {
+			DistanceModelKind::Inverse => "inverse",
+			DistanceModelKind::Polynomial => "polynomial",
+			DistanceModelKind::LookupTable => "table",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<Self>{
+		match name {
+			"inverse" => Some(DistanceModelKind::Inverse),
+			"polynomial" => Some(DistanceModelKind::Polynomial),
+			"table" => Some(DistanceModelKind::LookupTable),
+			_ => None
+		}
+	}
+}
+
+/// Maps apparent radius of the ball in pixels to its distance in metres.
+///
+/// Outside of the radius range covered by samples, Polynomial and LookupTable continue
+///  as `distance ~ 1 / radius` from the closest sampled end, so they don't diverge.
+#[derive(Clone, Debug)]
+pub enum DistanceModel{
+	/// distance = coefficient / radius, ideal pinhole camera (coefficient is real radius * focal length)
+	Inverse{coefficient: f64},
+	/// distance = sum of coefficients[k] * (1 / radius)^k, bends for blooming and close balls
+	Polynomial{coefficients: Vec<f64>, min_radius: f64, max_radius: f64},
+	/// piecewise linear in 1 / radius between (radius, distance) samples sorted by radius
+	LookupTable{samples: Vec<DistanceSample>}
+}
+
+/// Result of fitting a model to samples.
+#[derive(Clone, Debug)]
+pub struct DistanceFit{
+	pub model: DistanceModel,
+	/// measured - modelled distance of each sample, in metres
+	/// None when the model has as many parameters as there are samples, it passes through all of them and residuals say nothing
+	pub residuals: Option<Vec<f64>>,
+	/// samples needed for residuals
+	pub samples_for_residuals: usize
+}
+
+impl DistanceFit {
+	pub fn rms(&self) -> Option<f64>{
+		let residuals = self.residuals.as_ref()?;
+		Some((residuals.iter().map(|residual| residual * residual).sum::<f64>() / residuals.len() as f64).sqrt())
+	}
+
+	pub fn max(&self) -> Option<f64>{
+		let residuals = self.residuals.as_ref()?;
+		Some(residuals.iter().fold(0., |max, residual| residual.abs().max(max)))
+	}
+}
+
+impl DistanceModel {
+	pub const POLYNOMIAL_DEGREE: usize = 2;
+	/// in pixels, samples closer in radius can't be told apart
+	pub const MIN_RADIUS_DIFFERENCE: f64 = 0.01;
+	/// points checked between neighbouring samples when testing that distance decreases with radius
+	const MONOTONIC_STEPS: usize = 16;
+
+	pub fn get_kind(&self) -> DistanceModelKind{
+		match self {
+			DistanceModel::Inverse{..} => DistanceModelKind::Inverse,
+			DistanceModel::Polynomial{..} => DistanceModelKind::Polynomial,
+			DistanceModel::LookupTable{..} => DistanceModelKind::LookupTable,
+		}
+	}
+
+	pub fn distance(&self, radius: f64) -> f64{
+		match self {
+			DistanceModel::Inverse{coefficient} => coefficient / radius,
+			DistanceModel::Polynomial{coefficients, min_radius, max_radius} => {
+				let clamped = radius.clamp(*min_radius, *max_radius);
+				let inverse = 1. / clamped;
+				let distance = coefficients.iter().rev().fold(0., |sum, coefficient| sum * inverse + coefficient);
+				distance * clamped / radius
+			},
+			DistanceModel::LookupTable{samples} => Self::interpolate(samples, radius)
+		}
+	}
+
+	/// Fits model of `kind` to samples, at least 2 samples with different radius are needed.
+	/// Fails when two samples have the same radius or when distance of the fitted model doesn't decrease
+	///  with radius over the sampled range (e.g. polynomial bent by a bad sample).
+	pub fn fit(kind: DistanceModelKind, samples: &[DistanceSample]) -> opencv::Result<DistanceFit>{
+		if samples.len() < 2 {
+			return Err(opencv::Error::new(core::StsBadArg, "At least 2 samples are needed".to_owned()));
+		}
+		let mut sorted = samples.to_vec();
+		sorted.sort_by(|a, b| a.radius.total_cmp(&b.radius));
+		if let Some(pair) = sorted.windows(2).find(|pair| pair[1].radius - pair[0].radius < Self::MIN_RADIUS_DIFFERENCE) {
+			return Err(opencv::Error::new(core::StsBadArg, format!(
+				"Samples at {:.3} m and {:.3} m have the same radius {:.2} px", pair[0].distance, pair[1].distance, pair[0].radius
+			)));
+		}
+
+		let (model, parameters) = match kind {
+			DistanceModelKind::Inverse => (DistanceModel::Inverse{coefficient: Self::inverse_coefficient(samples)}, 1),
+			DistanceModelKind::Polynomial => {
+				let degree = Self::POLYNOMIAL_DEGREE.min(samples.len() - 1);
+				(Self::fit_polynomial(samples, degree)?, degree + 1)
+			},
+			DistanceModelKind::LookupTable => (DistanceModel::LookupTable{samples: sorted.clone()}, samples.len())
+		};
+		if !model.is_decreasing(&sorted) {
+			return Err(opencv::Error::new(core::StsBadArg, format!(
+				"Distance of {} model doesn't decrease with radius between {:.2} and {:.2} px, check the samples or use inverse model",
+				kind.name(), sorted[0].radius, sorted[sorted.len() - 1].radius
+			)));
+		}
+
+		let residuals = (samples.len() > parameters).then(|| samples.iter()
+			.map(|sample| sample.distance - model.distance(sample.radius))
+			.collect()
+		);
+		Ok(DistanceFit{model: model, residuals: residuals, samples_for_residuals: parameters + 1})
+	}
+
+	/// Distance is positive and strictly decreases with radius over radii of `sorted` samples.
+	fn is_decreasing(&self, sorted: &[DistanceSample]) -> bool{
+		let mut radii = vec![];
+		for pair in sorted.windows(2) {
+			for step in 0..Self::MONOTONIC_STEPS {
+				radii.push(pair[0].radius + (pair[1].radius - pair[0].radius) * step as f64 / Self::MONOTONIC_STEPS as f64);
+			}
+		}
+		radii.extend(sorted.last().map(|sample| sample.radius));
+		let distances: Vec<f64> = radii.iter().map(|radius| self.distance(*radius)).collect();
+		distances.iter().all(|distance| distance.is_finite() && *distance > 0.)
+			&& distances.windows(2).all(|pair| pair[1] < pair[0])
+	}
+
+	/// Least squares fit of coefficient in `distance = coefficient / radius`.
+	pub fn inverse_coefficient(samples: &[DistanceSample]) -> f64{
+		let numerator: f64 = samples.iter().map(|sample| sample.distance / sample.radius).sum();
+		let denominator: f64 = samples.iter().map(|sample| 1. / (sample.radius * sample.radius)).sum();
+		numerator / denominator
+	}
+
+	fn fit_polynomial(samples: &[DistanceSample], degree: usize) -> opencv::Result<DistanceModel>{
+		let powers: Vec<Vec<f64>> = samples.iter()
+			.map(|sample| (0..=degree).map(|power| (1. / sample.radius).powi(power as i32)).collect())
+			.collect();
+		let distances: Vec<Vec<f64>> = samples.iter().map(|sample| vec![sample.distance]).collect();
+
+		let mut solution = core::Mat::default();
+		core::solve(
+			&core::Mat::from_slice_2d(&powers)?,
+			&core::Mat::from_slice_2d(&distances)?,
+			&mut solution,
+			core::DECOMP_SVD
+		)?;
+		let mut coefficients = vec![];
+		for power in 0..=degree {
+			coefficients.push(*solution.at::<f64>(power as i32)?);
+		}
+
+		Ok(DistanceModel::Polynomial{
+			coefficients: coefficients,
+			min_radius: samples.iter().map(|sample| sample.radius).fold(f64::INFINITY, f64::min),
+			max_radius: samples.iter().map(|sample| sample.radius).fold(0., f64::max)
+		})
+	}
+
+	fn interpolate(samples: &[DistanceSample], radius: f64) -> f64{
+		let (first, last) = match (samples.first(), samples.last()) {
+			(Some(first), Some(last)) => (first, last),
+			_ => return f64::NAN
+		};
+		if radius <= first.radius {
+			return first.distance * first.radius / radius;
+		}
+		if radius >= last.radius {
+			return last.distance * last.radius / radius;
+		}
+		for segment in samples.windows(2) {
+			let (lower, upper) = (&segment[0], &segment[1]);
+			if radius <= upper.radius && upper.radius > lower.radius {
+				let t = (1. / radius - 1. / lower.radius) / (1. / upper.radius - 1. / lower.radius);
+				return lower.distance + t * (upper.distance - lower.distance);
+			}
+		}
+		last.distance * last.radius / radius
+	}
+
+	pub fn write(&self, storage: &mut core::FileStorage) -> opencv::Result<()>{
+		storage.write_str("distance_model", self.get_kind().name())?;
+		match self {
+			DistanceModel::Inverse{coefficient} => {
+				storage.write_f64("coefficient", *coefficient)?;
+			},
+			DistanceModel::Polynomial{coefficients, min_radius, max_radius} => {
+				storage.write_mat("coefficients", &core::Mat::from_slice(coefficients)?)?;
+				storage.write_f64("min_radius", *min_radius)?;
+				storage.write_f64("max_radius", *max_radius)?;
+			},
+			DistanceModel::LookupTable{samples} => {
+				let radii: Vec<f64> = samples.iter().map(|sample| sample.radius).collect();
+				let distances: Vec<f64> = samples.iter().map(|sample| sample.distance).collect();
+				storage.write_mat("table_radius", &core::Mat::from_slice(&radii)?)?;
+				storage.write_mat("table_distance", &core::Mat::from_slice(&distances)?)?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Reads model written by write(), None when storage holds no model.
+	pub fn read(storage: &core::FileStorage) -> opencv::Result<Option<DistanceModel>>{
+		let node = storage.get("distance_model")?;
+		if node.empty()? {
+			return Ok(None);
+		}
+		let kind = match DistanceModelKind::from_name(&node.to_string()?) {
+			Some(kind) => kind,
+			None => return Ok(None)
+		};
+		let model = match kind {
+			DistanceModelKind::Inverse => DistanceModel::Inverse{coefficient: storage.get("coefficient")?.real()?},
+			DistanceModelKind::Polynomial => DistanceModel::Polynomial{
+				coefficients: Self::read_values(&storage.get("coefficients")?.mat()?)?,
+				min_radius: storage.get("min_radius")?.real()?,
+				max_radius: storage.get("max_radius")?.real()?
+			},
+			DistanceModelKind::LookupTable => {
+				let radii = Self::read_values(&storage.get("table_radius")?.mat()?)?;
+				let distances = Self::read_values(&storage.get("table_distance")?.mat()?)?;
+				DistanceModel::LookupTable{samples: radii.into_iter()
+					.zip(distances.into_iter())
+					.map(|(radius, distance)| DistanceSample{radius: radius, distance: distance})
+					.collect()
+				}
+			}
+		};
+		Ok(Some(model))
+	}
+
+	fn read_values(mat: &core::Mat) -> opencv::Result<Vec<f64>>{
+		let mut values = vec![];
+		for index in 0..mat.total() as i32 {
+			values.push(*mat.at::<f64>(index)?);
+		}
+		Ok(values)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn samples(pairs: &[(f64, f64)]) -> Vec<DistanceSample>{
+		pairs.iter().map(|(radius, distance)| DistanceSample{radius: *radius, distance: *distance}).collect()
+	}
+
+	#[test]
+	fn inverse_model_fits_pinhole_samples(){
+		let fit = DistanceModel::fit(DistanceModelKind::Inverse, &samples(&[(10., 1.), (20., 0.5), (40., 0.25)])).unwrap();
+		assert!((fit.model.distance(25.) - 0.4).abs() < 1e-9);
+		assert!(fit.max().unwrap() < 1e-9);
+	}
+
+	#[test]
+	fn residuals_need_more_samples_than_parameters(){
+		let exact = DistanceModel::fit(DistanceModelKind::Polynomial, &samples(&[(10., 1.), (20., 0.52), (40., 0.25)])).unwrap();
+		assert!(exact.residuals.is_none());
+		assert_eq!(exact.samples_for_residuals, 4);
+
+		let fit = DistanceModel::fit(DistanceModelKind::Polynomial, &samples(&[(10., 1.), (20., 0.52), (30., 0.33), (40., 0.25)])).unwrap();
+		assert_eq!(fit.residuals.as_ref().map(Vec::len), Some(4));
+		assert!(fit.rms().unwrap() > 0.);
+
+		let table = DistanceModel::fit(DistanceModelKind::LookupTable, &samples(&[(10., 1.), (20., 0.52), (30., 0.33), (40., 0.25)])).unwrap();
+		assert!(table.residuals.is_none());
+	}
+
+	#[test]
+	fn rejects_samples_with_same_radius(){
+		for kind in [DistanceModelKind::Inverse, DistanceModelKind::Polynomial, DistanceModelKind::LookupTable] {
+			assert!(DistanceModel::fit(kind, &samples(&[(10., 1.), (20., 0.5), (20., 0.6)])).is_err());
+		}
+		assert!(DistanceModel::fit(DistanceModelKind::Inverse, &samples(&[(10., 1.)])).is_err());
+	}
+
+	#[test]
+	fn rejects_models_not_decreasing_with_radius(){
+		// ball at 20 px is reported further than at 10 px
+		let bad = samples(&[(10., 1.), (20., 1.2), (40., 0.5)]);
+		assert!(DistanceModel::fit(DistanceModelKind::Polynomial, &bad).is_err());
+		assert!(DistanceModel::fit(DistanceModelKind::LookupTable, &bad).is_err());
+	}
+
+	#[test]
+	fn models_continue_as_inverse_outside_samples(){
+		let fit = DistanceModel::fit(DistanceModelKind::LookupTable, &samples(&[(10., 1.), (20., 0.5)])).unwrap();
+		assert!((fit.model.distance(5.) - 2.).abs() < 1e-9);
+		assert!((fit.model.distance(40.) - 0.25).abs() < 1e-9);
+		assert!((fit.model.distance(15.) - 2. / 3.).abs() < 1e-9);
+	}
+}
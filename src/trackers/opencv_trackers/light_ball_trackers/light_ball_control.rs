@@ -0,0 +1,322 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use bevy::ecs::prelude as ecs;
+
+use crate::entity_spawner::EntitySpawner;
+use crate::trackers::opencv_trackers::opencv_utilities::frame_component;
+use crate::trackers::opencv_trackers::light_ball_trackers::light_ball_processing::LightBallTrackerProcessing;
+use crate::trackers::opencv_trackers::light_ball_trackers::light_ball_definitions::LightBallDefinition;
+use crate::trackers::opencv_trackers::light_ball_trackers::light_ball_tracker::LightBallTracker;
+
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Spawns light ball tracker while the app runs, ignored when tracker with the same name exists.
+pub struct SpawnLightBall(pub LightBallDefinition);
+
+/// Despawns light ball tracker with given name together with its preview window, mesh and light.
+pub struct DespawnLightBall(pub String);
+
+struct RemoteRequest{
+	line: String,
+	reply: mpsc::Sender<String>
+}
+
+/// Line based control channel on TCP address from `RTRACK_CONTROL_ADDRESS`, e.g. `127.0.0.1:7878`.
+/// Channel has no authentication, so only loopback addresses are accepted unless `RTRACK_CONTROL_ALLOW_REMOTE` is set.
+///
+/// Commands:
+/// `list` - names of light ball trackers,
+/// `spawn <definition>` - spawns tracker from definition in RON (same as in light_balls.ron),
+/// `despawn <name>` - despawns tracker,
+/// `pipeline <name | camera>` - stages of the mask pipeline of tracker or of the camera preprocessing with their parameters,
+/// `set <name | camera> <stage> <parameter> <value>` - changes parameter of the pipeline stage (of all cameras for `camera`).
+/// Each command is answered by single line, `ok` or `error: <reason>` for spawn, despawn and set.
+#[derive(ecs::Resource)]
+pub struct RemoteControl{
+	receiver: std::sync::Mutex<mpsc::Receiver<RemoteRequest>>
+}
+
+impl RemoteControl {
+	pub const ENV_VARIABLE: &'static str = "RTRACK_CONTROL_ADDRESS";
+	pub const ALLOW_REMOTE_ENV_VARIABLE: &'static str = "RTRACK_CONTROL_ALLOW_REMOTE";
+
+	/// Starts listening when the address is set.
+	pub fn from_env() -> Option<Self>{
+		let address = std::env::var(Self::ENV_VARIABLE).ok()?;
+		let allow_remote = std::env::var_os(Self::ALLOW_REMOTE_ENV_VARIABLE).is_some();
+		match Self::listen(&address, allow_remote) {
+			Ok(remote_control) => {
+				println!("Listening for light ball control on {}", address);
+				Some(remote_control)
+			},
+			Err(error) => {
+				println!("Unable to listen on {}: {}", address, error);
+				None
+			}
+		}
+	}
+
+	/// Listens on `address`, which has to resolve only to loopback addresses unless `allow_remote` is true.
+	pub fn listen(address: &str, allow_remote: bool) -> std::io::Result<Self>{
+		if !allow_remote {
+			if let Some(remote) = address.to_socket_addrs()?.find(|socket_address| !socket_address.ip().is_loopback()) {
+				return Err(std::io::Error::new(
+					std::io::ErrorKind::PermissionDenied,
+					format!("{} is not a loopback address, set {} to allow remote control", remote, Self::ALLOW_REMOTE_ENV_VARIABLE)
+				));
+			}
+		}
+		let listener = TcpListener::bind(address)?;
+		let (sender, receiver) = mpsc::channel();
+		std::thread::spawn(move || {
+			for stream in listener.incoming() {
+				match stream {
+					Ok(stream) => {
+						let sender = sender.clone();
+						std::thread::spawn(move || Self::serve(stream, sender));
+					},
+					Err(error) => println!("While accepting control connection: {}", error)
+				}
+			}
+		});
+		Ok(RemoteControl{receiver: std::sync::Mutex::new(receiver)})
+	}
+
+	fn serve(stream: TcpStream, sender: mpsc::Sender<RemoteRequest>){
+		let mut writer = match stream.try_clone() {
+			Ok(writer) => writer,
+			Err(_) => return
+		};
+		for line in BufReader::new(stream).lines() {
+			let line = match line {
+				Ok(line) => line,
+				Err(_) => return
+			};
+			if line.trim().is_empty() {
+				continue;
+			}
+			let (reply_sender, reply_receiver) = mpsc::channel();
+			// app is gone when the request can't be sent
+			if sender.send(RemoteRequest{line: line, reply: reply_sender}).is_err() {
+				return;
+			}
+			let reply = reply_receiver.recv_timeout(REPLY_TIMEOUT)
+				.unwrap_or_else(|_| "error: no reply".to_owned());
+			if writeln!(writer, "{}", reply).is_err() {
+				return;
+			}
+		}
+	}
+}
+
+pub struct LightBallControl;
+
+impl LightBallControl {
+	/// Target of pipeline commands meaning preprocessing of all cameras.
+	const CAMERA_TARGET: &'static str = "camera";
+
+	/// Turns requests of the remote control channel into spawn and despawn events, pipeline parameters are changed directly.
+	pub fn remote_system(
+		remote_control: Option<ecs::Res<RemoteControl>>,
+		mut tracker_query: ecs::Query<&mut LightBallTracker>,
+		processing_query: ecs::Query<&frame_component::FrameComponent, ecs::With<LightBallTrackerProcessing>>,
+		mut spawn_events: ecs::EventWriter<SpawnLightBall>,
+		mut despawn_events: ecs::EventWriter<DespawnLightBall>
+	){
+		let remote_control = match remote_control {
+			Some(remote_control) => remote_control,
+			None => return
+		};
+		let receiver = remote_control.receiver.lock().unwrap();
+		for request in receiver.try_iter() {
+			let (command, argument) = request.line.trim().split_once(char::is_whitespace)
+				.map(|(command, argument)| (command, argument.trim()))
+				.unwrap_or((request.line.trim(), ""));
+			if matches!(command, "pipeline" | "set") {
+				let _ = request.reply.send(Self::pipeline_command(command, argument, &mut tracker_query, &processing_query));
+				continue;
+			}
+			let exists = |name: &str| tracker_query.iter().any(|tracker| tracker.get_name() == name);
+
+			let reply = match command {
+				"list" => {
+					let names: Vec<&str> = tracker_query.iter().map(|tracker| tracker.get_name()).collect();
+					names.join(" ")
+				},
+				"spawn" => match Self::parse_definition(argument) {
+					Ok(definition) if exists(&definition.name) => format!("error: tracker '{}' already exists", definition.name),
+					Ok(definition) => {
+						spawn_events.send(SpawnLightBall(definition));
+						"ok".to_owned()
+					},
+					Err(error) => format!("error: {}", error)
+				},
+				"despawn" if exists(argument) => {
+					despawn_events.send(DespawnLightBall(argument.to_owned()));
+					"ok".to_owned()
+				},
+				"despawn" => format!("error: unknown tracker '{}'", argument),
+				_ => format!("error: unknown command '{}', use list, spawn, despawn, pipeline or set", command)
+			};
+			let _ = request.reply.send(reply);
+		}
+	}
+
+	/// Definition in RON which passes the same checks as definitions in light_balls.ron.
+	fn parse_definition(argument: &str) -> Result<LightBallDefinition, String>{
+		let definition = ron::from_str::<LightBallDefinition>(argument)
+			.map_err(|error| format!("invalid definition: {}", error))?;
+		definition.validate()?;
+		Ok(definition)
+	}
+
+	fn pipeline_command(
+		command: &str,
+		argument: &str,
+		tracker_query: &mut ecs::Query<&mut LightBallTracker>,
+		processing_query: &ecs::Query<&frame_component::FrameComponent, ecs::With<LightBallTrackerProcessing>>
+	) -> String{
+		let arguments: Vec<&str> = argument.split_whitespace().collect();
+		let target = arguments.first().copied().unwrap_or_default();
+
+		if command == "pipeline" {
+			if target == Self::CAMERA_TARGET {
+				return processing_query.iter().next()
+					.map(|frame_component| frame_component.get_pipeline().lock().unwrap().to_string())
+					.unwrap_or_else(|| "error: there is no camera".to_owned());
+			}
+			return tracker_query.iter().find(|tracker| tracker.get_name() == target)
+				.map(|tracker| tracker.get_mask_pipeline().lock().unwrap().to_string())
+				.unwrap_or_else(|| format!("error: unknown tracker '{}'", target));
+		}
+
+		let (stage, parameter, value) = match arguments[..] {
+			[_, stage, parameter, value] => match value.parse::<f64>() {
+				Ok(value) => (stage, parameter, value),
+				Err(_) => return format!("error: invalid value '{}'", value)
+			},
+			_ => return "error: usage is set <name | camera> <stage> <parameter> <value>".to_owned()
+		};
+		let result = if target == Self::CAMERA_TARGET {
+			processing_query.iter()
+				.map(|frame_component| frame_component.get_pipeline().lock().unwrap().set_parameter(stage, parameter, value))
+				.collect::<Result<Vec<_>, String>>()
+				.map(|_| ())
+		}
+		else {
+			match tracker_query.iter_mut().find(|tracker| tracker.get_name() == target) {
+				Some(mut tracker) => tracker.set_pipeline_parameter(stage, parameter, value),
+				None => Err(format!("unknown tracker '{}'", target))
+			}
+		};
+		match result {
+			Ok(_) => "ok".to_owned(),
+			Err(error) => format!("error: {}", error)
+		}
+	}
+
+	pub fn event_system(
+		mut commands: ecs::Commands,
+		mut spawn_events: ecs::EventReader<SpawnLightBall>,
+		mut despawn_events: ecs::EventReader<DespawnLightBall>,
+		tracker_query: ecs::Query<(ecs::Entity, &LightBallTracker)>
+	){
+		let mut names: Vec<String> = tracker_query.iter().map(|(_, tracker)| tracker.get_name().to_owned()).collect();
+
+		for DespawnLightBall(name) in despawn_events.iter() {
+			match tracker_query.iter().find(|(_, tracker)| tracker.get_name() == name) {
+				Some((entity, _)) => {
+					// window, mesh and light are components of the tracker entity, all of them go with it
+					commands.entity(entity).despawn();
+					names.retain(|other| other != name);
+					println!("Light ball {} despawned", name);
+				},
+				None => println!("Unable to despawn light ball {}, there is no such tracker", name)
+			}
+		}
+
+		for SpawnLightBall(definition) in spawn_events.iter() {
+			if names.contains(&definition.name) {
+				println!("Unable to spawn light ball {}, tracker with the same name exists", definition.name);
+				continue;
+			}
+			if let Err(error) = definition.validate() {
+				println!("Unable to spawn light ball {}: {}", definition.name, error);
+				continue;
+			}
+			definition.spawn(&mut commands);
+			names.push(definition.name.clone());
+			println!("Light ball {} spawned", definition.name);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use bevy::ecs::system::SystemState;
+
+	use super::*;
+	use crate::trackers::opencv_trackers::light_ball_trackers::light_ball_tracker::ColorRangeHSV;
+
+	type PipelineQueries<'w, 's> = (
+		ecs::Query<'w, 's, &'static mut LightBallTracker>,
+		ecs::Query<'w, 's, &'static frame_component::FrameComponent, ecs::With<LightBallTrackerProcessing>>
+	);
+
+	fn run(world: &mut ecs::World, line: &str) -> String{
+		let mut state = SystemState::<PipelineQueries>::new(world);
+		let (mut tracker_query, processing_query) = state.get_mut(world);
+		let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+		LightBallControl::pipeline_command(command, argument, &mut tracker_query, &processing_query)
+	}
+
+	fn world_with_tracker() -> ecs::World{
+		let definition: LightBallDefinition = ron::from_str(
+			"(name: \"blue\", color: Range(lower: (90., 100., 245.), upper: (130., 255., 255.)), ball_diameter: 0.08)"
+		).unwrap();
+		let mut world = ecs::World::new();
+		world.spawn(LightBallTracker::from_definition(&definition, definition.color.to_color_range()));
+		world
+	}
+
+	#[test]
+	fn pipeline_lists_stages_of_tracker(){
+		let mut world = world_with_tracker();
+		let reply = run(&mut world, "pipeline blue");
+		assert!(reply.starts_with("color_threshold(hue_lower=90"), "{}", reply);
+		assert!(reply.contains("morphology(erode=2, dilate=2)"), "{}", reply);
+		assert_eq!(run(&mut world, "pipeline green"), "error: unknown tracker 'green'");
+		assert_eq!(run(&mut world, "pipeline camera"), "error: there is no camera");
+	}
+
+	#[test]
+	fn set_changes_parameter_of_tracker(){
+		let mut world = world_with_tracker();
+		assert_eq!(run(&mut world, "set blue morphology erode 1"), "ok");
+		assert_eq!(run(&mut world, "set blue color_threshold hue_upper 140"), "ok");
+		assert!(run(&mut world, "pipeline blue").contains("morphology(erode=1, dilate=2)"));
+		let mut tracker_query = world.query::<&LightBallTracker>();
+		let tracker = tracker_query.single(&world);
+		assert!(*tracker.get_color_range() == ColorRangeHSV::new([90., 100., 245.], [140., 255., 255.]));
+	}
+
+	#[test]
+	fn set_rejects_malformed_commands(){
+		let mut world = world_with_tracker();
+		assert_eq!(run(&mut world, "set blue morphology erode many"), "error: invalid value 'many'");
+		assert!(run(&mut world, "set blue morphology").starts_with("error: usage is set"));
+		assert!(run(&mut world, "set blue morphology erode -1").starts_with("error: "));
+		assert!(run(&mut world, "set blue blur kernel_size 3").starts_with("error: "));
+		assert_eq!(run(&mut world, "set green morphology erode 1"), "error: unknown tracker 'green'");
+	}
+
+	#[test]
+	fn spawn_definition_is_validated(){
+		assert!(LightBallControl::parse_definition("(name: \"green\", color: Hue(centre: 60., tolerance: 10., saturation: (100., 255.), value: (200., 255.)), ball_diameter: 0.08)").is_ok());
+		assert!(LightBallControl::parse_definition("(name: \"green\", ball_diameter: 0.08)").unwrap_err().starts_with("invalid definition"));
+		assert!(LightBallControl::parse_definition("(name: \"green\", color: Hue(centre: 60., tolerance: 10., saturation: (100., 255.), value: (200., 255.)), ball_diameter: 0.)").is_err());
+	}
+}
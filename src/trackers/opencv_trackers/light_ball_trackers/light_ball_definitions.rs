@@ -0,0 +1,236 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::trackers::tracker::{Position, TrackerRole};
+use crate::trackers::opencv_trackers::tracker_definitions::DefinitionsFile;
+use crate::trackers::opencv_trackers::light_ball_trackers::light_ball_tracker::ColorRangeHSV;
+use crate::trackers::opencv_trackers::light_ball_trackers::blob_quality::BlobFilter;
+use crate::trackers::opencv_trackers::light_ball_trackers::radius_estimation::RadiusEstimator;
+
+/// Colour of the ball, either explicit HSV bounds (hue may wrap around 0) or hue centre with tolerance.
+/// Hue is in OpenCV units (0-180), saturation and value 0-255.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum ColorDefinition{
+	Range{lower: [f64; 3], upper: [f64; 3]},
+	Hue{centre: f64, tolerance: f64, saturation: (f64, f64), value: (f64, f64)}
+}
+
+impl ColorDefinition {
+	pub fn to_color_range(&self) -> ColorRangeHSV{
+		match self {
+			ColorDefinition::Range{lower, upper} => ColorRangeHSV::new(*lower, *upper),
+			ColorDefinition::Hue{centre, tolerance, saturation, value} => ColorRangeHSV::from_hue(*centre, *tolerance, *saturation, *value)
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Smoothing{
+	/// weight of the newest position in exponential smoothing, 1 disables smoothing
+	pub position: f64
+}
+
+impl Default for Smoothing {
+	fn default() -> Self {
+		Smoothing{position: 1.}
+	}
+}
+
+impl Smoothing {
+	/// Exponential smoothing of `position`, `smoothed` holds the previous result.
+	pub fn smooth(&self, smoothed: &mut Option<[f64; 3]>, position: Position) -> Position{
+		let weight = self.position;
+		let next = match *smoothed {
+			Some(previous) => [
+				previous[0] + weight * (position.x - previous[0]),
+				previous[1] + weight * (position.y - previous[1]),
+				previous[2] + weight * (position.z - previous[2])
+			],
+			None => [position.x, position.y, position.z]
+		};
+		*smoothed = Some(next);
+		Position{x: next[0], y: next[1], z: next[2]}
+	}
+}
+
+/// Single light ball tracker, spawned with `EntitySpawner`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LightBallDefinition{
+	pub name: String,
+	#[serde(default)]
+	pub role: TrackerRole,
+	pub color: ColorDefinition,
+	/// in metres
+	pub ball_diameter: f64,
+	#[serde(default)]
+	pub smoothing: Smoothing,
+	/// search only around the predicted blob position, falls back to the whole frame after a miss
+	#[serde(default = "enabled")]
+	pub roi_search: bool,
+	/// shape limits of blobs accepted as the ball
+	#[serde(default)]
+	pub blob_filter: BlobFilter,
+	/// how blob centre and radius are estimated, ellipse or RANSAC fits cope with motion blur and partly covered balls
+	#[serde(default)]
+	pub radius_estimator: RadiusEstimator
+}
+
+fn enabled() -> bool{
+	true
+}
+
+impl LightBallDefinition {
+	/// Colour range calibrated in the preview when it was saved, otherwise the defined one.
+	pub fn get_color_range(&self) -> ColorRangeHSV{
+		ColorRangeHSV::load(&self.name).unwrap_or_else(|_| self.color.to_color_range())
+	}
+
+	/// Checks values which can't be rejected by deserialisation.
+	pub fn validate(&self) -> Result<(), String>{
+		if self.name.is_empty() {
+			return Err("Tracker has no name".to_owned());
+		}
+		if !(self.ball_diameter > 0.) {
+			return Err(format!("Tracker '{}' needs positive ball_diameter", self.name));
+		}
+		if !(self.smoothing.position > 0. && self.smoothing.position <= 1.) {
+			return Err(format!("Smoothing of tracker '{}' must be in (0, 1]", self.name));
+		}
+		Ok(())
+	}
+}
+
+/// Set of light ball trackers, read from `config/light_balls.ron` or file in `RTRACK_LIGHT_BALLS`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LightBallDefinitions{
+	pub trackers: Vec<LightBallDefinition>
+}
+
+impl Default for LightBallDefinitions {
+	fn default() -> Self {
+		LightBallDefinitions{trackers: vec![
+			LightBallDefinition{
+				name: "red".to_owned(),
+				role: TrackerRole::Generic,
+				// hue wraps around 0
+				color: ColorDefinition::Range{lower: [168., 100., 245.], upper: [12., 255., 255.]},
+				ball_diameter: 0.08,
+				smoothing: Smoothing::default(),
+				roi_search: true,
+				blob_filter: BlobFilter::default(),
+				radius_estimator: RadiusEstimator::default()
+			},
+			LightBallDefinition{
+				name: "blue".to_owned(),
+				role: TrackerRole::Generic,
+				color: ColorDefinition::Range{lower: [90., 100., 245.], upper: [255., 255., 255.]},
+				ball_diameter: 0.08,
+				smoothing: Smoothing::default(),
+				roi_search: true,
+				blob_filter: BlobFilter::default(),
+				radius_estimator: RadiusEstimator::default()
+			}
+		]}
+	}
+}
+
+impl DefinitionsFile for LightBallDefinitions {
+	const FILE_NAME: &'static str = "light_balls.ron";
+	const ENV_VARIABLE: &'static str = "RTRACK_LIGHT_BALLS";
+	const FALLBACK: &'static str = "using built-in light balls";
+
+	fn validate(&self, path: &Path) -> Result<(), String>{
+		for (index, definition) in self.trackers.iter().enumerate() {
+			definition.validate()
+				.map_err(|error| format!("{} (tracker {} in {})", error, index, path.display()))?;
+			if self.trackers[..index].iter().any(|other| other.name == definition.name) {
+				return Err(format!("Tracker '{}' is defined twice in {}", definition.name, path.display()));
+			}
+		}
+		Ok(())
+	}
+}
+
+impl LightBallDefinitions {
+	pub fn find(&self, name: &str) -> Option<&LightBallDefinition>{
+		self.trackers.iter().find(|definition| definition.name == name)
+	}
+
+	pub fn get_names(&self) -> Vec<&str>{
+		self.trackers.iter().map(|definition| definition.name.as_str()).collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn definition(name: &str) -> LightBallDefinition{
+		ron::from_str(&format!("(name: \"{}\", color: Range(lower: (90., 100., 245.), upper: (130., 255., 255.)), ball_diameter: 0.08)", name)).unwrap()
+	}
+
+	fn validate(trackers: Vec<LightBallDefinition>) -> Result<(), String>{
+		LightBallDefinitions{trackers}.validate(Path::new("light_balls.ron"))
+	}
+
+	#[test]
+	fn minimal_definition_uses_defaults(){
+		let definition = definition("blue");
+		assert_eq!(definition.name, "blue");
+		assert_eq!(definition.role, TrackerRole::Generic);
+		assert_eq!(definition.smoothing.position, 1.);
+		assert!(definition.roi_search);
+		assert_eq!(definition.ball_diameter, 0.08);
+	}
+
+	#[test]
+	fn default_definitions_are_valid(){
+		assert!(validate(LightBallDefinitions::default().trackers).is_ok());
+	}
+
+	#[test]
+	fn duplicate_names_are_rejected(){
+		assert!(validate(vec![definition("blue"), definition("red")]).is_ok());
+		assert!(validate(vec![definition("blue"), definition("blue")]).is_err());
+	}
+
+	#[test]
+	fn non_positive_ball_diameter_is_rejected(){
+		for ball_diameter in [0., -0.08, f64::NAN] {
+			let mut tracker = definition("blue");
+			tracker.ball_diameter = ball_diameter;
+			assert!(validate(vec![tracker]).is_err());
+		}
+	}
+
+	#[test]
+	fn smoothing_outside_unit_interval_is_rejected(){
+		for (position, valid) in [(0., false), (-0.5, false), (1.5, false), (0.3, true), (1., true)] {
+			let mut tracker = definition("blue");
+			tracker.smoothing.position = position;
+			assert_eq!(validate(vec![tracker]).is_ok(), valid);
+		}
+	}
+
+	#[test]
+	fn single_definition_is_validated(){
+		assert!(definition("blue").validate().is_ok());
+		let mut tracker = definition("");
+		assert!(tracker.validate().is_err());
+		tracker.name = "blue".to_owned();
+		tracker.ball_diameter = -1.;
+		assert!(tracker.validate().is_err());
+	}
+
+	#[test]
+	fn color_definitions_map_to_ranges(){
+		let range = ColorDefinition::Range{lower: [168., 100., 245.], upper: [12., 255., 255.]};
+		assert!(range.to_color_range() == ColorRangeHSV::new([168., 100., 245.], [12., 255., 255.]));
+		let hue = ColorDefinition::Hue{centre: 5., tolerance: 10., saturation: (100., 255.), value: (200., 255.)};
+		let color_range = hue.to_color_range();
+		assert!(color_range == ColorRangeHSV::new([175., 100., 200.], [15., 255., 255.]));
+		assert!(color_range.is_hue_wrapped());
+	}
+}
@@ -1,100 +1,36 @@
 use bevy::ecs::prelude as ecs;
-use bevy::ecs::query::With;
-
-
-
-use opencv::prelude as cv;
-
-
-use crate::trackers::opencv_trackers::opencv_utilities;
-
-use crate::entity_spawner::*;
-use crate::trackers::opencv_trackers::camera_observer::*;
-
 
 use crate::trackers::opencv_trackers::OpencvTrackers;
-
-use opencv_utilities::{
-	frame_component,
-	window_preview
-};
-
-use std::any::type_name;
+use crate::trackers::opencv_trackers::camera_processing::{self, CameraProcessing};
+use crate::trackers::opencv_trackers::light_ball_trackers::color_calibration;
+use crate::trackers::opencv_trackers::opencv_utilities::processing_pipeline;
 
 // ------- Light Ball Tracker Processing ------- //
 pub struct LightBallTrackerProcessingBuilder;
-impl EntitySpawner for LightBallTrackerProcessingBuilder{
-	fn spawn(&self, commands: &mut ecs::Commands) -> ecs::Entity{
-		let mut window_component = window_preview::WindowPreviewComponent::default();
-		window_component.processing_function = Some(LightBallTrackerProcessing::undo_preprocess_frame_color);
-		window_component.window.set_title(type_name::<LightBallTrackerProcessing>());
-
-		commands.spawn( 
-			(
-				LightBallTrackerProcessing::default(),
-				CameraObserverSubscriberComponent,
-				frame_component::FrameComponent::new(LightBallTrackerProcessing::preprocess_frame),
-
-				// debug components
-				window_component,
-				window_preview::WindowInLayout
-			)
-		).id()
-	}
-}
 
 impl bevy::app::Plugin for LightBallTrackerProcessingBuilder{
 	fn build(&self, app: &mut bevy::prelude::App) {
+		app.init_resource::<color_calibration::ColorCalibration>();
 		OpencvTrackers::init_schedule(app)
-			.add_system(LightBallTrackerProcessing::observer_subscribe_system);
+			.add_system(camera_processing::subscribe_system::<LightBallTrackerProcessing>)
+			.add_system(color_calibration::ColorCalibration::selection_system);
 	}
 }
 
 #[derive(ecs::Component, Default)]
 pub struct LightBallTrackerProcessing;
-impl LightBallTrackerProcessing {
 
-	fn observer_subscribe_system(
-		mut commands: ecs::Commands,
-		camera_observers: Option<ecs::ResMut<CameraObservers>>,
-		query: ecs::Query<(ecs::Entity, &frame_component::FrameComponent), With<LightBallTrackerProcessing>>
-	){
-		if let Some(mut observers) =  camera_observers {
-			for camera_observer in  &mut observers.list {
-				let mut any_subscribed = false;
-				for (entity, _) in query.iter() {
-					if camera_observer.is_subscribed(&entity){
-						any_subscribed = true;
-					}
-				}
-				if any_subscribed == false {
-					// add new subscribed frame component
-					let new_entity = LightBallTrackerProcessingBuilder{}.spawn(&mut commands);
-					camera_observer.subscribe(new_entity);
-				}
-			}
-		}
+impl CameraProcessing for LightBallTrackerProcessing {
+	/// Blur against noise and conversion to HSV, where colour ranges of balls are defined.
+	fn preprocess_pipeline() -> processing_pipeline::ProcessingPipeline{
+		processing_pipeline::ProcessingPipeline::new()
+			.with(processing_pipeline::GaussianBlur{kernel_size: 11})
+			.with(processing_pipeline::ConvertColor{code: opencv::imgproc::COLOR_BGR2HSV})
 	}
 
-	pub fn preprocess_frame(dest: &mut cv::Mat, src: &cv::Mat) -> opencv::Result<()>{		
-		let mut blured = cv::Mat::default();
-
-		//double sigmaX, double sigmaY = (0.0), int borderType = 4
-		opencv::imgproc::gaussian_blur(
-			src,
-			&mut blured,
-			opencv::core::Size{width: 11, height: 11},
-			0.0, 0.0,
-			4
-		)?;		
-
-		opencv::imgproc::cvt_color(&blured, dest, opencv::imgproc::COLOR_BGR2HSV, 0)?;
-		Ok(())
-		
+	/// Converts preprocessed HSV frames back to BGR for displaying.
+	fn preview_pipeline() -> processing_pipeline::ProcessingPipeline{
+		processing_pipeline::ProcessingPipeline::new()
+			.with(processing_pipeline::ConvertColor{code: opencv::imgproc::COLOR_HSV2BGR})
 	}
-	pub fn undo_preprocess_frame_color(dest: &mut cv::Mat, src: &cv::Mat) -> opencv::Result<()>{
-		opencv::imgproc::cvt_color(&src, dest, opencv::imgproc::COLOR_HSV2BGR, 0)?;
-		Ok(())
-	}
-
 }
@@ -1,7 +1,11 @@
 use bevy::ecs::prelude as ecs;
+use bevy::ecs::prelude::IntoSystemConfig;
 
 use opencv::prelude::MatTraitConstManual;
 use opencv::prelude::MatTraitConst;
+use opencv::prelude::MatTrait;
+use opencv::prelude::MatExprTraitConst;
+use opencv::prelude::{FileStorageTrait, FileStorageTraitConst, FileNodeTraitConst};
 use opencv::prelude as cv;
 use crate::trackers::opencv_trackers::light_ball_trackers::light_ball_tracker::window_preview::WindowPreviewComponent;
 
@@ -14,105 +18,111 @@ use crate::entity_spawner::*;
 use crate::state::*;
 
 use crate::trackers::opencv_trackers::OpencvTrackers;
+use crate::trackers::opencv_trackers::frame_synchroniser::FrameSynchroniser;
+use crate::trackers::opencv_trackers::triangulation::{Observation, Triangulator};
+use crate::trackers::opencv_trackers::calibration::playspace::Playspace;
+use crate::trackers::opencv_trackers::calibration::undistortion::UndistortionComponent;
+use crate::trackers::opencv_trackers::calibration::intrinsics::CameraIntrinsics;
+use crate::trackers::opencv_trackers::calibration;
+use crate::trackers::opencv_trackers::camera_observer::ObservedCamera;
+use crate::trackers::opencv_trackers::camera_processing::CameraProcessing;
 use crate::trackers::opencv_trackers::light_ball_trackers::light_ball_processing;
+use crate::trackers::opencv_trackers::light_ball_trackers::distance_model::DistanceModel;
+use crate::trackers::opencv_trackers::light_ball_trackers::light_ball_definitions::{LightBallDefinition, Smoothing};
+use crate::trackers::opencv_trackers::light_ball_trackers::light_ball_control;
+use crate::trackers::opencv_trackers::light_ball_trackers::blob_assignment::{self, BlobCandidate, BlobTrack, BlobTracks};
+use crate::trackers::opencv_trackers::light_ball_trackers::blob_quality::{BlobFilter, BlobQuality};
+use crate::trackers::opencv_trackers::light_ball_trackers::radius_estimation::RadiusEstimator;
 
 
 use opencv_utilities::{
 	frame_component,
 	window_preview
 };
+use opencv_utilities::processing_pipeline::{ProcessingPipeline, ProcessingStage, Morphology};
 
 use std::any::type_name;
+use std::collections::HashMap;
 // ------- Light Ball Tracker ------- //
 
+/// Spawns light ball trackers from their definitions and registers their systems.
 pub struct LightBallTrackerBuilder{
-	colors: [ColorRangeHSV; 2]
+	definitions: Vec<LightBallDefinition>
 }
 impl LightBallTrackerBuilder {
-	pub const fn default() -> Self{
-		LightBallTrackerBuilder{colors: [
-			// RED
-			ColorRangeHSV{
-				color_lower: opencv::core::VecN{0: [0.0, 2.00, 245., 0.]},
-				color_upper: opencv::core::VecN{0: [76., 100., 252., 0.]},
-			},
-			// BLUE
-			ColorRangeHSV{
-				color_lower: opencv::core::VecN{0: [90., 100., 245., 0.]},
-				color_upper: opencv::core::VecN{0: [255., 255., 255., 0.]},
-			}
-
-
-		]}
+	pub fn new(definitions: Vec<LightBallDefinition>) -> Self{
+		LightBallTrackerBuilder{definitions: definitions}
 	}
 }
 
-impl EntitySpawner for LightBallTrackerBuilder{
+impl EntitySpawner for LightBallDefinition{
 	fn spawn(&self, command: &mut ecs::Commands) -> ecs::Entity{
-		let entity = command.spawn(()).id();
-
 		let mut window_component = window_preview::WindowPreviewComponent::default();
-		window_component.window.set_title(type_name::<LightBallTracker>());
+		window_component.window.set_title(&format!("{} {}", type_name::<LightBallTracker>(), self.name));
 		//window_component.window.set_position(500, 550)
 
-		command.entity(entity)
-			.insert((
-				tracker::TrackerData::default(),
-				LightBallTracker::default(),
-				frame_component::FrameComponent::default(),
-				window_component,
-				window_preview::WindowInLayout
-			));
-		
-		
-		return entity;
-	}
-}
+		let color_range = self.get_color_range();
+		// OpenCV hue is half of degrees
+		let color = bevy::prelude::Color::hsl((color_range.get_hue_centre() * 2.) as f32, 1.0, 0.50);
 
-impl bevy::app::Plugin for LightBallTrackerBuilder{
-	fn build(&self, app: &mut bevy::prelude::App) {
-		let world = &mut app.world;
-		for color in &self.colors {
-			let light_ball = entity_spawner::spawn_from_world(world, self);
-			if let Some(mut tracker) = world.entity_mut(light_ball).get_mut::<LightBallTracker>(){
-				tracker.color_range = color.clone();
-			}
-			
+		let entity = command.spawn((
+			tracker::TrackerData::default(),
+			self.role,
+			LightBallTracker::from_definition(self, color_range),
+			frame_component::FrameComponent::default(),
+			window_component,
+			window_preview::WindowInLayout
+		)).id();
 
-			let mut meshes = world.resource_mut::<bevy::prelude::Assets<bevy::prelude::Mesh>>();		
+		command.add(move |world: &mut ecs::World| {
+			let mut meshes = world.resource_mut::<bevy::prelude::Assets<bevy::prelude::Mesh>>();
 			let mesh = meshes.add(bevy::prelude::Mesh::from(bevy::prelude::shape::Cube { size: 0.25 }));
 
 			let mut materials = world.resource_mut::<bevy::prelude::Assets<bevy::prelude::StandardMaterial>>();
-
-			let mut hue = color.color_lower.get(0).unwrap().clone();
-			let hue_upper = color.color_upper.get(0).unwrap().clone();
-			if (hue * 3.) > hue_upper{
-				hue = hue_upper;
-			}
-
 			let material = bevy::prelude::StandardMaterial {
-				base_color: bevy::prelude::Color::hsl(hue as f32, 1.0, 0.50),
+				base_color: color,
 				unlit: true,
 				..Default::default()
 			};
-			let mut material_handle = materials.add(material);
-			
-			world.entity_mut(light_ball).insert(bevy::prelude::PbrBundle {
-				mesh: mesh,
-				material: material_handle,
-				transform: bevy::prelude::Transform::from_xyz(0.0, 0.5, 0.0),
-				..Default::default()
-			});
+			let material_handle = materials.add(material);
 
-			world.entity_mut(light_ball).insert(
-				bevy::pbr::PointLightBundle{
-					point_light: bevy::pbr::PointLight{
-						color: bevy::prelude::Color::hsl(hue as f32, 1.0, 0.50),
-						..Default::default()
-					},
+			if let Some(mut entity_mut) = world.get_entity_mut(entity) {
+				entity_mut.insert(bevy::prelude::PbrBundle {
+					mesh: mesh,
+					material: material_handle,
+					transform: bevy::prelude::Transform::from_xyz(0.0, 0.5, 0.0),
 					..Default::default()
-				}
-			);
+				});
+				entity_mut.insert(
+					bevy::pbr::PointLightBundle{
+						point_light: bevy::pbr::PointLight{
+							color: color,
+							..Default::default()
+						},
+						..Default::default()
+					}
+				);
+			}
+		});
+
+		return entity;
+	}
+}
+
+impl bevy::app::Plugin for LightBallTrackerBuilder{
+	fn build(&self, app: &mut bevy::prelude::App) {
+		let world = &mut app.world;
+		for definition in &self.definitions {
+			entity_spawner::spawn_from_world(world, definition);
+		}
+
+		// trackers added or removed at runtime
+		app.add_event::<light_ball_control::SpawnLightBall>()
+			.add_event::<light_ball_control::DespawnLightBall>()
+			.add_system(light_ball_control::LightBallControl::remote_system)
+			.add_system(light_ball_control::LightBallControl::event_system.after(light_ball_control::LightBallControl::remote_system));
+		if let Some(remote_control) = light_ball_control::RemoteControl::from_env() {
+			app.insert_resource(remote_control);
 		}
 
 		OpencvTrackers::init_schedule(app)
@@ -126,60 +136,162 @@ type Color3d = opencv::core::Vec3i;
 pub struct LightBallTracker{
 	state: StateWrapper<String, String>,
 	counter: f32,
+	name: String,
 	color_range: ColorRangeHSV,
-	calibration: LightBallCalibration
+	/// in metres
+	real_radius: f64,
+	smoothing: Smoothing,
+	smoothed_position: Option<[f64; 3]>,
+	/// turns preprocessed HSV frame into mask of the ball
+	mask_pipeline: std::sync::Mutex<ProcessingPipeline>,
+	/// search only around the predicted blob position, see `find_blobs`
+	roi_search: bool,
+	/// rejects blobs which don't look like a ball
+	blob_filter: BlobFilter,
+	radius_estimator: RadiusEstimator,
+	/// last blob seen by each camera
+	blob_tracks: BlobTracks,
+	/// size/distance calibration for each camera id, loaded when camera is seen for the first time
+	calibrations: HashMap<String, LightBallCalibration>
 }
 impl LightBallTracker {
+	/// in pixels, smaller blobs are noise left by the mask pipeline
+	const MIN_BLOB_AREA: f64 = 3.;
+
+	pub fn from_definition(definition: &LightBallDefinition, color_range: ColorRangeHSV) -> Self{
+		LightBallTracker{
+			name: definition.name.clone(),
+			real_radius: definition.ball_diameter / 2.,
+			smoothing: definition.smoothing.clone(),
+			roi_search: definition.roi_search,
+			blob_filter: definition.blob_filter.clone(),
+			radius_estimator: definition.radius_estimator,
+			mask_pipeline: std::sync::Mutex::new(Self::mask_pipeline(&color_range)),
+			color_range: color_range,
+			..Default::default()
+		}
+	}
 	
 	fn update_system(
 		mut commands: ecs::Commands,
-		frame_query: ecs::Query<&frame_component::FrameComponent, (ecs::With<light_ball_processing::LightBallTrackerProcessing>, ecs::Changed<frame_component::FrameComponent>)>,
-		mut tracker_query: ecs::Query<(ecs::Entity, &mut LightBallTracker, &mut frame_component::FrameComponent), ecs::Without<light_ball_processing::LightBallTrackerProcessing>>,
-		mut debug_screen_space_view_entity: ecs::Local<Option<ecs::Entity>>
+		synchroniser: ecs::Res<FrameSynchroniser>,
+		triangulator: ecs::Res<Triangulator>,
+		playspace: ecs::Res<Playspace>,
+		processing_query: ecs::Query<(&ObservedCamera, Option<&UndistortionComponent>), ecs::With<light_ball_processing::LightBallTrackerProcessing>>,
+		mut tracker_query: ecs::Query<(ecs::Entity, &mut LightBallTracker, &mut frame_component::FrameComponent, Option<&WindowPreviewComponent>), ecs::Without<light_ball_processing::LightBallTrackerProcessing>>,
+		mut debug_screen_space_view_entity: ecs::Local<Option<ecs::Entity>>,
+		mut last_generation: ecs::Local<u64>
 	){
+		// only frames captured at the same time are processed together
+		let latest_frameset = match synchroniser.lock_new(&mut last_generation) {
+			Some(latest_frameset) => latest_frameset,
+			None => return
+		};
+		let synchronised_frames = latest_frameset.as_ref().map_or(&[][..], |frameset| &frameset.frames[..]);
+
+		// whole frames are already undistorted by processing pipeline of the camera when needed
+		let mut camera_frames = vec![];
+		for synchronised_frame in synchronised_frames {
+			if let Ok((camera, undistortion)) = processing_query.get(synchronised_frame.source) {
+				camera_frames.push((&synchronised_frame.frame, camera, undistortion));
+			}
+		}
 		
 		let mut debug_screen_space_frame: Option<cv::Mat> = None;
-		for (entity, mut tracker, mut frame_mask) in tracker_query.iter_mut() {
-			for frame_component in frame_query.iter() {
-				let frame = frame_component.get_frame().lock().unwrap();
-
-				if let Ok(mask) = Self::make_mask(&frame, &tracker.color_range){
-					
-					if let Ok(screen_space) = Self::compute_screen_space_position(&mask) {
-						if let Some(position) = tracker.compute_position(&screen_space, &mask.size().unwrap()){
-							commands.add(move |world: &mut ecs::World| {
-								let mut entity_mut = world.entity_mut(entity);
-								if let Some(mut transform) = entity_mut.get_mut::<bevy::prelude::Transform>(){
-									*transform = bevy::prelude::Transform::from_xyz(position.x as f32 / 10., position.y as f32 / 10., position.z as f32 / 10.);
-								}
-
-								if let Some(mut data) = entity_mut.get_mut::<tracker::TrackerData>(){
-									data.position = position;
-								}
+		let mut trackers: Vec<_> = tracker_query.iter_mut().collect();
+		let mut observations: Vec<Vec<Observation>> = trackers.iter().map(|_| vec![]).collect();
+		let mut ball_radii = vec![0.; trackers.len()];
+		// quality score of blobs behind the observations, weighted by their visible fraction
+		let mut scores: Vec<Vec<f64>> = trackers.iter().map(|_| vec![]).collect();
+
+		// trackers of the same colour share the mask, their balls are told apart by assignment of blobs
+		let groups = Self::color_groups(&trackers.iter().map(|(_, tracker, _, _)| tracker.get_color_range()).collect::<Vec<_>>());
+		for (frame, camera, undistortion) in camera_frames.iter() {
+			let frame: &cv::Mat = frame;
+			let mask_size = match frame.size() {
+				Ok(size) => size,
+				Err(_) => continue
+			};
+			for group in groups.iter() {
+				// mask is kept only for preview windows, in the buffer of the first previewed member
+				let previewed: Vec<usize> = group.iter().copied().filter(|&index| trackers[index].3.is_some()).collect();
+				let mut preview_mask = previewed.first().map(|&index| std::mem::take(trackers[index].2.get_frame_mut()));
+
+				let mut members: Vec<&mut LightBallTracker> = trackers.iter_mut().enumerate()
+					.filter(|(index, _)| group.contains(index))
+					.map(|(_, (_, tracker, _, _))| &mut **tracker)
+					.collect();
+
+				// searched around the predicted positions when possible
+				let found = Self::find_blobs(&mut members, &camera.camera_id, frame, &triangulator, preview_mask.as_mut());
+				if let Some(mask) = preview_mask {
+					for &index in previewed.iter().skip(1) {
+						mask.copy_to(trackers[index].2.get_frame_mut()).unwrap_or_default();
+					}
+					*trackers[previewed[0]].2.get_frame_mut() = mask;
+				}
+				let blobs = match found {
+					Ok(blobs) => blobs,
+					Err(_) => continue
+				};
+				for (&index, blob) in group.iter().zip(blobs.iter()) {
+					let (_, tracker, _, _) = &mut trackers[index];
+					if let Some(blob) = blob {
+						let screen_space = &blob.circle;
+						let mut undistorted_screen_space = *screen_space;
+						if let Some(component) = undistortion {
+							if let Ok(position) = component.get_undistortion().lock().unwrap().undistort_point(screen_space.position, mask_size) {
+								undistorted_screen_space.position = position;
+							}
+						}
+						let intrinsics = undistortion.map(|component| component.get_undistortion().lock().unwrap().get_intrinsics().clone());
+						let calibration = tracker.get_calibration(&camera.camera_id, &mask_size, intrinsics.as_ref()).clone();
+						ball_radii[index] = calibration.get_real_radius();
+						if let Some(position) = Self::compute_position(&calibration, &undistorted_screen_space, &mask_size, intrinsics.as_ref()){
+							observations[index].push(Observation{
+								camera_id: camera.camera_id.clone(),
+								pixel: undistorted_screen_space.position,
+								frame_size: mask_size,
+								radius_position: position
 							});
+							// partly covered balls are less certain, their radius (and so distance) is estimated from a part of the edge
+							scores[index].push(blob.quality.score * blob.visible_fraction);
 						}
 
 						if let None = debug_screen_space_frame{
 							debug_screen_space_frame = Some(frame.clone())
 						}
-						
-						Self::debug_screen_space(&mut debug_screen_space_frame.as_mut().unwrap(), &screen_space, &tracker.calibration, &tracker.color_range, 0.85)
+
+						Self::debug_screen_space(&mut debug_screen_space_frame.as_mut().unwrap(), screen_space, blob.visible_fraction, &calibration, &tracker.color_range, 0.85)
 							.unwrap_or_default();
 					}
-					
-					
-					// feed mask to frame mask of this 
-					frame_mask.take(mask);
 				}
 			}
 		}
+
+		for (index, (entity, tracker, _, _)) in trackers.iter_mut().enumerate() {
+			let entity = *entity;
+			// views of all cameras are combined into single position
+			let located = triangulator.locate(&observations[index]);
+			tracker.blob_tracks.locate(located.as_ref(), &observations[index], &triangulator, tracker.real_radius);
+			let found = located.map(|tracking_position| {
+				playspace.record(&tracker.name, &tracking_position, ball_radii[index]);
+				tracker::TrackerData{
+					position: tracker.smooth(playspace.to_playspace(&tracking_position)),
+					confidence: scores[index].iter().sum::<f64>() / scores[index].len().max(1) as f64,
+					..Default::default()
+				}
+			});
+			// lost ball keeps its last position, only confidence drops
+			tracker::TrackerData::report(&mut commands, entity, found);
+		}
 		
 		// debug view
 
 		if let Some(debug_view) = *debug_screen_space_view_entity{
 			let debug_frame_component = 
 			frame_component::FrameComponent::new_with_frame(
-				frame_component::FrameComponent::default_processing_function, debug_screen_space_frame.unwrap_or_default()
+				ProcessingPipeline::default(), debug_screen_space_frame.unwrap_or_default()
 			);
 			commands.entity(debug_view).insert(debug_frame_component);
 		}
@@ -192,10 +304,7 @@ impl LightBallTracker {
 			*debug_screen_space_view_entity = Some(commands.spawn(
 				(
 					frame_component::FrameComponent::default(),
-					WindowPreviewComponent{
-						window: screen_space_window,
-						processing_function: Some(light_ball_processing::LightBallTrackerProcessing::undo_preprocess_frame_color)
-					},
+					WindowPreviewComponent::new(screen_space_window, light_ball_processing::LightBallTrackerProcessing::preview_pipeline()),
 					window_preview::WindowInLayout{}
 				)
 			).id());
@@ -204,14 +313,14 @@ impl LightBallTracker {
 	}
 	
 
-	fn debug_screen_space(frame: &mut cv::Mat, screen_space: &EnclosingCircle, calibration: &LightBallCalibration, color_range: &ColorRangeHSV, font_size: f64) -> opencv::Result<()>{
+	fn debug_screen_space(frame: &mut cv::Mat, screen_space: &EnclosingCircle, visible_fraction: f64, calibration: &LightBallCalibration, color_range: &ColorRangeHSV, font_size: f64) -> opencv::Result<()>{
 		//cv::circle(debugProcessedFrame, screenSpace.position ,screenSpace.radius, cv::Scalar(255, 166, 0),4);
 		type Pos = opencv::core::Point2i;
 		opencv::imgproc::circle(
 			frame,
 			opencv::core::Point2f::new(screen_space.position.x, screen_space.position.y).to::<i32>().unwrap(),
 			screen_space.radius as i32,
-			color_range.color_lower,
+			color_range.get_draw_color(),
 			4,
 			opencv::imgproc::LineTypes::LINE_8 as i32,
 			0
@@ -257,7 +366,7 @@ impl LightBallTracker {
 			false
 		)?;
 		let distance 
-			= "Distance: ".to_string() + &(calibration.object_real_radius * calibration.focal_length / screen_space.radius as f64).to_string();
+			= "Distance: ".to_string() + &calibration.distance(screen_space.radius as f64).to_string();
 		opencv::imgproc::put_text(
 			frame,
 			&distance,
@@ -269,147 +378,611 @@ impl LightBallTracker {
 			8,
 			false
 		)?;
+		let visible
+			= "Visible: ".to_string() + &format!("{:.0}%", visible_fraction * 100.);
+		opencv::imgproc::put_text(
+			frame,
+			&visible,
+			text_pos + Pos::new(0, (font_size * 150.) as i32),
+			opencv::imgproc::FONT_HERSHEY_SIMPLEX,
+			font_size,
+			opencv::core::Scalar::new(255., 255., 255., 255.),
+			2,
+			8,
+			false
+		)?;
 		Ok(())
 	}
 
-	fn compute_position(&mut self, screen_space: &EnclosingCircle, screen_size: &opencv::core::Size) -> Option<tracker::Position>{
-		if self.calibration.focal_length == 0.0{// temporary solution till, there will be a proper calibration process
-			self.calibration
-			 = LightBallCalibration::from_real_object_distance(screen_size, 115., 4., 19. /*232,6.5,20*/);
+	/// Position of the ball in metres in the camera frame: x right, y down and z forward along the optical axis (OpenCV convention).
+	/// Undistorted blob centre is back-projected through `intrinsics` (ideal pinhole camera when missing)
+	///  and placed on that ray at the distance derived from the blob radius.
+	pub fn compute_position(calibration: &LightBallCalibration, screen_space: &EnclosingCircle, screen_size: &opencv::core::Size, intrinsics: Option<&CameraIntrinsics>) -> Option<tracker::Position>{
+		if screen_space.radius <= 0. {
+			return None;
 		}
 
-		
 		//https://www.pyimagesearch.com/2015/01/19/find-distance-camera-objectmarker-using-python-opencv/
 		//see calibrateRealObjectDistance() function
-		let distance     = self.calibration.object_real_radius * self.calibration.focal_length / screen_space.radius as f64;
-		let azimuth      = (screen_size.width - screen_space.position.x as i32) as f64 * (self.calibration.angle_of_view.width / screen_size.width as f64);
-		let inclination  = (screen_size.height - screen_space.position.y as i32) as f64 * (self.calibration.angle_of_view.height / screen_size.height as f64);
-
-		//std::cout<<"dist:"<<distance<<";inc: "<<toDegrees(inclination)<<" deg;azi: "<<toDegrees(azimuth)<<" deg\n";
-		/* Old solution not sure why it is not working (x axis is going along with y axis): from https://en.wikipedia.org/wiki/Spherical_coordinate_system
-		x = distance * std::sin(inclination) * std::cos(azimuth);
-		y = distance * std::sin(inclination) * std::sin(azimuth);
-		z = distance * std::cos(inclination);
-		*/
-		//this works, treating each axis as if it was solved for [x;y] and [z;y] in 2D seperatly
-		let x = distance * inclination.cos() * azimuth.sin();
-		let y = distance * inclination.sin();
-		let z = distance * inclination.cos();
-		
-		if (x * y * z) == std::f64::INFINITY {
+		let distance = calibration.distance(screen_space.radius as f64);
+
+		let camera = match intrinsics {
+			Some(intrinsics) => intrinsics.scaled_to(*screen_size),
+			None => CameraIntrinsics::ideal(*screen_size, calibration.focal_length)
+		};
+		let point = camera
+			.and_then(|camera| camera.back_project(screen_space.position, distance))
+			.ok()?;
+
+		if !(point.x.is_finite() && point.y.is_finite() && point.z.is_finite()) {
 			return None
 		}
 
-		Some(tracker::Position{x,y,z})
-		/*self.counter += 6.6;
-		let mut pos = tracker::Position::default();
-		pos.y = self.counter;
-		pos*/
-		
+		Some(tracker::Position{x: point.x, y: point.y, z: point.z})
 	}
 
-	fn make_mask(frame: &cv::Mat, color_range: &ColorRangeHSV) -> opencv::Result<cv::Mat> {
-		//opencv::core::Scalar::new(75.,2.,100., 0.);
-		//opencv::core::Scalar::new(78., 10.,250., 0.);
-		//let color_lower = opencv::core::Scalar::new(0.,2.,245., 0.);//Color3d::from([75.,2.,100.]);
-		//let color_upper = opencv::core::Scalar::new(76., 100.,252., 0.);//Color3d::from([78., 10.,255.]);
+	pub fn get_name(&self) -> &str{
+		&self.name
+	}
 
-		let mut in_range = cv::Mat::default();
-		opencv::core::in_range(frame, &color_range.color_lower, &color_range.color_upper, &mut in_range)?;
+	pub fn get_color_range(&self) -> &ColorRangeHSV{
+		&self.color_range
+	}
 
-		let anchor = opencv::core::Point::new(-1, -1);
+	pub fn set_color_range(&mut self, color_range: ColorRangeHSV){
+		let pipeline = self.mask_pipeline.get_mut().unwrap();
+		if let Some(stage) = pipeline.get_stage_mut(ColorThreshold::NAME) {
+			for (parameter, value) in ColorThreshold{color_range: color_range.clone()}.get_parameters() {
+				let _ = stage.set_parameter(parameter, value);
+			}
+		}
+		self.color_range = color_range;
+	}
 
-		let mut eroded = cv::Mat::default();
-		let border_value = opencv::imgproc::morphology_default_border_value()?;
-		opencv::imgproc::erode(
-			&in_range,
-			&mut eroded,
-			&cv::Mat::default(),
-			anchor.clone(),
-			2,
-			opencv::core::BORDER_CONSTANT,
-			border_value.clone()
-		)?;
-		
-		let mut mask = cv::Mat::default();
-		opencv::imgproc::dilate(
-			&eroded,
-			&mut mask,
-			&cv::Mat::default(),
-			anchor.clone(),
-			2,
-			opencv::core::BORDER_CONSTANT,
-			border_value.clone()
-		)?;
-		//mask.clone_from(frame);
-		Ok(mask)
+	pub fn get_mask_pipeline(&self) -> &std::sync::Mutex<ProcessingPipeline>{
+		&self.mask_pipeline
 	}
-	
 
-	//finds a biggets volume of pixels and make a enclosing circle around it
-	fn compute_screen_space_position(mask: &cv::Mat) -> opencv::Result<EnclosingCircle>{
-		let mut circle = EnclosingCircle::default();
-		let mut contours = Contours::default();
-		if !mask.empty() {
-			opencv::imgproc::find_contours(
-				mask,
-				&mut contours,
-				opencv::imgproc::RETR_EXTERNAL,
-				opencv::imgproc::CHAIN_APPROX_SIMPLE,
-				opencv::core::Point::default()
-			)?;
-			let max_area_index = Self::get_max_area_contour(&contours);
-	 
-			if let Some(area_index) = max_area_index {
-				if let Ok(max_controur) = contours.get(area_index){
-					opencv::imgproc::min_enclosing_circle(&max_controur, &mut circle.position, &mut circle.radius)?;
-					//opencv::imgproc::min_area_rect(points)
-				}
+	/// Changes parameter of a stage of the mask pipeline, colour range follows changes of the colour threshold.
+	pub fn set_pipeline_parameter(&mut self, stage_name: &str, parameter: &str, value: f64) -> Result<(), String>{
+		let pipeline = self.mask_pipeline.get_mut().unwrap();
+		pipeline.set_parameter(stage_name, parameter, value)?;
+		if stage_name == ColorThreshold::NAME {
+			if let Some(stage) = pipeline.get_stage_mut(ColorThreshold::NAME) {
+				self.color_range = ColorThreshold::color_range_from(&stage.get_parameters());
 			}
 		}
-		Ok(circle)
+		Ok(())
 	}
 
-	
-	fn get_max_area_contour(contours: &Contours) -> Option<usize> {
-		//source https://stackoverflow.com/questions/46187563/finding-largest-contours-c
-		let mut max_area = 0.0;
-		let mut max_area_contour_index: Option<usize> = None;
-	
-		
-		for (index, contour) in contours.iter().enumerate() {
-			if let Ok(new_area) = opencv::imgproc::contour_area(&contour, false){
-				if new_area > max_area {
-					max_area = new_area;
-					max_area_contour_index = Some(index);
+	/// Colour threshold followed by opening, which removes small specks.
+	pub fn mask_pipeline(color_range: &ColorRangeHSV) -> ProcessingPipeline{
+		ProcessingPipeline::new()
+			.with(ColorThreshold{color_range: color_range.clone()})
+			.with(Morphology{erode_iterations: 2, dilate_iterations: 2})
+	}
+
+	fn smooth(&mut self, position: tracker::Position) -> tracker::Position{
+		self.smoothing.smooth(&mut self.smoothed_position, position)
+	}
+
+	/// Size/distance calibration of this tracker for camera with `camera_id`, scaled to `frame_size`.
+	fn get_calibration(&mut self, camera_id: &str, frame_size: &opencv::core::Size, intrinsics: Option<&CameraIntrinsics>) -> &LightBallCalibration{
+		let name = &self.name;
+		let real_radius = self.real_radius;
+		let calibration = self.calibrations.entry(camera_id.to_owned())
+			.or_insert_with(|| LightBallCalibration::load_or_guess(camera_id, name, real_radius, frame_size, intrinsics));
+		if calibration.frame_size != *frame_size {
+			*calibration = calibration.scaled_to(frame_size);
+		}
+		calibration
+	}
+
+	/// Indices of trackers grouped by equal colour ranges.
+	fn color_groups(color_ranges: &[&ColorRangeHSV]) -> Vec<Vec<usize>>{
+		let mut groups: Vec<Vec<usize>> = vec![];
+		for (index, color_range) in color_ranges.iter().enumerate() {
+			match groups.iter_mut().find(|group| color_ranges[group[0]] == *color_range) {
+				Some(group) => group.push(index),
+				None => groups.push(vec![index])
+			}
+		}
+		groups
+	}
+
+	/// Finds balls of `trackers` sharing one colour range in preprocessed `frame` of camera `camera_id`,
+	///  returns blob of each tracker (None when missing), masks are made by pipeline and filter of the first tracker.
+	/// Blobs are assigned to trackers by `blob_assignment::assign`, so identities stay when balls of the same colour cross,
+	///  tracks lost by this camera are reprojected from other cameras by `BlobTracks`.
+	/// Balls with a track are searched only in windows around their predicted positions, the whole frame is searched
+	///  only for the others and for balls with `roi_search` disabled, so blobs found in the windows are left out.
+	/// Mask of the whole frame is written to `preview_mask` when given, its buffer is reused for frames of the same size.
+	/// Blur and HSV conversion of the processing entity still run on whole frames, they are shared by all trackers and previews.
+	fn find_blobs(trackers: &mut [&mut LightBallTracker], camera_id: &str, frame: &cv::Mat, triangulator: &Triangulator, mut preview_mask: Option<&mut cv::Mat>) -> opencv::Result<Vec<Option<BlobCandidate>>>{
+		let frame_size = frame.size()?;
+		let tracks: Vec<Option<BlobTrack>> = trackers.iter()
+			.map(|tracker| tracker.blob_tracks.get_or_seed(camera_id, frame_size, triangulator, tracker.real_radius))
+			.collect();
+		let windows: Vec<Option<opencv::core::Rect>> = trackers.iter().zip(tracks.iter())
+			.map(|(tracker, track)| track.as_ref()
+				.filter(|_| tracker.roi_search)
+				.map(|track| track.search_window(frame_size))
+				.filter(|window| window.width > 0 && window.height > 0))
+			.collect();
+
+		let mut blobs: Vec<Option<BlobCandidate>> = vec![None; trackers.len()];
+		let searched: Vec<opencv::core::Rect> = windows.iter().flatten().copied().collect();
+		if !searched.is_empty() {
+			let candidates = trackers[0].find_in_windows(frame, &searched, preview_mask.as_deref_mut())?;
+			// balls without window take nothing here, they are searched in the whole frame
+			let window_tracks: Vec<Option<BlobTrack>> = tracks.iter().zip(windows.iter())
+				.map(|(track, window)| window.and(*track))
+				.collect();
+			for ((blob, window), assigned) in blobs.iter_mut().zip(windows.iter()).zip(blob_assignment::assign(&window_tracks, &candidates)) {
+				if window.is_some() {
+					*blob = assigned.map(|index| candidates[index].clone());
 				}
 			}
 		}
-		return max_area_contour_index;
+
+		let unsearched: Vec<usize> = (0..trackers.len()).filter(|index| windows[*index].is_none()).collect();
+		if !unsearched.is_empty() {
+			let mask = trackers[0].make_mask(frame)?;
+			let candidates: Vec<BlobCandidate> = trackers[0].find_candidates(&mask, frame, opencv::core::Point::default())?
+				.into_iter()
+				.filter(|candidate| !blobs.iter().flatten().any(|blob| {
+					let offset = candidate.circle.position - blob.circle.position;
+					(offset.x * offset.x + offset.y * offset.y).sqrt() <= blob.circle.radius
+				}))
+				.collect();
+			let unsearched_tracks: Vec<Option<BlobTrack>> = unsearched.iter().map(|index| tracks[*index]).collect();
+			for (index, assigned) in unsearched.iter().zip(blob_assignment::assign(&unsearched_tracks, &candidates)) {
+				blobs[*index] = assigned.map(|assigned| candidates[assigned].clone());
+			}
+			if let Some(preview_mask) = preview_mask {
+				*preview_mask = mask;
+			}
+		}
+
+		for (tracker, blob) in trackers.iter_mut().zip(blobs.iter()) {
+			match blob {
+				Some(blob) => tracker.blob_tracks.follow(camera_id, &blob.circle),
+				None => tracker.blob_tracks.remove(camera_id)
+			}
+		}
+		Ok(blobs)
+	}
+
+	/// Candidates in the union of `windows`, thresholded as one mask so blobs in overlapping windows are found once.
+	/// Mask of the whole frame is written to `preview_mask` when given, zero outside of the windows.
+	fn find_in_windows(&mut self, frame: &cv::Mat, windows: &[opencv::core::Rect], preview_mask: Option<&mut cv::Mat>) -> opencv::Result<Vec<BlobCandidate>>{
+		let left = windows.iter().map(|window| window.x).min().unwrap_or_default();
+		let top = windows.iter().map(|window| window.y).min().unwrap_or_default();
+		let right = windows.iter().map(|window| window.x + window.width).max().unwrap_or_default();
+		let bottom = windows.iter().map(|window| window.y + window.height).max().unwrap_or_default();
+		let bounds = opencv::core::Rect::new(left, top, right - left, bottom - top);
+
+		let mask = cv::Mat::zeros(bounds.height, bounds.width, opencv::core::CV_8UC1)?.to_mat()?;
+		for window in windows {
+			let window_mask = self.make_mask(&cv::Mat::roi(frame, *window)?)?;
+			let mut region = cv::Mat::roi(&mask, opencv::core::Rect::new(window.x - left, window.y - top, window.width, window.height))?;
+			// overlapping windows are joined
+			let mut joined = cv::Mat::default();
+			opencv::core::bitwise_or(&region, &window_mask, &mut joined, &cv::Mat::default())?;
+			joined.copy_to(&mut region)?;
+		}
+
+		if let Some(preview_mask) = preview_mask {
+			let frame_size = frame.size()?;
+			if preview_mask.size()? == frame_size && preview_mask.typ() == opencv::core::CV_8UC1 {
+				preview_mask.set_to(&opencv::core::Scalar::all(0.), &opencv::core::no_array())?;
+			}
+			else {
+				*preview_mask = cv::Mat::zeros(frame_size.height, frame_size.width, opencv::core::CV_8UC1)?.to_mat()?;
+			}
+			mask.copy_to(&mut cv::Mat::roi(preview_mask, bounds)?)?;
+		}
+		self.find_candidates(&mask, &cv::Mat::roi(frame, bounds)?, bounds.tl())
+	}
+
+	/// Every blob of the mask which passes the blob filter, circles come from the radius estimator
+	///  and positions are moved by `offset` of the mask in the frame.
+	/// Shape and brightness are measured around the enclosing circle, radius limits of the filter apply to the estimated circle.
+	/// `frame` is the preprocessed frame region under the mask, its brightness is part of blob quality.
+	pub(super) fn find_candidates(&self, mask: &cv::Mat, frame: &cv::Mat, offset: opencv::core::Point) -> opencv::Result<Vec<BlobCandidate>>{
+		let mut candidates = vec![];
+		if mask.empty() {
+			return Ok(candidates);
+		}
+		let mut contours = Contours::default();
+		opencv::imgproc::find_contours(
+			mask,
+			&mut contours,
+			opencv::imgproc::RETR_EXTERNAL,
+			// every edge point for fitting the circle
+			opencv::imgproc::CHAIN_APPROX_NONE,
+			opencv::core::Point::default()
+		)?;
+		for contour in contours.iter() {
+			let area = opencv::imgproc::contour_area(&contour, false)?;
+			if area < Self::MIN_BLOB_AREA {
+				continue;
+			}
+			let mut circle = EnclosingCircle::default();
+			opencv::imgproc::min_enclosing_circle(&contour, &mut circle.position, &mut circle.radius)?;
+			let quality = BlobQuality::measure(&contour, area, &circle, frame, opencv::core::Point::default())?;
+			let fit = self.radius_estimator.estimate(&contour, &circle)?;
+			// lamps, screens and reflections are rarely round, filled and darker around
+			if !self.blob_filter.accepts(&fit.circle, &quality) {
+				continue;
+			}
+			circle = fit.circle;
+			circle.position += opencv::core::Point2f::new(offset.x as f32, offset.y as f32);
+			candidates.push(BlobCandidate{circle: circle, area: area, quality: quality, visible_fraction: fit.visible_fraction});
+		}
+		Ok(candidates)
+	}
+
+	pub(super) fn make_mask(&mut self, frame: &cv::Mat) -> opencv::Result<cv::Mat> {
+		let mut mask = cv::Mat::default();
+		self.mask_pipeline.get_mut().unwrap().process(&mut mask, frame)?;
+		Ok(mask)
 	}
 
 }
+/// Range of HSV colours, bounds are inclusive.
+/// Hue is circular (0-180 in OpenCV), when lower hue is above upper hue the range wraps around 0,
+///  e.g. red from 170 through 180/0 up to 10.
 #[derive(Default, Clone)]
-struct ColorRangeHSV{
+pub struct ColorRangeHSV{
 	color_lower: opencv::core::Scalar,
 	color_upper: opencv::core::Scalar
 }
 
+impl PartialEq for ColorRangeHSV {
+	fn eq(&self, other: &Self) -> bool {
+		self.color_lower.0 == other.color_lower.0 && self.color_upper.0 == other.color_upper.0
+	}
+}
+
+impl ColorRangeHSV {
+	/// Full turn of the hue in OpenCV 8-bit HSV.
+	pub const HUE_PERIOD: f64 = 180.;
+	/// Percentiles of channels used as range bounds, so few outlying pixels don't widen the range.
+	const PERCENTILES: (f64, f64) = (0.05, 0.95);
+	/// Added to both sides of the range for hue, saturation and value.
+	const MARGIN: [f64; 3] = [4., 25., 25.];
+
+	pub const fn new(color_lower: [f64; 3], color_upper: [f64; 3]) -> Self{
+		ColorRangeHSV{
+			color_lower: opencv::core::VecN{0: [color_lower[0], color_lower[1], color_lower[2], 0.]},
+			color_upper: opencv::core::VecN{0: [color_upper[0], color_upper[1], color_upper[2], 0.]}
+		}
+	}
+
+	/// Hues within `tolerance` of `hue_centre` on both sides, wrapping around 0 when needed.
+	/// Tolerance of half the period or more accepts any hue.
+	pub fn from_hue(hue_centre: f64, tolerance: f64, saturation: (f64, f64), value: (f64, f64)) -> Self{
+		let (hue_lower, hue_upper) = if 2. * tolerance >= Self::HUE_PERIOD {
+			(0., Self::HUE_PERIOD)
+		}
+		else {
+			((hue_centre - tolerance).rem_euclid(Self::HUE_PERIOD), (hue_centre + tolerance).rem_euclid(Self::HUE_PERIOD))
+		};
+		Self::new([hue_lower, saturation.0, value.0], [hue_upper, saturation.1, value.1])
+	}
+
+	pub fn is_hue_wrapped(&self) -> bool{
+		self.color_lower.0[0] > self.color_upper.0[0]
+	}
+
+	/// Non-wrapping intervals (lower, upper) for `in_range`, the range is their union.
+	pub fn get_intervals(&self) -> Vec<(opencv::core::Scalar, opencv::core::Scalar)>{
+		if !self.is_hue_wrapped() {
+			return vec![(self.color_lower, self.color_upper)];
+		}
+		let mut to_period = self.color_upper;
+		to_period.0[0] = Self::HUE_PERIOD;
+		let mut from_zero = self.color_lower;
+		from_zero.0[0] = 0.;
+		vec![(self.color_lower, to_period), (from_zero, self.color_upper)]
+	}
+
+	/// Hue in the middle of the range, in OpenCV units (0-180).
+	pub fn get_hue_centre(&self) -> f64{
+		let lower = self.color_lower.0[0];
+		let mut upper = self.color_upper.0[0];
+		if self.is_hue_wrapped() {
+			upper += Self::HUE_PERIOD;
+		}
+		((lower + upper) / 2.).rem_euclid(Self::HUE_PERIOD)
+	}
+
+	/// Colour for drawing in the HSV frame.
+	pub fn get_draw_color(&self) -> opencv::core::Scalar{
+		opencv::core::Scalar::new(self.get_hue_centre(), self.color_upper.0[1], self.color_upper.0[2], 0.)
+	}
+
+	pub fn path(tracker_name: &str) -> std::path::PathBuf{
+		calibration::config_directory()
+			.join("light_balls")
+			.join(format!("{}_color.yml", crate::trackers::opencv_trackers::file_name_from_id(tracker_name)))
+	}
+
+	pub fn save(&self, tracker_name: &str) -> opencv::Result<std::path::PathBuf>{
+		let path = Self::path(tracker_name);
+		let mut storage = calibration::open_for_writing(&path)?;
+		storage.write_str("tracker", tracker_name)?;
+		storage.write_mat("lower", &opencv::core::Mat::from_slice(&self.color_lower.0[..3])?)?;
+		storage.write_mat("upper", &opencv::core::Mat::from_slice(&self.color_upper.0[..3])?)?;
+		storage.release()?;
+		Ok(path)
+	}
+
+	pub fn load(tracker_name: &str) -> opencv::Result<Self>{
+		let mut storage = calibration::open_for_reading(&Self::path(tracker_name))?;
+		let lower = storage.get("lower")?.mat()?;
+		let upper = storage.get("upper")?.mat()?;
+		storage.release()?;
+
+		let mut color_range = ColorRangeHSV::default();
+		for channel in 0..3 {
+			color_range.color_lower.0[channel] = *lower.at::<f64>(channel as i32)?;
+			color_range.color_upper.0[channel] = *upper.at::<f64>(channel as i32)?;
+		}
+		Ok(color_range)
+	}
+
+	/// Proposes range from HSV pixels of `region` in `hsv_frame`.
+	/// Only brighter half of the pixels is used, so background around the ball doesn't leak into the range.
+	/// Hues are measured around their circular mean, so red pixels on both sides of 0 give wrapped range.
+	pub fn from_hsv_region(hsv_frame: &cv::Mat, region: opencv::core::Rect) -> opencv::Result<Option<Self>>{
+		let x = region.x.max(0);
+		let y = region.y.max(0);
+		let region = opencv::core::Rect::new(
+			x,
+			y,
+			(region.x + region.width).min(hsv_frame.cols()) - x,
+			(region.y + region.height).min(hsv_frame.rows()) - y
+		);
+		if region.width <= 0 || region.height <= 0 {
+			return Ok(None);
+		}
+		let pixels = cv::Mat::roi(hsv_frame, region)?;
+		let mut channels: [Vec<f64>; 3] = [vec![], vec![], vec![]];
+		for row in 0..pixels.rows() {
+			for column in 0..pixels.cols() {
+				let pixel = pixels.at_2d::<opencv::core::Vec3b>(row, column)?;
+				for channel in 0..3 {
+					channels[channel].push(pixel[channel] as f64);
+				}
+			}
+		}
+
+		let mut values = channels[2].clone();
+		values.sort_by(|a, b| a.total_cmp(b));
+		let median_value = values[values.len() / 2];
+		let bright: Vec<usize> = (0..channels[2].len()).filter(|index| channels[2][*index] >= median_value).collect();
+
+		// circular mean of the hue
+		let to_angle = 2. * std::f64::consts::PI / Self::HUE_PERIOD;
+		let (sin, cos) = bright.iter()
+			.map(|index| channels[0][*index] * to_angle)
+			.fold((0., 0.), |(sin, cos), angle| (sin + angle.sin(), cos + angle.cos()));
+		let hue_mean = (sin.atan2(cos) / to_angle).rem_euclid(Self::HUE_PERIOD);
+
+		let mut lower = [0.; 3];
+		let mut upper = [0.; 3];
+		for channel in 0..3 {
+			let mut samples: Vec<f64> = bright.iter().map(|index| {
+				let sample = channels[channel][*index];
+				if channel == 0 {
+					// signed hue difference from the mean
+					(sample - hue_mean + Self::HUE_PERIOD / 2.).rem_euclid(Self::HUE_PERIOD) - Self::HUE_PERIOD / 2.
+				}
+				else {
+					sample
+				}
+			}).collect();
+			samples.sort_by(|a, b| a.total_cmp(b));
+			lower[channel] = samples[(Self::PERCENTILES.0 * (samples.len() - 1) as f64) as usize] - Self::MARGIN[channel];
+			upper[channel] = samples[(Self::PERCENTILES.1 * (samples.len() - 1) as f64) as usize] + Self::MARGIN[channel];
+		}
+
+		let saturation = (lower[1].max(0.), upper[1].min(255.));
+		let value = (lower[2].max(0.), upper[2].min(255.));
+		let hue_centre = hue_mean + (lower[0] + upper[0]) / 2.;
+		let tolerance = (upper[0] - lower[0]) / 2.;
+		Ok(Some(Self::from_hue(hue_centre, tolerance, saturation, value)))
+	}
+}
+
+impl std::fmt::Display for ColorRangeHSV {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f, "[{}, {}, {}] - [{}, {}, {}]",
+			self.color_lower.0[0], self.color_lower.0[1], self.color_lower.0[2],
+			self.color_upper.0[0], self.color_upper.0[1], self.color_upper.0[2]
+		)
+	}
+}
+
+/// Mask of HSV frame pixels within the colour range, intervals of wrapped hue are joined together.
+pub struct ColorThreshold{
+	pub color_range: ColorRangeHSV
+}
+
+impl ColorThreshold {
+	pub const NAME: &'static str = "color_threshold";
+	const PARAMETERS: [&'static str; 6] = ["hue_lower", "saturation_lower", "value_lower", "hue_upper", "saturation_upper", "value_upper"];
+
+	pub fn color_range_from(parameters: &[(&'static str, f64)]) -> ColorRangeHSV{
+		let mut bounds = [0.; 6];
+		for (name, value) in parameters {
+			if let Some(index) = Self::PARAMETERS.iter().position(|parameter| parameter == name) {
+				bounds[index] = *value;
+			}
+		}
+		ColorRangeHSV::new([bounds[0], bounds[1], bounds[2]], [bounds[3], bounds[4], bounds[5]])
+	}
+}
+
+impl ProcessingStage for ColorThreshold {
+	fn get_name(&self) -> &'static str{
+		Self::NAME
+	}
+
+	fn process(&mut self, dest: &mut cv::Mat, src: &cv::Mat) -> opencv::Result<()>{
+		*dest = cv::Mat::default();
+		for (lower, upper) in self.color_range.get_intervals() {
+			let mut interval = cv::Mat::default();
+			opencv::core::in_range(src, &lower, &upper, &mut interval)?;
+			if dest.empty() {
+				*dest = interval;
+			}
+			else {
+				let mut joined = cv::Mat::default();
+				opencv::core::bitwise_or(&*dest, &interval, &mut joined, &cv::Mat::default())?;
+				*dest = joined;
+			}
+		}
+		Ok(())
+	}
+
+	fn get_parameters(&self) -> Vec<(&'static str, f64)>{
+		let lower = &self.color_range.color_lower.0;
+		let upper = &self.color_range.color_upper.0;
+		Self::PARAMETERS.iter().copied().zip([lower[0], lower[1], lower[2], upper[0], upper[1], upper[2]]).collect()
+	}
+
+	fn set_parameter(&mut self, name: &str, value: f64) -> Result<(), String>{
+		let index = Self::PARAMETERS.iter().position(|parameter| *parameter == name)
+			.ok_or_else(|| format!("Stage {} has no parameter {}", Self::NAME, name))?;
+		let bounds = if index < 3 { &mut self.color_range.color_lower } else { &mut self.color_range.color_upper };
+		bounds.0[index % 3] = value;
+		Ok(())
+	}
+}
+
 
 
-#[derive(Default)]
-struct LightBallCalibration{
+#[derive(Default, Clone)]
+pub struct LightBallCalibration{
 	angle_of_view: opencv::core::Size2d,
+	/// in pixels
 	focal_length: f64,
-	object_real_radius: f64
+	/// in metres
+	object_real_radius: f64,
+	/// frame size for which focal_length was computed
+	frame_size: opencv::core::Size,
+	/// fitted radius to distance model, replaces `object_real_radius * focal_length / radius` when set
+	distance_model: Option<DistanceModel>,
+	/// frame size of radii in distance_model
+	model_frame_size: opencv::core::Size
 }
 
 impl LightBallCalibration {
-	fn from_real_object_distance(frame_size: &opencv::core::Size, pixel_radius: f64, real_radius: f64, real_distance: f64) -> Self{
+	pub fn path(camera_id: &str, tracker_name: &str) -> std::path::PathBuf{
+		calibration::camera_config_directory(camera_id).join(format!("light_ball_{}.yml", crate::trackers::opencv_trackers::file_name_from_id(tracker_name)))
+	}
+
+	pub fn save(&self, camera_id: &str, tracker_name: &str) -> opencv::Result<std::path::PathBuf>{
+		let path = Self::path(camera_id, tracker_name);
+		let mut storage = calibration::open_for_writing(&path)?;
+		storage.write_str("camera_id", camera_id)?;
+		storage.write_str("tracker", tracker_name)?;
+		storage.write_i32("image_width", self.frame_size.width)?;
+		storage.write_i32("image_height", self.frame_size.height)?;
+		storage.write_f64("focal_length", self.focal_length)?;
+		storage.write_f64("real_radius", self.object_real_radius)?;
+		if let Some(model) = &self.distance_model {
+			model.write(&mut storage)?;
+		}
+		storage.release()?;
+		Ok(path)
+	}
+
+	pub fn load(camera_id: &str, tracker_name: &str) -> opencv::Result<Self>{
+		let mut storage = calibration::open_for_reading(&Self::path(camera_id, tracker_name))?;
+		let frame_size = opencv::core::Size::new(storage.get("image_width")?.to_i32()?, storage.get("image_height")?.to_i32()?);
+		let focal_length = storage.get("focal_length")?.real()?;
+		let real_radius = storage.get("real_radius")?.real()?;
+		let model = DistanceModel::read(&storage)?;
+		storage.release()?;
+
+		let calibration = Self::from_focal_length(&frame_size, focal_length, real_radius);
+		Ok(match model {
+			Some(model) => calibration.with_model(model),
+			None => calibration
+		})
+	}
+
+	/// Saved calibration, when camera wasn't calibrated by `rtrack calibrate-ball` pinhole model with `real_radius`
+	///  and focal length (fx) of `intrinsics`, or of typical camera when there are no intrinsics.
+	pub fn load_or_guess(camera_id: &str, tracker_name: &str, real_radius: f64, frame_size: &opencv::core::Size, intrinsics: Option<&CameraIntrinsics>) -> Self{
+		if let Ok(calibration) = Self::load(camera_id, tracker_name) {
+			return calibration;
+		}
+		let focal_length = intrinsics
+			.and_then(|intrinsics| intrinsics.scaled_to(*frame_size).and_then(|intrinsics| intrinsics.focal_length()).ok())
+			.map(|(fx, _)| fx)
+			.unwrap_or_else(|| Self::from_real_object_distance(frame_size, 115., 0.04, 0.19).get_focal_length());
+		Self::from_focal_length(frame_size, focal_length, real_radius)
+	}
+
+	/// Uses `model` for radii measured in frames of current frame size.
+	pub fn with_model(mut self, model: DistanceModel) -> Self{
+		self.model_frame_size = self.frame_size;
+		self.distance_model = Some(model);
+		self
+	}
+
+	pub fn get_distance_model(&self) -> Option<&DistanceModel>{
+		self.distance_model.as_ref()
+	}
+
+	/// Distance of the ball in metres from its apparent radius in pixels.
+	pub fn distance(&self, radius: f64) -> f64{
+		match &self.distance_model {
+			Some(model) if self.frame_size.width > 0 => {
+				model.distance(radius * self.model_frame_size.width as f64 / self.frame_size.width as f64)
+			},
+			//https://www.pyimagesearch.com/2015/01/19/find-distance-camera-objectmarker-using-python-opencv/
+			_ => self.object_real_radius * self.focal_length / radius
+		}
+	}
+
+	/// Same calibration for frames of different resolution (with same field of view).
+	pub fn scaled_to(&self, frame_size: &opencv::core::Size) -> Self{
+		let scale = if self.frame_size.width == 0 { 1. } else { frame_size.width as f64 / self.frame_size.width as f64 };
+		let mut scaled = Self::from_focal_length(frame_size, self.focal_length * scale, self.object_real_radius);
+		scaled.distance_model = self.distance_model.clone();
+		scaled.model_frame_size = self.model_frame_size;
+		scaled
+	}
+
+	pub fn get_focal_length(&self) -> f64{
+		self.focal_length
+	}
+
+	pub fn get_frame_size(&self) -> &opencv::core::Size{
+		&self.frame_size
+	}
+
+	pub fn get_real_radius(&self) -> f64{
+		self.object_real_radius
+	}
+
+	pub fn from_real_object_distance(frame_size: &opencv::core::Size, pixel_radius: f64, real_radius: f64, real_distance: f64) -> Self{
 		//https://www.pyimagesearch.com/2015/01/19/find-distance-camera-objectmarker-using-python-opencv/
+		Self::from_focal_length(frame_size, pixel_radius * real_distance / real_radius, real_radius)
+	}
+
+	pub fn from_focal_length(frame_size: &opencv::core::Size, focal_length: f64, real_radius: f64) -> Self{
 		let mut calibration = LightBallCalibration::default();
-		calibration.focal_length       = pixel_radius * real_distance / real_radius;
+		calibration.focal_length       = focal_length;
 		calibration.object_real_radius  = real_radius;
+		calibration.frame_size          = *frame_size;
 
 		//https://en.wikipedia.org/wiki/Angle_of_view
 		calibration.angle_of_view.width         = 2.0 * (frame_size.width as f64 / (2.0 * calibration.focal_length)).atan();
@@ -419,9 +992,117 @@ impl LightBallCalibration {
 	}
 }
 
-#[derive(Default)]
-struct EnclosingCircle{
-	position: opencv::core::Point2f,
-	radius: f32
+#[derive(Default, Clone, Copy)]
+pub struct EnclosingCircle{
+	pub position: opencv::core::Point2f,
+	pub radius: f32
 }
 type Contours = opencv::core::Vector<opencv::core::Vector<opencv::core::Point>>;
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn channels(scalar: &opencv::core::Scalar) -> [f64; 3]{
+		[scalar.0[0], scalar.0[1], scalar.0[2]]
+	}
+
+	#[test]
+	fn hue_range_wraps_around_zero(){
+		let red = ColorRangeHSV::from_hue(175., 10., (100., 255.), (200., 255.));
+		assert!(red.is_hue_wrapped());
+		let intervals = red.get_intervals();
+		assert_eq!(intervals.len(), 2);
+		assert_eq!(channels(&intervals[0].0), [165., 100., 200.]);
+		assert_eq!(channels(&intervals[0].1), [180., 255., 255.]);
+		assert_eq!(channels(&intervals[1].0), [0., 100., 200.]);
+		assert_eq!(channels(&intervals[1].1), [5., 255., 255.]);
+		assert!((red.get_hue_centre() - 175.).abs() < 1e-9);
+	}
+
+	#[test]
+	fn hue_range_without_wrap_is_single_interval(){
+		let green = ColorRangeHSV::from_hue(60., 10., (100., 255.), (200., 255.));
+		assert!(!green.is_hue_wrapped());
+		let intervals = green.get_intervals();
+		assert_eq!(intervals.len(), 1);
+		assert_eq!(channels(&intervals[0].0), [50., 100., 200.]);
+		assert_eq!(channels(&intervals[0].1), [70., 255., 255.]);
+		assert!((green.get_hue_centre() - 60.).abs() < 1e-9);
+	}
+
+	#[test]
+	fn wide_hue_tolerance_accepts_any_hue(){
+		let white = ColorRangeHSV::from_hue(3., 90., (0., 30.), (240., 255.));
+		assert!(!white.is_hue_wrapped());
+		let intervals = white.get_intervals();
+		assert_eq!(channels(&intervals[0].0)[0], 0.);
+		assert_eq!(channels(&intervals[0].1)[0], ColorRangeHSV::HUE_PERIOD);
+	}
+
+	#[test]
+	fn hue_centre_of_range_ending_at_zero(){
+		let range = ColorRangeHSV::new([170., 0., 0.], [10., 255., 255.]);
+		assert!(range.get_hue_centre().abs() < 1e-9 || (range.get_hue_centre() - ColorRangeHSV::HUE_PERIOD).abs() < 1e-9);
+	}
+
+	#[test]
+	fn color_threshold_parameters_round_trip(){
+		let color_range = ColorRangeHSV::new([168., 100., 245.], [12., 255., 255.]);
+		let stage = ColorThreshold{color_range: color_range.clone()};
+		let parameters = stage.get_parameters();
+		assert_eq!(parameters.len(), 6);
+		assert!(ColorThreshold::color_range_from(&parameters) == color_range);
+	}
+
+	#[test]
+	fn color_threshold_sets_single_bound(){
+		let mut stage = ColorThreshold{color_range: ColorRangeHSV::new([90., 100., 245.], [255., 255., 255.])};
+		stage.set_parameter("saturation_lower", 50.).unwrap();
+		stage.set_parameter("hue_upper", 130.).unwrap();
+		let color_range = ColorThreshold::color_range_from(&stage.get_parameters());
+		assert!(color_range == ColorRangeHSV::new([90., 50., 245.], [130., 255., 255.]));
+		assert!(stage.set_parameter("hue", 10.).is_err());
+	}
+
+	/// HSV frame of `size` x `size` pixels with saturation 200 and value 220, hue of each pixel given by `hue`.
+	fn hsv_frame(size: i32, hue: impl Fn(i32, i32) -> u8) -> cv::Mat{
+		let mut frame = cv::Mat::new_rows_cols_with_default(size, size, opencv::core::CV_8UC3, opencv::core::Scalar::all(0.)).unwrap();
+		for row in 0..size {
+			for column in 0..size {
+				*frame.at_2d_mut::<opencv::core::Vec3b>(row, column).unwrap() = opencv::core::VecN([hue(row, column), 200, 220]);
+			}
+		}
+		frame
+	}
+
+	fn contains_hue(color_range: &ColorRangeHSV, hue: f64) -> bool{
+		color_range.get_intervals().iter().any(|(lower, upper)| lower.0[0] <= hue && hue <= upper.0[0])
+	}
+
+	#[test]
+	fn region_with_hues_on_both_sides_of_zero_gives_wrapped_range(){
+		// one row of ten in the region is red on the other side of 0
+		let frame = hsv_frame(20, |row, _| if row == 0 { 175 } else { 5 });
+		let color_range = ColorRangeHSV::from_hsv_region(&frame, opencv::core::Rect::new(0, 0, 10, 10)).unwrap().unwrap();
+		assert!(color_range.is_hue_wrapped());
+		assert!(contains_hue(&color_range, 5.));
+		assert!(contains_hue(&color_range, 175.));
+		assert!(!contains_hue(&color_range, 90.));
+	}
+
+	#[test]
+	fn region_is_clipped_by_frame(){
+		let frame = hsv_frame(20, |_, column| if column < 10 { 60 } else { 120 });
+		let color_range = ColorRangeHSV::from_hsv_region(&frame, opencv::core::Rect::new(15, 15, 10, 10)).unwrap().unwrap();
+		assert!(contains_hue(&color_range, 120.));
+		assert!(!contains_hue(&color_range, 60.));
+	}
+
+	#[test]
+	fn region_outside_frame_gives_no_range(){
+		let frame = hsv_frame(20, |_, _| 60);
+		assert!(ColorRangeHSV::from_hsv_region(&frame, opencv::core::Rect::new(30, 30, 5, 5)).unwrap().is_none());
+		assert!(ColorRangeHSV::from_hsv_region(&frame, opencv::core::Rect::new(-10, -10, 5, 5)).unwrap().is_none());
+	}
+}
@@ -1,16 +1,27 @@
 pub mod light_ball_tracker;
+pub mod light_ball_definitions;
+pub mod light_ball_control;
+pub mod ball_calibration;
+pub mod distance_model;
+pub mod color_calibration;
+pub mod blob_assignment;
+pub mod blob_quality;
+pub mod radius_estimation;
 mod light_ball_processing;
 use bevy::app::Plugin;
 
-const SETUP_LIST:  &'static [&'static dyn  Plugin] = &[
-	&light_ball_tracker::LightBallTrackerBuilder::default(),
-	&light_ball_processing::LightBallTrackerProcessingBuilder,
-];
+use crate::trackers::opencv_trackers::tracker_definitions::DefinitionsFile;
 
 pub fn setup_entities(app: &mut bevy::prelude::App){
-		
+	// trackers come from configuration, so ball set can change without recompiling
+	let definitions = light_ball_definitions::LightBallDefinitions::from_env();
+	let setup_list: Vec<Box<dyn Plugin>> = vec![
+		Box::new(light_ball_tracker::LightBallTrackerBuilder::new(definitions.trackers)),
+		Box::new(light_ball_processing::LightBallTrackerProcessingBuilder),
+	];
+
 	//world.init_resource()
-	for entity_builder in SETUP_LIST{
+	for entity_builder in setup_list{
 		entity_builder.build(app);
 	}
-}
\ No newline at end of file
+}
@@ -0,0 +1,243 @@
+use opencv::core;
+
+use serde::{Deserialize, Serialize};
+
+use crate::trackers::opencv_trackers::light_ball_trackers::light_ball_tracker::EnclosingCircle;
+
+/// How blob centre and radius are estimated from the ball contour.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RadiusEstimator{
+	/// smallest circle around the contour, grows with motion blur and shrinks when the ball is partly covered
+	#[default]
+	EnclosingCircle,
+	/// minor axis of ellipse fitted to the contour, motion blur stretches only the major axis
+	Ellipse,
+	/// circle fitted to edge points by RANSAC, edges of covering objects are outliers
+	Ransac
+}
+
+/// Circle of the ball and fraction (0-1) of its perimeter seen as edge of the blob.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct CircleFit{
+	pub circle: EnclosingCircle,
+	pub visible_fraction: f64
+}
+
+/// Angular bins of the perimeter for measuring the visible fraction.
+const PERIMETER_BINS: usize = 36;
+const RANSAC_ITERATIONS: usize = 100;
+/// Distance of inlier from the circle, in pixels and in multiples of the radius, the larger is used.
+const INLIER_TOLERANCE: (f32, f32) = (1., 0.05);
+/// Fits too far from the enclosing circle are failures, in multiples of its radius.
+const MAX_DEVIATION: f32 = 0.5;
+
+impl RadiusEstimator {
+	/// Circle of the ball with `contour` (all points, not approximated) enclosed by `enclosing`,
+	///  falls back to the enclosing circle when the fit fails.
+	pub fn estimate(&self, contour: &core::Vector<core::Point>, enclosing: &EnclosingCircle) -> opencv::Result<CircleFit>{
+		let points: Vec<core::Point2f> = contour.iter()
+			.map(|point| core::Point2f::new(point.x as f32, point.y as f32))
+			.collect();
+		let fitted = match self {
+			RadiusEstimator::EnclosingCircle => None,
+			RadiusEstimator::Ellipse => Self::fit_ellipse(contour)?,
+			RadiusEstimator::Ransac => Self::fit_ransac(&points)
+		};
+		let circle = fitted
+			.filter(|circle| Self::is_plausible(circle, enclosing))
+			.unwrap_or(*enclosing);
+		Ok(CircleFit{circle: circle, visible_fraction: Self::visible_fraction(&points, &circle)})
+	}
+
+	fn is_plausible(circle: &EnclosingCircle, enclosing: &EnclosingCircle) -> bool{
+		let offset = circle.position - enclosing.position;
+		let distance = (offset.x * offset.x + offset.y * offset.y).sqrt();
+		circle.radius.is_finite() && circle.radius > 0.
+			&& (circle.radius - enclosing.radius).abs() <= enclosing.radius * MAX_DEVIATION
+			&& distance <= enclosing.radius * MAX_DEVIATION
+	}
+
+	fn fit_ellipse(contour: &core::Vector<core::Point>) -> opencv::Result<Option<EnclosingCircle>>{
+		// fit needs 5 points at least
+		if contour.len() < 5 {
+			return Ok(None);
+		}
+		let ellipse = opencv::imgproc::fit_ellipse(contour)?;
+		let minor_axis = ellipse.size.width.min(ellipse.size.height);
+		Ok(Some(EnclosingCircle{position: ellipse.center, radius: minor_axis / 2.}))
+	}
+
+	/// Circle with the most edge points near it, refined by least squares on them.
+	fn fit_ransac(points: &[core::Point2f]) -> Option<EnclosingCircle>{
+		if points.len() < 3 {
+			return None;
+		}
+		// deterministic xorshift, same contour gives the same circle
+		let mut state = points.len() as u32 | 1;
+		let mut random = |bound: usize| {
+			state ^= state << 13;
+			state ^= state >> 17;
+			state ^= state << 5;
+			state as usize % bound
+		};
+
+		let mut best: Option<(usize, EnclosingCircle)> = None;
+		for _ in 0..RANSAC_ITERATIONS {
+			let sample = [points[random(points.len())], points[random(points.len())], points[random(points.len())]];
+			let circle = match Self::circle_through(&sample) {
+				Some(circle) => circle,
+				None => continue
+			};
+			let inliers = points.iter().filter(|point| Self::is_inlier(point, &circle)).count();
+			if best.as_ref().map_or(true, |(count, _)| inliers > *count) {
+				best = Some((inliers, circle));
+			}
+		}
+
+		let (_, circle) = best?;
+		let inliers: Vec<core::Point2f> = points.iter().copied().filter(|point| Self::is_inlier(point, &circle)).collect();
+		Self::least_squares(&inliers).or(Some(circle))
+	}
+
+	fn is_inlier(point: &core::Point2f, circle: &EnclosingCircle) -> bool{
+		let offset = *point - circle.position;
+		let distance = (offset.x * offset.x + offset.y * offset.y).sqrt();
+		(distance - circle.radius).abs() <= INLIER_TOLERANCE.0.max(circle.radius * INLIER_TOLERANCE.1)
+	}
+
+	/// Circumscribed circle of three points, None when they are collinear.
+	fn circle_through(points: &[core::Point2f; 3]) -> Option<EnclosingCircle>{
+		let [a, b, c] = points.map(|point| (point.x as f64, point.y as f64));
+		let determinant = 2. * (a.0 * (b.1 - c.1) + b.0 * (c.1 - a.1) + c.0 * (a.1 - b.1));
+		if determinant.abs() < 1e-6 {
+			return None;
+		}
+		let (a2, b2, c2) = (a.0 * a.0 + a.1 * a.1, b.0 * b.0 + b.1 * b.1, c.0 * c.0 + c.1 * c.1);
+		let x = (a2 * (b.1 - c.1) + b2 * (c.1 - a.1) + c2 * (a.1 - b.1)) / determinant;
+		let y = (a2 * (c.0 - b.0) + b2 * (a.0 - c.0) + c2 * (b.0 - a.0)) / determinant;
+		let radius = ((a.0 - x).powi(2) + (a.1 - y).powi(2)).sqrt();
+		Some(EnclosingCircle{position: core::Point2f::new(x as f32, y as f32), radius: radius as f32})
+	}
+
+	/// Algebraic (Kåsa) circle fit, x² + y² + Dx + Ey + F = 0 solved in least squares.
+	fn least_squares(points: &[core::Point2f]) -> Option<EnclosingCircle>{
+		if points.len() < 3 {
+			return None;
+		}
+		let mut normal = [[0f64; 3]; 3];
+		let mut right = [0f64; 3];
+		for point in points {
+			let (x, y) = (point.x as f64, point.y as f64);
+			let row = [x, y, 1.];
+			let value = -(x * x + y * y);
+			for ((normal_row, right_value), factor) in normal.iter_mut().zip(right.iter_mut()).zip(row) {
+				for (cell, other) in normal_row.iter_mut().zip(row) {
+					*cell += factor * other;
+				}
+				*right_value += factor * value;
+			}
+		}
+		let [d, e, f] = Self::solve3(normal, right)?;
+		let (x, y) = (-d / 2., -e / 2.);
+		let squared_radius = x * x + y * y - f;
+		if !(squared_radius > 0.) {
+			return None;
+		}
+		Some(EnclosingCircle{position: core::Point2f::new(x as f32, y as f32), radius: squared_radius.sqrt() as f32})
+	}
+
+	/// Cramer's rule for 3x3 system.
+	fn solve3(matrix: [[f64; 3]; 3], right: [f64; 3]) -> Option<[f64; 3]>{
+		let determinant = |m: &[[f64; 3]; 3]| {
+			m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+				- m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+				+ m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+		};
+		let whole = determinant(&matrix);
+		if whole.abs() < 1e-9 {
+			return None;
+		}
+		let mut solution = [0.; 3];
+		for (column, value) in solution.iter_mut().enumerate() {
+			let mut replaced = matrix;
+			for (replaced_row, right_value) in replaced.iter_mut().zip(right) {
+				replaced_row[column] = right_value;
+			}
+			*value = determinant(&replaced) / whole;
+		}
+		Some(solution)
+	}
+
+	/// Fraction of angular bins around `circle` with an edge point lying on it.
+	fn visible_fraction(points: &[core::Point2f], circle: &EnclosingCircle) -> f64{
+		let mut seen = [false; PERIMETER_BINS];
+		for point in points.iter().filter(|point| Self::is_inlier(point, circle)) {
+			let offset = *point - circle.position;
+			let angle = (offset.y as f64).atan2(offset.x as f64) + std::f64::consts::PI;
+			let bin = (angle / (2. * std::f64::consts::PI) * PERIMETER_BINS as f64) as usize;
+			seen[bin.min(PERIMETER_BINS - 1)] = true;
+		}
+		seen.iter().filter(|seen| **seen).count() as f64 / PERIMETER_BINS as f64
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Points on the arc of circle from `start` to `end` angle, in radians.
+	fn arc(centre: (f32, f32), radius: f32, start: f64, end: f64, count: usize) -> Vec<core::Point2f>{
+		(0..count).map(|index| {
+			let angle = start + (end - start) * index as f64 / count as f64;
+			core::Point2f::new(centre.0 + radius * angle.cos() as f32, centre.1 + radius * angle.sin() as f32)
+		}).collect()
+	}
+
+	fn assert_circle(circle: &EnclosingCircle, centre: (f32, f32), radius: f32, tolerance: f32){
+		assert!((circle.position.x - centre.0).abs() <= tolerance, "x {} != {}", circle.position.x, centre.0);
+		assert!((circle.position.y - centre.1).abs() <= tolerance, "y {} != {}", circle.position.y, centre.1);
+		assert!((circle.radius - radius).abs() <= tolerance, "radius {} != {}", circle.radius, radius);
+	}
+
+	#[test]
+	fn least_squares_fits_points_on_circle(){
+		let points = arc((50., 40.), 20., 0., 2. * std::f64::consts::PI, 24);
+		let circle = RadiusEstimator::least_squares(&points).unwrap();
+		assert_circle(&circle, (50., 40.), 20., 1e-3);
+		// short arc determines the circle as well
+		let circle = RadiusEstimator::least_squares(&arc((50., 40.), 20., 0., 1., 12)).unwrap();
+		assert_circle(&circle, (50., 40.), 20., 1e-2);
+	}
+
+	#[test]
+	fn least_squares_fails_for_line_or_too_few_points(){
+		let line: Vec<core::Point2f> = (0..10).map(|index| core::Point2f::new(index as f32, 2. * index as f32)).collect();
+		assert!(RadiusEstimator::least_squares(&line).is_none());
+		assert!(RadiusEstimator::least_squares(&line[..2]).is_none());
+	}
+
+	#[test]
+	fn ransac_ignores_edge_of_covering_object(){
+		// three quarters of the ball, the rest is covered by straight edge of a hand
+		let mut points = arc((100., 100.), 30., 0., 1.5 * std::f64::consts::PI, 90);
+		let corner = (100., 70.);
+		points.extend((0..30).map(|index| core::Point2f::new(corner.0 + index as f32, corner.1 + index as f32)));
+		let circle = RadiusEstimator::fit_ransac(&points).unwrap();
+		assert_circle(&circle, (100., 100.), 30., 0.5);
+
+		let all = RadiusEstimator::least_squares(&points).unwrap();
+		assert!((all.radius - 30.).abs() > 0.5, "edge points should spoil plain least squares");
+	}
+
+	#[test]
+	fn visible_fraction_counts_covered_perimeter(){
+		let circle = EnclosingCircle{position: core::Point2f::new(0., 0.), radius: 10.};
+		let whole = arc((0., 0.), 10., 0., 2. * std::f64::consts::PI, 360);
+		assert_eq!(RadiusEstimator::visible_fraction(&whole, &circle), 1.);
+		let half = arc((0., 0.), 10., 0., std::f64::consts::PI, 180);
+		assert!((RadiusEstimator::visible_fraction(&half, &circle) - 0.5).abs() <= 1. / PERIMETER_BINS as f64);
+		// points away from the circle are not its edge
+		let inner = arc((0., 0.), 5., 0., 2. * std::f64::consts::PI, 360);
+		assert_eq!(RadiusEstimator::visible_fraction(&inner, &circle), 0.);
+	}
+}
@@ -0,0 +1,132 @@
+use std::path::Path;
+
+use opencv::aruco;
+
+use serde::{Deserialize, Serialize};
+
+use crate::trackers::tracker::TrackerRole;
+use crate::trackers::opencv_trackers::tracker_definitions::DefinitionsFile;
+use crate::trackers::opencv_trackers::light_ball_trackers::light_ball_definitions::Smoothing;
+
+/// Family of printed markers, all markers of the set come from the same dictionary.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MarkerDictionary{
+	#[default]
+	Aruco4x4_50,
+	Aruco5x5_100,
+	Aruco6x6_250,
+	ArucoOriginal,
+	AprilTag16h5,
+	AprilTag25h9,
+	AprilTag36h10,
+	AprilTag36h11
+}
+
+impl MarkerDictionary {
+	pub fn to_predefined(&self) -> aruco::PREDEFINED_DICTIONARY_NAME{
+		use aruco::PREDEFINED_DICTIONARY_NAME::*;
+		match self {
+			MarkerDictionary::Aruco4x4_50 => DICT_4X4_50,
+			MarkerDictionary::Aruco5x5_100 => DICT_5X5_100,
+			MarkerDictionary::Aruco6x6_250 => DICT_6X6_250,
+			MarkerDictionary::ArucoOriginal => DICT_ARUCO_ORIGINAL,
+			MarkerDictionary::AprilTag16h5 => DICT_APRILTAG_16h5,
+			MarkerDictionary::AprilTag25h9 => DICT_APRILTAG_25h9,
+			MarkerDictionary::AprilTag36h10 => DICT_APRILTAG_36h10,
+			MarkerDictionary::AprilTag36h11 => DICT_APRILTAG_36h11
+		}
+	}
+
+	/// Number of markers in the dictionary, valid ids are below it.
+	pub fn size(&self) -> i32{
+		match self {
+			MarkerDictionary::Aruco4x4_50 => 50,
+			MarkerDictionary::Aruco5x5_100 => 100,
+			MarkerDictionary::Aruco6x6_250 => 250,
+			MarkerDictionary::ArucoOriginal => 1024,
+			MarkerDictionary::AprilTag16h5 => 30,
+			MarkerDictionary::AprilTag25h9 => 35,
+			MarkerDictionary::AprilTag36h10 => 2320,
+			MarkerDictionary::AprilTag36h11 => 587
+		}
+	}
+}
+
+/// Single printed marker attached to a prop or calibration object.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MarkerDefinition{
+	pub name: String,
+	#[serde(default)]
+	pub role: TrackerRole,
+	/// id of the marker in the dictionary
+	pub id: i32,
+	/// side of the black square in metres
+	pub size: f64,
+	#[serde(default)]
+	pub smoothing: Smoothing
+}
+
+/// Marker trackers, read from `config/markers.ron` or file in `RTRACK_MARKERS`.
+/// There are no marker trackers without the file.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct MarkerDefinitions{
+	#[serde(default)]
+	pub dictionary: MarkerDictionary,
+	pub markers: Vec<MarkerDefinition>
+}
+
+impl DefinitionsFile for MarkerDefinitions {
+	const FILE_NAME: &'static str = "markers.ron";
+	const ENV_VARIABLE: &'static str = "RTRACK_MARKERS";
+	const FALLBACK: &'static str = "marker trackers are disabled";
+
+	fn validate(&self, path: &Path) -> Result<(), String>{
+		for (index, definition) in self.markers.iter().enumerate() {
+			if definition.name.is_empty() {
+				return Err(format!("Marker {} in {} has no name", index, path.display()));
+			}
+			if let Some(other) = self.markers[..index].iter().find(|other| other.name == definition.name || other.id == definition.id) {
+				return Err(format!("Markers '{}' and '{}' share name or id in {}", other.name, definition.name, path.display()));
+			}
+			if definition.id < 0 || definition.id >= self.dictionary.size() {
+				return Err(format!("Marker '{}' needs id from 0 to {} of {:?}", definition.name, self.dictionary.size() - 1, self.dictionary));
+			}
+			if !(definition.size > 0.) {
+				return Err(format!("Marker '{}' needs positive size", definition.name));
+			}
+			if !(definition.smoothing.position > 0. && definition.smoothing.position <= 1.) {
+				return Err(format!("Smoothing of marker '{}' must be in (0, 1]", definition.name));
+			}
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn marker(name: &str, id: i32) -> MarkerDefinition{
+		MarkerDefinition{name: name.to_owned(), role: TrackerRole::Generic, id, size: 0.05, smoothing: Smoothing::default()}
+	}
+
+	fn validate(dictionary: MarkerDictionary, markers: Vec<MarkerDefinition>) -> Result<(), String>{
+		MarkerDefinitions{dictionary, markers}.validate(Path::new("markers.ron"))
+	}
+
+	#[test]
+	fn duplicate_ids_are_rejected(){
+		assert!(validate(MarkerDictionary::Aruco4x4_50, vec![marker("head", 1), marker("hand", 2)]).is_ok());
+		assert!(validate(MarkerDictionary::Aruco4x4_50, vec![marker("head", 1), marker("hand", 1)]).is_err());
+		assert!(validate(MarkerDictionary::Aruco4x4_50, vec![marker("head", 1), marker("head", 2)]).is_err());
+	}
+
+	#[test]
+	fn ids_outside_dictionary_are_rejected(){
+		let size = MarkerDictionary::AprilTag16h5.size();
+		assert!(validate(MarkerDictionary::AprilTag16h5, vec![marker("head", size - 1)]).is_ok());
+		assert!(validate(MarkerDictionary::AprilTag16h5, vec![marker("head", size)]).is_err());
+		assert!(validate(MarkerDictionary::AprilTag16h5, vec![marker("head", -1)]).is_err());
+		assert!(validate(MarkerDictionary::Aruco6x6_250, vec![marker("head", size)]).is_ok());
+	}
+}
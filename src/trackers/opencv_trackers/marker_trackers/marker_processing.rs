@@ -0,0 +1,30 @@
+use bevy::ecs::prelude as ecs;
+
+use crate::trackers::opencv_trackers::OpencvTrackers;
+use crate::trackers::opencv_trackers::camera_processing::{self, CameraProcessing};
+use crate::trackers::opencv_trackers::opencv_utilities::processing_pipeline;
+
+// ------- Marker Tracker Processing ------- //
+pub struct MarkerTrackerProcessingBuilder;
+
+impl bevy::app::Plugin for MarkerTrackerProcessingBuilder{
+	fn build(&self, app: &mut bevy::prelude::App) {
+		OpencvTrackers::init_schedule(app)
+			.add_system(camera_processing::subscribe_system::<MarkerTrackerProcessing>);
+	}
+}
+
+/// Grayscale frames of single camera for marker trackers.
+/// Frames keep lens distortion, detected corners are undistorted before pose estimation.
+#[derive(ecs::Component, Default)]
+pub struct MarkerTrackerProcessing;
+
+impl CameraProcessing for MarkerTrackerProcessing {
+	const UNDISTORT_FRAMES: bool = false;
+
+	/// Markers are detected in intensity, blur would soften their corners.
+	fn preprocess_pipeline() -> processing_pipeline::ProcessingPipeline{
+		processing_pipeline::ProcessingPipeline::new()
+			.with(processing_pipeline::Grayscale)
+	}
+}
@@ -0,0 +1,361 @@
+use bevy::ecs::prelude as ecs;
+
+use opencv::{
+	prelude::*,
+	core,
+	calib3d,
+	aruco
+};
+
+use crate::entity_spawner;
+use crate::entity_spawner::EntitySpawner;
+use crate::trackers::tracker;
+use crate::trackers::opencv_trackers::OpencvTrackers;
+use crate::trackers::opencv_trackers::frame_synchroniser::FrameSynchroniser;
+use crate::trackers::opencv_trackers::calibration::playspace::Playspace;
+use crate::trackers::opencv_trackers::calibration::undistortion::UndistortionComponent;
+use crate::trackers::opencv_trackers::calibration::intrinsics::CameraIntrinsics;
+use crate::trackers::opencv_trackers::calibration::CalibrationCache;
+use crate::trackers::opencv_trackers::calibration::extrinsics::{CameraExtrinsics, Pose};
+use crate::trackers::opencv_trackers::camera_observer::ObservedCamera;
+use crate::trackers::opencv_trackers::light_ball_trackers::light_ball_definitions::Smoothing;
+use crate::trackers::opencv_trackers::marker_trackers::marker_definitions::{MarkerDefinition, MarkerDefinitions, MarkerDictionary};
+use crate::trackers::opencv_trackers::marker_trackers::marker_processing::MarkerTrackerProcessing;
+use crate::trackers::opencv_trackers::opencv_utilities::{
+	frame_component,
+	window_preview
+};
+
+use std::any::type_name;
+use std::collections::HashMap;
+use std::sync::Mutex;
+// ------- Marker Tracker ------- //
+
+/// Spawns marker trackers from their definitions and registers their systems.
+pub struct MarkerTrackerBuilder{
+	definitions: MarkerDefinitions
+}
+impl MarkerTrackerBuilder {
+	pub fn new(definitions: MarkerDefinitions) -> Self{
+		MarkerTrackerBuilder{definitions: definitions}
+	}
+}
+
+impl EntitySpawner for MarkerDefinition{
+	fn spawn(&self, commands: &mut ecs::Commands) -> ecs::Entity{
+		let mut window_component = window_preview::WindowPreviewComponent::default();
+		window_component.window.set_title(&format!("{} {}", type_name::<MarkerTracker>(), self.name));
+
+		commands.spawn((
+			tracker::TrackerData::default(),
+			self.role,
+			MarkerTracker::from_definition(self),
+			frame_component::FrameComponent::default(),
+			window_component,
+			window_preview::WindowInLayout
+		)).id()
+	}
+}
+
+impl bevy::app::Plugin for MarkerTrackerBuilder{
+	fn build(&self, app: &mut bevy::prelude::App) {
+		let detection = match MarkerDetection::new(self.definitions.dictionary) {
+			Ok(detection) => detection,
+			Err(error) => {
+				println!("Unable to create marker detector: {}, marker trackers are disabled", error);
+				return;
+			}
+		};
+		app.insert_resource(detection);
+		let world = &mut app.world;
+		for definition in &self.definitions.markers {
+			entity_spawner::spawn_from_world(world, definition);
+		}
+
+		OpencvTrackers::init_schedule(app)
+			.add_system(MarkerTracker::update_system);
+	}
+}
+
+/// Dictionary of markers and detector parameters, created once and shared by all marker trackers.
+#[derive(ecs::Resource)]
+pub struct MarkerDetection{
+	dictionary: Mutex<core::Ptr<aruco::Dictionary>>,
+	parameters: Mutex<core::Ptr<aruco::DetectorParameters>>
+}
+
+impl MarkerDetection {
+	pub fn new(dictionary: MarkerDictionary) -> opencv::Result<Self>{
+		Ok(MarkerDetection{
+			dictionary: Mutex::new(aruco::get_predefined_dictionary(dictionary.to_predefined())?),
+			parameters: Mutex::new(aruco::DetectorParameters::create()?)
+		})
+	}
+
+	/// Corners (clockwise from top-left) and ids of markers in grayscale `frame`.
+	pub fn detect(&self, frame: &core::Mat) -> opencv::Result<(core::Vector<core::Vector<core::Point2f>>, core::Vector<i32>)>{
+		let mut corners = core::Vector::<core::Vector<core::Point2f>>::new();
+		let mut ids = core::Vector::<i32>::new();
+		let mut rejected = core::Vector::<core::Vector<core::Point2f>>::new();
+		aruco::detect_markers(
+			frame,
+			&self.dictionary.lock().unwrap(),
+			&mut corners,
+			&mut ids,
+			&self.parameters.lock().unwrap(),
+			&mut rejected,
+			&core::Mat::default(),
+			&core::Mat::default()
+		)?;
+		Ok((corners, ids))
+	}
+}
+
+/// Pose of the marker seen by single camera.
+struct MarkerObservation{
+	camera_id: String,
+	/// transforms points from the marker frame into the camera frame
+	pose: Pose,
+	/// mean side of the marker in pixels, larger markers give more precise poses
+	pixel_size: f64
+}
+
+/// Printed ArUco or AprilTag marker with full 6DoF pose.
+/// Marker frame has origin in the centre of the marker, x right, y up and z out of the printed side.
+#[derive(ecs::Component, Default)]
+pub struct MarkerTracker{
+	name: String,
+	id: i32,
+	/// side of the marker in metres
+	size: f64,
+	smoothing: Smoothing,
+	smoothed_position: Option<[f64; 3]>
+}
+
+impl MarkerTracker {
+	pub fn from_definition(definition: &MarkerDefinition) -> Self{
+		MarkerTracker{
+			name: definition.name.clone(),
+			id: definition.id,
+			size: definition.size,
+			smoothing: definition.smoothing.clone(),
+			..Default::default()
+		}
+	}
+
+	pub fn get_name(&self) -> &str{
+		&self.name
+	}
+
+	pub fn get_id(&self) -> i32{
+		self.id
+	}
+
+	fn update_system(
+		mut commands: ecs::Commands,
+		synchroniser: ecs::Res<FrameSynchroniser>,
+		playspace: ecs::Res<Playspace>,
+		detection: ecs::Res<MarkerDetection>,
+		processing_query: ecs::Query<(&ObservedCamera, Option<&UndistortionComponent>), ecs::With<MarkerTrackerProcessing>>,
+		mut tracker_query: ecs::Query<(ecs::Entity, &mut MarkerTracker, &mut frame_component::FrameComponent, Option<&window_preview::WindowPreviewComponent>), ecs::Without<MarkerTrackerProcessing>>,
+		mut extrinsics: ecs::Local<CalibrationCache<CameraExtrinsics>>,
+		mut last_generation: ecs::Local<u64>
+	){
+		// only frames captured at the same time are processed together
+		let latest_frameset = match synchroniser.lock_new(&mut last_generation) {
+			Some(latest_frameset) => latest_frameset,
+			None => return
+		};
+		let synchronised_frames = latest_frameset.as_ref().map_or(&[][..], |frameset| &frameset.frames[..]);
+
+		let mut trackers: Vec<_> = tracker_query.iter_mut().collect();
+		let mut observations: Vec<Vec<MarkerObservation>> = trackers.iter().map(|_| vec![]).collect();
+		for synchronised_frame in synchronised_frames {
+			let (camera, undistortion) = match processing_query.get(synchronised_frame.source) {
+				Ok(found) => found,
+				Err(_) => continue
+			};
+			extrinsics.load(&camera.camera_id, |camera_id| CameraExtrinsics::load(camera_id).ok());
+			let frame = &synchronised_frame.frame;
+			let (corners, ids) = match detection.detect(frame) {
+				Ok(detected) => detected,
+				Err(_) => continue
+			};
+			let frame_size = match frame.size() {
+				Ok(size) => size,
+				Err(_) => continue
+			};
+			// uncalibrated camera is guessed to have about 53° horizontal field of view
+			let intrinsics = match undistortion {
+				Some(component) => component.get_undistortion().lock().unwrap().get_intrinsics().scaled_to(frame_size),
+				None => CameraIntrinsics::ideal(frame_size, frame_size.width as f64)
+			};
+			let intrinsics = match intrinsics {
+				Ok(intrinsics) => intrinsics,
+				Err(_) => continue
+			};
+
+			// markers are drawn only for preview windows, into their buffers which are reused for frames of the same size
+			let mut previews: Vec<&mut core::Mat> = trackers.iter_mut()
+				.filter(|(_, _, _, preview)| preview.is_some())
+				.map(|(_, _, frame_component, _)| frame_component.get_frame_mut())
+				.collect();
+			if let Some((preview, others)) = previews.split_first_mut() {
+				frame.copy_to(&mut **preview).unwrap_or_default();
+				aruco::draw_detected_markers(&mut **preview, &corners, &ids, core::Scalar::all(255.)).unwrap_or_default();
+				for other in others.iter_mut() {
+					preview.copy_to(&mut **other).unwrap_or_default();
+				}
+			}
+
+			for (index, (_, tracker, _, _)) in trackers.iter_mut().enumerate() {
+				let marker_corners = match ids.iter().position(|id| id == tracker.id) {
+					Some(position) => corners.get(position).unwrap(),
+					None => continue
+				};
+				if let Ok(Some((pose, pixel_size))) = tracker.estimate_pose(&marker_corners, &intrinsics) {
+					observations[index].push(MarkerObservation{camera_id: camera.camera_id.clone(), pose: pose, pixel_size: pixel_size});
+				}
+			}
+		}
+
+		for (index, (entity, tracker, _, _)) in trackers.iter_mut().enumerate() {
+			let entity = *entity;
+			let found = Self::locate(&observations[index], &extrinsics).map(|pose| {
+				let pose = pose.then(playspace.get_alignment());
+				let [x, y, z] = pose.translation;
+				tracker::TrackerData{
+					position: tracker.smooth(tracker::Position{x: x, y: y, z: z}),
+					rotation: tracker::Rotation::from_matrix(&pose.rotation),
+					confidence: 1.
+				}
+			});
+			// lost marker keeps its last pose, only confidence drops
+			tracker::TrackerData::report(&mut commands, entity, found);
+		}
+	}
+
+	/// Pose of the marker in the camera frame from its `corners` and mean side of the marker in pixels.
+	fn estimate_pose(&self, corners: &core::Vector<core::Point2f>, intrinsics: &CameraIntrinsics) -> opencv::Result<Option<(Pose, f64)>>{
+		let half = (self.size / 2.) as f32;
+		// same order as detected corners, required by SOLVEPNP_IPPE_SQUARE
+		let object_points = core::Vector::<core::Point3f>::from_iter([
+			core::Point3f::new(-half, half, 0.),
+			core::Point3f::new(half, half, 0.),
+			core::Point3f::new(half, -half, 0.),
+			core::Point3f::new(-half, -half, 0.)
+		]);
+		let pixels = intrinsics.undistort_pixels(corners)?;
+		if pixels.len() != 4 {
+			return Ok(None);
+		}
+
+		let mut rvec = core::Mat::default();
+		let mut tvec = core::Mat::default();
+		if !calib3d::solve_pnp(&object_points, &pixels, &intrinsics.camera_matrix, &core::Mat::default(), &mut rvec, &mut tvec, false, calib3d::SOLVEPNP_IPPE_SQUARE)? {
+			return Ok(None);
+		}
+
+		let mut perimeter = 0.;
+		for index in 0..4 {
+			let side = pixels.get((index + 1) % 4)? - pixels.get(index)?;
+			perimeter += ((side.x * side.x + side.y * side.y) as f64).sqrt();
+		}
+		Ok(Some((Pose::from_rvec_tvec(&rvec, &tvec)?, perimeter / 4.)))
+	}
+
+	/// Pose of the marker in the tracking frame (reference camera) from cameras calibrated against the most common reference,
+	///  position is mean of their estimates and rotation comes from the camera which sees the marker largest.
+	/// Once any camera is calibrated, uncalibrated cameras are ignored. Without calibrated cameras, pose is in the frame
+	///  of the camera with the lowest id, which doesn't change while the marker moves between views.
+	fn locate(observations: &[MarkerObservation], extrinsics: &CalibrationCache<CameraExtrinsics>) -> Option<Pose>{
+		if !extrinsics.has_any() {
+			let camera_id = extrinsics.get_camera_ids().min()?;
+			return observations.iter()
+				.find(|observation| observation.camera_id == camera_id)
+				.map(|observation| observation.pose);
+		}
+
+		// only cameras calibrated against the same reference share coordinate frame, ties go to the lowest reference id
+		let mut references: HashMap<&str, usize> = HashMap::new();
+		for camera_id in extrinsics.get_camera_ids() {
+			if let Some(camera) = extrinsics.get(camera_id) {
+				*references.entry(camera.reference_camera.as_str()).or_default() += 1;
+			}
+		}
+		let (reference, _) = references.into_iter().max_by(|(a, a_count), (b, b_count)| a_count.cmp(b_count).then(b.cmp(a)))?;
+		let poses: Vec<(Pose, f64)> = observations.iter()
+			.filter_map(|observation| extrinsics.get(&observation.camera_id).map(|camera| (observation, camera)))
+			.filter(|(_, camera)| camera.reference_camera == reference)
+			.map(|(observation, camera)| (observation.pose.then(&camera.pose.inverse()), observation.pixel_size))
+			.collect();
+		let (mut pose, _) = poses.iter().copied().max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+		for axis in 0..3 {
+			pose.translation[axis] = poses.iter().map(|(pose, _)| pose.translation[axis]).sum::<f64>() / poses.len() as f64;
+		}
+		Some(pose)
+	}
+
+	fn smooth(&mut self, position: tracker::Position) -> tracker::Position{
+		self.smoothing.smooth(&mut self.smoothed_position, position)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Pose rotated by `angle` radians around z axis.
+	fn pose(angle: f64, translation: [f64; 3]) -> Pose{
+		let (sin, cos) = angle.sin_cos();
+		Pose{rotation: [[cos, -sin, 0.], [sin, cos, 0.], [0., 0., 1.]], translation}
+	}
+
+	fn observation(camera_id: &str, pose: Pose, pixel_size: f64) -> MarkerObservation{
+		MarkerObservation{camera_id: camera_id.to_owned(), pose, pixel_size}
+	}
+
+	fn assert_pose_near(actual: &Pose, expected: &Pose){
+		for row in 0..3 {
+			assert!((actual.translation[row] - expected.translation[row]).abs() < 1e-9, "{:?} != {:?}", actual, expected);
+			for column in 0..3 {
+				assert!((actual.rotation[row][column] - expected.rotation[row][column]).abs() < 1e-9, "{:?} != {:?}", actual, expected);
+			}
+		}
+	}
+
+	#[test]
+	fn uncalibrated_pose_is_in_frame_of_lowest_camera_id(){
+		let mut extrinsics = CalibrationCache::<CameraExtrinsics>::default();
+		for camera_id in ["b", "a"] {
+			extrinsics.load(camera_id, |_| None);
+		}
+		let seen_by_a = pose(0.3, [0.1, 0.2, 1.5]);
+		let observations = [observation("b", pose(-0.4, [0.5, 0., 2.]), 40.), observation("a", seen_by_a, 20.)];
+		assert_pose_near(&MarkerTracker::locate(&observations, &extrinsics).unwrap(), &seen_by_a);
+		// camera b alone doesn't share frame with camera a
+		assert!(MarkerTracker::locate(&observations[..1], &extrinsics).is_none());
+	}
+
+	#[test]
+	fn calibrated_views_are_averaged(){
+		let camera_b = CameraExtrinsics{pose: pose(0.5, [-0.5, 0.1, 0.]), reference_camera: "a".to_owned(), reprojection_error: 0.};
+		let mut extrinsics = CalibrationCache::<CameraExtrinsics>::default();
+		extrinsics.load("a", |_| Some(CameraExtrinsics{pose: Pose::default(), reference_camera: "a".to_owned(), reprojection_error: 0.}));
+		extrinsics.load("b", |_| Some(camera_b.clone()));
+		extrinsics.load("c", |_| None);
+
+		// each view is off by 1 cm in opposite directions, rotation of b differs and b sees the marker larger
+		let in_reference = pose(0.2, [0.1, 0.2, 2.]);
+		let seen_by_a = pose(0.2, [0.11, 0.2, 2.]);
+		let seen_by_b = pose(0.25, [0.09, 0.2, 2.]).then(&camera_b.pose);
+		let observations = [
+			observation("a", seen_by_a, 20.),
+			observation("b", seen_by_b, 40.),
+			// uncalibrated camera is ignored once any camera is calibrated
+			observation("c", pose(0., [5., 5., 5.]), 80.)
+		];
+		let located = MarkerTracker::locate(&observations, &extrinsics).unwrap();
+		assert_pose_near(&located, &Pose{rotation: pose(0.25, [0.; 3]).rotation, translation: in_reference.translation});
+	}
+}
@@ -0,0 +1,22 @@
+pub mod marker_tracker;
+pub mod marker_definitions;
+mod marker_processing;
+use bevy::app::Plugin;
+
+use crate::trackers::opencv_trackers::tracker_definitions::DefinitionsFile;
+
+pub fn setup_entities(app: &mut bevy::prelude::App){
+	// grayscale processing of every camera is needed only when some marker is defined
+	let definitions = marker_definitions::MarkerDefinitions::from_env();
+	if definitions.markers.is_empty() {
+		return;
+	}
+	let setup_list: Vec<Box<dyn Plugin>> = vec![
+		Box::new(marker_tracker::MarkerTrackerBuilder::new(definitions)),
+		Box::new(marker_processing::MarkerTrackerProcessingBuilder),
+	];
+
+	for entity_builder in setup_list{
+		entity_builder.build(app);
+	}
+}
@@ -1,9 +1,19 @@
+pub mod calibration;
 pub mod camera;
 pub mod camera_observer;
+pub mod camera_processing;
+pub mod camera_recorder;
+pub mod frame_synchroniser;
+pub mod network_camera;
 pub mod opencv_utilities;
+pub mod tracker_definitions;
+pub mod triangulation;
 pub mod light_ball_trackers;
+pub mod ir_trackers;
+pub mod marker_trackers;
 
 use bevy::ecs::prelude as ecs;
+use bevy::ecs::prelude::IntoSystemConfig;
 
 
 #[derive(bevy::ecs::schedule::ScheduleLabel,Debug, PartialEq, Eq, Hash, Clone)]
@@ -22,14 +32,32 @@ impl OpencvTrackers {
 	}
 }
 
+/// Turns camera id (bus info, path or url) into string usable as file or directory name.
+pub fn file_name_from_id(camera_id: &str) -> String{
+	camera_id.chars()
+		.map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+		.collect()
+}
+
 pub fn setup_entities(app: &mut bevy::prelude::App) {
 	// TODO make it run before debug render phase and before exposing it to OpenXR
 	app.add_system(OpencvTrackers::run_schedule);
+	app.insert_resource(camera::CameraFilter::from_env());
+	app.insert_resource(camera_recorder::CameraRecorder::from_env());
+	app.add_system(camera_recorder::CameraRecorder::toggle_system);
+	app.insert_resource(frame_synchroniser::FrameSynchroniser::from_env());
+	app.insert_resource(triangulation::Triangulator::from_env());
+	app.insert_resource(calibration::playspace::Playspace::from_env());
+	app.add_system(calibration::playspace::Playspace::input_system);
 
 	OpencvTrackers::init_schedule(app)
 		.add_system(camera_observer::CameraObservers::assignment_system)
-		.add_system(camera_observer::CameraObservers::update_system);
+		.add_system(camera_observer::CameraObservers::update_system)
+		.add_system(frame_synchroniser::FrameSynchroniser::collect_system.after(camera_observer::CameraObservers::update_system))
+		.add_system(frame_synchroniser::FrameSynchroniser::report_system);
 			
 	opencv_utilities::setup_entities(app);
 	light_ball_trackers::setup_entities(app);
+	ir_trackers::setup_entities(app);
+	marker_trackers::setup_entities(app);
 }
\ No newline at end of file
@@ -0,0 +1,363 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use opencv::{
+    prelude::*,
+    imgcodecs
+};
+
+use crate::trackers::opencv_trackers::camera_observer::FrameSource;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// JPEG of single frame and time it was received.
+type JpegResult = std::io::Result<(Instant, Vec<u8>)>;
+
+/// Frame source reading MJPEG stream (multipart/x-mixed-replace) over HTTP, e.g. from phone or Raspberry Pi camera apps.
+/// Stream is connected and read on separate thread, so slow or unreachable host doesn't block other cameras,
+///  when connection fails error is reported on next grab and CameraObserver takes care of reconnecting.
+pub struct NetworkCameraSource{
+	receiver: mpsc::Receiver<JpegResult>,
+	grabbed: Option<Vec<u8>>
+}
+
+impl NetworkCameraSource {
+	/// Starts connecting to `url` in form of `http://host[:port][/path]` and reading the stream in background.
+	pub fn connect(url: &str) -> opencv::Result<Self>{
+		let to_opencv_error = |error: std::io::Error| {
+			opencv::Error::new(opencv::core::StsError, format!("Unable to open stream {}: {}", url, error))
+		};
+		let address = StreamAddress::parse(url).map_err(to_opencv_error)?;
+
+		let (sender, receiver) = mpsc::channel();
+		let url = url.to_owned();
+		std::thread::spawn(move || {
+			match Self::open_stream(&address) {
+				Ok((reader, boundary)) => Self::read_stream(reader, boundary, sender),
+				Err(error) => {
+					let error = std::io::Error::new(error.kind(), format!("Unable to open stream {}: {}", url, error));
+					sender.send(Err(error)).unwrap_or_default();
+				}
+			}
+		});
+		Ok(NetworkCameraSource{receiver: receiver, grabbed: None})
+	}
+
+	fn open_stream(address: &StreamAddress) -> std::io::Result<(BufReader<TcpStream>, Vec<u8>)>{
+		let mut stream = Self::connect_any(&address.host)?;
+		stream.set_read_timeout(Some(READ_TIMEOUT))?;
+		stream.set_write_timeout(Some(READ_TIMEOUT))?;
+		write!(stream, "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: rtrack\r\nAccept: multipart/x-mixed-replace\r\n\r\n", address.path, address.authority)?;
+
+		let mut reader = BufReader::new(stream);
+		let status = read_line(&mut reader)?;
+		if status.split_whitespace().nth(1) != Some("200") {
+			return Err(invalid_data(format!("unexpected response '{}'", status)));
+		}
+
+		let mut boundary = None;
+		loop {
+			let header = read_line(&mut reader)?;
+			if header.is_empty() {
+				break;
+			}
+			if let Some((name, value)) = header.split_once(':') {
+				if name.trim().eq_ignore_ascii_case("content-type") {
+					boundary = Self::parse_boundary(value);
+				}
+			}
+		}
+
+		let boundary = boundary.ok_or_else(|| invalid_data("response is not multipart/x-mixed-replace".to_owned()))?;
+		Ok((reader, boundary))
+	}
+
+	/// Connects to the first reachable address of `host`, waiting at most CONNECT_TIMEOUT for each.
+	fn connect_any(host: &str) -> std::io::Result<TcpStream>{
+		let mut last_error = invalid_data(format!("{} has no address", host));
+		for socket_address in host.to_socket_addrs()? {
+			match TcpStream::connect_timeout(&socket_address, CONNECT_TIMEOUT) {
+				Ok(stream) => return Ok(stream),
+				Err(error) => last_error = error
+			}
+		}
+		Err(last_error)
+	}
+
+	fn parse_boundary(content_type: &str) -> Option<Vec<u8>>{
+		let mut parameters = content_type.split(';').map(str::trim);
+		if !parameters.next()?.eq_ignore_ascii_case("multipart/x-mixed-replace") {
+			return None;
+		}
+		let boundary = parameters
+			.filter_map(|parameter| parameter.split_once('='))
+			.find(|(name, _)| name.trim().eq_ignore_ascii_case("boundary"))
+			.map(|(_, value)| value.trim().trim_matches('"'))?;
+		// some servers already include leading dashes in the parameter
+		let boundary = boundary.strip_prefix("--").unwrap_or(boundary);
+		Some(format!("--{}", boundary).into_bytes())
+	}
+
+	fn read_stream(mut reader: BufReader<TcpStream>, boundary: Vec<u8>, sender: mpsc::Sender<JpegResult>){
+		loop {
+			let result = Self::read_part(&mut reader, &boundary).map(|jpeg| (Instant::now(), jpeg));
+			let failed = result.is_err();
+			// receiver is gone when the source was dropped
+			if sender.send(result).is_err() || failed {
+				return;
+			}
+		}
+	}
+
+	fn read_part(reader: &mut BufReader<TcpStream>, boundary: &[u8]) -> std::io::Result<Vec<u8>>{
+		let mut line = vec![];
+		loop {
+			line.clear();
+			if reader.read_until(b'\n', &mut line)? == 0 {
+				return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "stream closed"));
+			}
+			if line.starts_with(boundary) {
+				break;
+			}
+		}
+
+		let mut content_length = None;
+		loop {
+			let header = read_line(reader)?;
+			if header.is_empty() {
+				break;
+			}
+			if let Some((name, value)) = header.split_once(':') {
+				if name.trim().eq_ignore_ascii_case("content-length") {
+					content_length = value.trim().parse::<usize>().ok();
+				}
+			}
+		}
+
+		let mut jpeg = vec![];
+		match content_length {
+			Some(length) => {
+				jpeg.resize(length, 0);
+				reader.read_exact(&mut jpeg)?;
+			},
+			None => {
+				// without length, JPEG ends with EOI marker 0xFF 0xD9
+				while !jpeg.ends_with(&[0xFF, 0xD9]) {
+					if reader.read_until(0xD9, &mut jpeg)? == 0 {
+						return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "stream closed"));
+					}
+				}
+			}
+		}
+		Ok(jpeg)
+	}
+}
+
+impl FrameSource for NetworkCameraSource {
+	fn grab_frame(&mut self) -> opencv::Result<Option<Instant>> {
+		// only the newest frame is decoded, older ones would just add latency
+		let mut latest = None;
+		loop {
+			match self.receiver.try_recv() {
+				Ok(jpeg) => latest = Some(jpeg),
+				Err(mpsc::TryRecvError::Empty) => break,
+				Err(mpsc::TryRecvError::Disconnected) => {
+					if latest.is_none() {
+						return Err(opencv::Error::new(opencv::core::StsError, "Stream reader stopped".to_owned()));
+					}
+					break;
+				}
+			}
+		}
+
+		match latest {
+			Some(Ok((received, jpeg))) => {
+				self.grabbed = Some(jpeg);
+				Ok(Some(received))
+			},
+			Some(Err(error)) => Err(opencv::Error::new(opencv::core::StsError, error.to_string())),
+			None => Ok(None)
+		}
+	}
+
+	fn retrieve_frame(&mut self, frame: &mut Mat) -> opencv::Result<bool> {
+		match self.grabbed.take() {
+			Some(jpeg) => {
+				*frame = imgcodecs::imdecode(&opencv::core::Vector::<u8>::from_slice(&jpeg), imgcodecs::IMREAD_COLOR)?;
+				Ok(!frame.empty())
+			},
+			None => Ok(false)
+		}
+	}
+}
+
+/// Parts of `http://host[:port][/path]` url.
+struct StreamAddress{
+	/// host with port
+	host: String,
+	/// host as written in the url, for Host header
+	authority: String,
+	path: String
+}
+
+impl StreamAddress {
+	fn parse(url: &str) -> std::io::Result<Self>{
+		let address = url.strip_prefix("http://")
+			.ok_or_else(|| invalid_data("only http:// streams are supported".to_owned()))?;
+		let (authority, path) = match address.find('/') {
+			Some(index) => (&address[..index], &address[index..]),
+			None => (address, "/")
+		};
+		if authority.is_empty() {
+			return Err(invalid_data("missing host".to_owned()));
+		}
+		let host = if authority.contains(':') { authority.to_owned() } else { format!("{}:80", authority) };
+		Ok(StreamAddress{host: host, authority: authority.to_owned(), path: path.to_owned()})
+	}
+}
+
+fn read_line(reader: &mut BufReader<TcpStream>) -> std::io::Result<String>{
+	let mut line = vec![];
+	reader.read_until(b'\n', &mut line)?;
+	Ok(String::from_utf8_lossy(&line).trim_end().to_owned())
+}
+
+fn invalid_data(message: String) -> std::io::Error{
+	std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::net::TcpListener;
+
+	const BOUNDARY: &str = "rtrackframe";
+
+	/// Uniform JPEG image with every channel equal to `value`.
+	fn jpeg(value: f64) -> Vec<u8>{
+		let image = Mat::new_rows_cols_with_default(16, 16, opencv::core::CV_8UC3, opencv::core::Scalar::all(value)).unwrap();
+		let mut buffer = opencv::core::Vector::<u8>::new();
+		imgcodecs::imencode(".jpg", &image, &mut buffer, &opencv::core::Vector::new()).unwrap();
+		buffer.to_vec()
+	}
+
+	/// Local stand-in for camera app, serves `frames` to single client and closes the connection when `close` receives.
+	fn serve(listener: TcpListener, frames: Vec<Vec<u8>>, close: mpsc::Receiver<()>) -> std::thread::JoinHandle<()>{
+		std::thread::spawn(move || {
+			let (mut stream, _) = listener.accept().unwrap();
+			let mut request = BufReader::new(stream.try_clone().unwrap());
+			while !read_line(&mut request).unwrap().is_empty() {}
+
+			write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: multipart/x-mixed-replace; boundary=\"{}\"\r\n\r\n", BOUNDARY).unwrap();
+			for (index, frame) in frames.iter().enumerate() {
+				write!(stream, "--{}\r\nContent-Type: image/jpeg\r\n", BOUNDARY).unwrap();
+				// every other part has no length, so JPEG end has to be found by its EOI marker
+				if index % 2 == 0 {
+					write!(stream, "Content-Length: {}\r\n", frame.len()).unwrap();
+				}
+				write!(stream, "\r\n").unwrap();
+				stream.write_all(frame).unwrap();
+				write!(stream, "\r\n").unwrap();
+			}
+			close.recv().unwrap_or_default();
+		})
+	}
+
+	fn next_frame(source: &mut NetworkCameraSource) -> opencv::Result<Mat>{
+		let deadline = Instant::now() + Duration::from_secs(5);
+		loop {
+			if source.grab_frame()?.is_some() {
+				let mut frame = Mat::default();
+				if source.retrieve_frame(&mut frame)? {
+					return Ok(frame);
+				}
+			}
+			assert!(Instant::now() < deadline, "no frame received in time");
+			std::thread::sleep(Duration::from_millis(5));
+		}
+	}
+
+	fn channel_value(frame: &Mat) -> u8{
+		frame.at_2d::<opencv::core::Vec3b>(8, 8).unwrap()[0]
+	}
+
+	#[test]
+	fn parses_boundary_of_multipart_content_type(){
+		assert_eq!(NetworkCameraSource::parse_boundary(" multipart/x-mixed-replace; boundary=frame"), Some(b"--frame".to_vec()));
+		assert_eq!(NetworkCameraSource::parse_boundary("multipart/x-mixed-replace;boundary=\"--frame\""), Some(b"--frame".to_vec()));
+		assert_eq!(NetworkCameraSource::parse_boundary("Multipart/X-Mixed-Replace; charset=utf-8; Boundary=frame"), Some(b"--frame".to_vec()));
+		assert_eq!(NetworkCameraSource::parse_boundary("image/jpeg"), None);
+		assert_eq!(NetworkCameraSource::parse_boundary("multipart/x-mixed-replace"), None);
+	}
+
+	#[test]
+	fn parses_stream_url(){
+		let address = StreamAddress::parse("http://192.168.1.20:8080/video").unwrap();
+		assert_eq!(address.host, "192.168.1.20:8080");
+		assert_eq!(address.path, "/video");
+		let address = StreamAddress::parse("http://raspberrypi.local").unwrap();
+		assert_eq!(address.host, "raspberrypi.local:80");
+		assert_eq!(address.authority, "raspberrypi.local");
+		assert_eq!(address.path, "/");
+		assert!(StreamAddress::parse("rtsp://camera/stream").is_err());
+		assert!(StreamAddress::parse("http:///video").is_err());
+	}
+
+	#[test]
+	fn reads_parts_with_and_without_length(){
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let address = StreamAddress::parse(&format!("http://{}/video", listener.local_addr().unwrap())).unwrap();
+		let frames = vec![jpeg(40.), jpeg(200.)];
+		let (close, closed) = mpsc::channel();
+		let server = serve(listener, frames.clone(), closed);
+
+		let (mut reader, boundary) = NetworkCameraSource::open_stream(&address).unwrap();
+		assert_eq!(boundary, format!("--{}", BOUNDARY).into_bytes());
+		assert_eq!(NetworkCameraSource::read_part(&mut reader, &boundary).unwrap(), frames[0]);
+		assert_eq!(NetworkCameraSource::read_part(&mut reader, &boundary).unwrap(), frames[1]);
+
+		close.send(()).unwrap();
+		server.join().unwrap();
+		let error = NetworkCameraSource::read_part(&mut reader, &boundary).unwrap_err();
+		assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+	}
+
+	#[test]
+	fn decodes_frames_and_reconnects_after_server_closes(){
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("http://{}/video", listener.local_addr().unwrap());
+		let (close, closed) = mpsc::channel();
+		let server = serve(listener.try_clone().unwrap(), vec![jpeg(200.)], closed);
+
+		let mut source = NetworkCameraSource::connect(&url).unwrap();
+		let frame = next_frame(&mut source).unwrap();
+		assert_eq!((frame.cols(), frame.rows()), (16, 16));
+		assert!(channel_value(&frame).abs_diff(200) <= 2);
+
+		// closed stream is reported as error, so CameraObserver reconnects
+		close.send(()).unwrap();
+		server.join().unwrap();
+		assert!(next_frame(&mut source).is_err());
+
+		let (close, closed) = mpsc::channel();
+		let server = serve(listener, vec![jpeg(40.)], closed);
+		let mut source = NetworkCameraSource::connect(&url).unwrap();
+		let frame = next_frame(&mut source).unwrap();
+		assert!(channel_value(&frame).abs_diff(40) <= 2);
+		close.send(()).unwrap();
+		server.join().unwrap();
+	}
+
+	#[test]
+	fn reports_unreachable_host_without_blocking(){
+		// nothing listens on the port once the listener is dropped
+		let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+		let started = Instant::now();
+		let mut source = NetworkCameraSource::connect(&format!("http://127.0.0.1:{}/video", port)).unwrap();
+		assert!(started.elapsed() < Duration::from_millis(100));
+		assert!(next_frame(&mut source).is_err());
+	}
+}
@@ -1,27 +1,31 @@
 use opencv::prelude as cv;
 use bevy::ecs::prelude as ecs;
 
-pub type ProcessingFunction = fn(dest: &mut cv::Mat, src: &cv::Mat) -> opencv::Result<()>;
+use crate::trackers::opencv_trackers::opencv_utilities::processing_pipeline::ProcessingPipeline;
 
 
 #[derive(ecs::Component)]
 pub struct FrameComponent{
 	frame: std::sync::Mutex<cv::Mat>,
-	pub processing_function: ProcessingFunction 
+	timestamp: Option<std::time::Instant>,
+	/// applied to frames fed by `apply`, parameters can be changed at runtime
+	pipeline: std::sync::Mutex<ProcessingPipeline>
 }
 
 
 impl FrameComponent {
-	pub fn new(processing_function: ProcessingFunction) -> Self{
+	pub fn new(pipeline: ProcessingPipeline) -> Self{
 		FrameComponent{
 			frame: std::sync::Mutex::new(cv::Mat::default()),
-			processing_function: processing_function
+			timestamp: None,
+			pipeline: std::sync::Mutex::new(pipeline)
 		}
 	}
-	pub fn new_with_frame(processing_function: ProcessingFunction, frame: cv::Mat) -> Self{
+	pub fn new_with_frame(pipeline: ProcessingPipeline, frame: cv::Mat) -> Self{
 		FrameComponent{
 			frame: std::sync::Mutex::new(frame),
-			processing_function: processing_function
+			timestamp: None,
+			pipeline: std::sync::Mutex::new(pipeline)
 		}
 	}
 
@@ -29,16 +33,32 @@ impl FrameComponent {
 		&self.frame
 	}
 
+	/// Frame to be filled in place, so its buffer is reused between frames of the same size.
+	pub fn get_frame_mut(&mut self) -> &mut cv::Mat {
+		self.frame.get_mut().unwrap()
+	}
+
+	pub fn get_pipeline(&self) -> &std::sync::Mutex<ProcessingPipeline> {
+		&self.pipeline
+	}
+
+	/// Time when the frame was grabbed from camera (received for network cameras), None when frame didn't come from camera.
+	pub fn get_timestamp(&self) -> Option<std::time::Instant> {
+		self.timestamp
+	}
+
+	pub fn set_timestamp(&mut self, timestamp: Option<std::time::Instant>) {
+		self.timestamp = timestamp;
+	}
+
 	pub fn apply(&mut self, other_frame: &cv::Mat) -> opencv::Result<()>{
-		let mut frame = self.frame.lock().unwrap();
-		(self.processing_function)(&mut frame, other_frame)
+		let frame = self.frame.get_mut().unwrap();
+		self.pipeline.get_mut().unwrap().process(frame, other_frame)
 	}
 
 	pub fn process(&self, other_frame: &cv::Mat) -> opencv::Result<cv::Mat>{
 		let mut frame = cv::Mat::default();
-		if let Err(error) = (self.processing_function)(&mut frame, other_frame){
-			return Err(error)
-		}
+		self.pipeline.lock().unwrap().process(&mut frame, other_frame)?;
 		Ok(frame)
 	}
 
@@ -46,19 +66,10 @@ impl FrameComponent {
 		let mut frame = self.frame.lock().unwrap();
 		*frame = other_frame;
 	}
-
-	pub fn default_processing_function(dest: &mut cv::Mat, src: &cv::Mat) -> opencv::Result<()>{
-		
-		dest.clone_from(src);
-		Ok(())
-	}
 }
 
 impl Default for FrameComponent {
 	fn default() -> Self {
-		FrameComponent{
-			frame: std::sync::Mutex::new(cv::Mat::default()),
-			processing_function: FrameComponent::default_processing_function
-		}
+		FrameComponent::new(ProcessingPipeline::default())
 	}
 }
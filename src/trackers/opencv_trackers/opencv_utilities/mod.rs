@@ -1,5 +1,6 @@
 pub mod window_preview;
 pub mod frame_component;
+pub mod processing_pipeline;
 
 use crate::trackers::opencv_trackers::OpencvTrackers;
 
@@ -7,4 +8,4 @@ pub fn setup_entities(app: &mut bevy::prelude::App){
 	OpencvTrackers::init_schedule(app)
 		.add_system(window_preview::WindowPreviewComponent::window_display_system)
 		.add_system(window_preview::WindowPreviewComponent::window_layout_system);
-}
\ No newline at end of file
+}
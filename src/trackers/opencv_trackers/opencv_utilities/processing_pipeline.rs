@@ -0,0 +1,437 @@
+use opencv::prelude as cv;
+use opencv::prelude::MatTraitConst;
+
+use crate::trackers::opencv_trackers::calibration::undistortion::Undistortion;
+
+/// Single step of frame processing, which can hold its own parameters and state.
+pub trait ProcessingStage: Send {
+	/// Name identifying the stage in its pipeline.
+	fn get_name(&self) -> &'static str;
+
+	fn process(&mut self, dest: &mut cv::Mat, src: &cv::Mat) -> opencv::Result<()>;
+
+	/// Tunable parameters with their current values.
+	fn get_parameters(&self) -> Vec<(&'static str, f64)>{
+		vec![]
+	}
+
+	fn set_parameter(&mut self, name: &str, _value: f64) -> Result<(), String>{
+		Err(format!("Stage {} has no parameter {}", self.get_name(), name))
+	}
+}
+
+/// Stages applied one after another, empty pipeline copies the frame as is.
+#[derive(Default)]
+pub struct ProcessingPipeline{
+	stages: Vec<Box<dyn ProcessingStage>>
+}
+
+impl ProcessingPipeline {
+	pub fn new() -> Self{
+		ProcessingPipeline::default()
+	}
+
+	pub fn with(mut self, stage: impl ProcessingStage + 'static) -> Self{
+		self.stages.push(Box::new(stage));
+		self
+	}
+
+	pub fn push(&mut self, stage: Box<dyn ProcessingStage>){
+		self.stages.push(stage);
+	}
+
+	pub fn insert(&mut self, index: usize, stage: Box<dyn ProcessingStage>){
+		self.stages.insert(index, stage);
+	}
+
+	pub fn is_empty(&self) -> bool{
+		self.stages.is_empty()
+	}
+
+	pub fn get_stages(&self) -> &[Box<dyn ProcessingStage>]{
+		&self.stages
+	}
+
+	pub fn get_stage_mut(&mut self, name: &str) -> Option<&mut Box<dyn ProcessingStage>>{
+		self.stages.iter_mut().find(|stage| stage.get_name() == name)
+	}
+
+	pub fn set_parameter(&mut self, stage_name: &str, parameter: &str, value: f64) -> Result<(), String>{
+		self.get_stage_mut(stage_name)
+			.ok_or_else(|| format!("There is no stage {}", stage_name))?
+			.set_parameter(parameter, value)
+	}
+
+	pub fn process(&mut self, dest: &mut cv::Mat, src: &cv::Mat) -> opencv::Result<()>{
+		let mut stages = self.stages.iter_mut();
+		let first = match stages.next() {
+			Some(first) => first,
+			None => {
+				dest.clone_from(src);
+				return Ok(());
+			}
+		};
+		first.process(dest, src)?;
+		for stage in stages {
+			let input = std::mem::take(dest);
+			stage.process(dest, &input)?;
+		}
+		Ok(())
+	}
+}
+
+impl std::fmt::Display for ProcessingPipeline {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let stages: Vec<String> = self.stages.iter().map(|stage| {
+			let parameters: Vec<String> = stage.get_parameters().iter()
+				.map(|(name, value)| format!("{}={}", name, value))
+				.collect();
+			format!("{}({})", stage.get_name(), parameters.join(", "))
+		}).collect();
+		write!(f, "{}", stages.join(" -> "))
+	}
+}
+
+fn parse_count(name: &str, value: f64) -> Result<i32, String>{
+	if !(value >= 0.) || value.fract() != 0. {
+		return Err(format!("{} must be non-negative integer", name));
+	}
+	Ok(value as i32)
+}
+
+pub struct GaussianBlur{
+	/// odd size of square kernel in pixels
+	pub kernel_size: i32
+}
+
+impl ProcessingStage for GaussianBlur {
+	fn get_name(&self) -> &'static str{
+		"blur"
+	}
+
+	fn process(&mut self, dest: &mut cv::Mat, src: &cv::Mat) -> opencv::Result<()>{
+		if self.kernel_size <= 1 {
+			dest.clone_from(src);
+			return Ok(());
+		}
+		opencv::imgproc::gaussian_blur(
+			src,
+			dest,
+			opencv::core::Size{width: self.kernel_size, height: self.kernel_size},
+			0.0, 0.0,
+			opencv::core::BORDER_DEFAULT
+		)
+	}
+
+	fn get_parameters(&self) -> Vec<(&'static str, f64)>{
+		vec![("kernel_size", self.kernel_size as f64)]
+	}
+
+	fn set_parameter(&mut self, name: &str, value: f64) -> Result<(), String>{
+		match name {
+			"kernel_size" => {
+				let kernel_size = parse_count(name, value)?;
+				if kernel_size > 1 && kernel_size % 2 == 0 {
+					return Err("kernel_size must be odd".to_owned());
+				}
+				self.kernel_size = kernel_size;
+				Ok(())
+			},
+			_ => Err(format!("Stage {} has no parameter {}", self.get_name(), name))
+		}
+	}
+}
+
+pub struct ConvertColor{
+	/// `imgproc::COLOR_*` conversion code
+	pub code: i32
+}
+
+impl ProcessingStage for ConvertColor {
+	fn get_name(&self) -> &'static str{
+		"convert_color"
+	}
+
+	fn process(&mut self, dest: &mut cv::Mat, src: &cv::Mat) -> opencv::Result<()>{
+		opencv::imgproc::cvt_color(src, dest, self.code, 0)
+	}
+
+	fn get_parameters(&self) -> Vec<(&'static str, f64)>{
+		vec![("code", self.code as f64)]
+	}
+}
+
+/// Single channel intensity, frames which are already single channel (e.g. IR cameras) are copied.
+pub struct Grayscale;
+
+impl ProcessingStage for Grayscale {
+	fn get_name(&self) -> &'static str{
+		"grayscale"
+	}
+
+	fn process(&mut self, dest: &mut cv::Mat, src: &cv::Mat) -> opencv::Result<()>{
+		match src.channels() {
+			1 => {
+				dest.clone_from(src);
+				Ok(())
+			},
+			4 => opencv::imgproc::cvt_color(src, dest, opencv::imgproc::COLOR_BGRA2GRAY, 0),
+			_ => opencv::imgproc::cvt_color(src, dest, opencv::imgproc::COLOR_BGR2GRAY, 0)
+		}
+	}
+}
+
+/// Binary threshold of single channel frame.
+pub struct Threshold{
+	pub threshold: f64,
+	pub maximum: f64
+}
+
+impl ProcessingStage for Threshold {
+	fn get_name(&self) -> &'static str{
+		"threshold"
+	}
+
+	fn process(&mut self, dest: &mut cv::Mat, src: &cv::Mat) -> opencv::Result<()>{
+		opencv::imgproc::threshold(src, dest, self.threshold, self.maximum, opencv::imgproc::THRESH_BINARY)?;
+		Ok(())
+	}
+
+	fn get_parameters(&self) -> Vec<(&'static str, f64)>{
+		vec![("threshold", self.threshold), ("maximum", self.maximum)]
+	}
+
+	fn set_parameter(&mut self, name: &str, value: f64) -> Result<(), String>{
+		match name {
+			"threshold" => self.threshold = value,
+			"maximum" => self.maximum = value,
+			_ => return Err(format!("Stage {} has no parameter {}", self.get_name(), name))
+		}
+		Ok(())
+	}
+}
+
+/// Erosion followed by dilation (opening), removes small specks from a mask.
+pub struct Morphology{
+	pub erode_iterations: i32,
+	pub dilate_iterations: i32
+}
+
+impl ProcessingStage for Morphology {
+	fn get_name(&self) -> &'static str{
+		"morphology"
+	}
+
+	fn process(&mut self, dest: &mut cv::Mat, src: &cv::Mat) -> opencv::Result<()>{
+		let anchor = opencv::core::Point::new(-1, -1);
+		let border_value = opencv::imgproc::morphology_default_border_value()?;
+
+		let mut eroded = cv::Mat::default();
+		opencv::imgproc::erode(
+			src,
+			&mut eroded,
+			&cv::Mat::default(),
+			anchor,
+			self.erode_iterations,
+			opencv::core::BORDER_CONSTANT,
+			border_value
+		)?;
+		opencv::imgproc::dilate(
+			&eroded,
+			dest,
+			&cv::Mat::default(),
+			anchor,
+			self.dilate_iterations,
+			opencv::core::BORDER_CONSTANT,
+			border_value
+		)
+	}
+
+	fn get_parameters(&self) -> Vec<(&'static str, f64)>{
+		vec![("erode", self.erode_iterations as f64), ("dilate", self.dilate_iterations as f64)]
+	}
+
+	fn set_parameter(&mut self, name: &str, value: f64) -> Result<(), String>{
+		match name {
+			"erode" => self.erode_iterations = parse_count(name, value)?,
+			"dilate" => self.dilate_iterations = parse_count(name, value)?,
+			_ => return Err(format!("Stage {} has no parameter {}", self.get_name(), name))
+		}
+		Ok(())
+	}
+}
+
+/// Crops the frame to `region`, clipped by frame borders. Empty region keeps the whole frame.
+#[derive(Default)]
+pub struct RegionOfInterest{
+	pub region: opencv::core::Rect
+}
+
+impl ProcessingStage for RegionOfInterest {
+	fn get_name(&self) -> &'static str{
+		"roi"
+	}
+
+	fn process(&mut self, dest: &mut cv::Mat, src: &cv::Mat) -> opencv::Result<()>{
+		let x = self.region.x.max(0);
+		let y = self.region.y.max(0);
+		let width = (self.region.x + self.region.width).min(src.cols()) - x;
+		let height = (self.region.y + self.region.height).min(src.rows()) - y;
+		if self.region.width <= 0 || self.region.height <= 0 || width <= 0 || height <= 0 {
+			dest.clone_from(src);
+			return Ok(());
+		}
+		*dest = cv::Mat::roi(src, opencv::core::Rect::new(x, y, width, height))?.try_clone()?;
+		Ok(())
+	}
+
+	fn get_parameters(&self) -> Vec<(&'static str, f64)>{
+		vec![
+			("x", self.region.x as f64),
+			("y", self.region.y as f64),
+			("width", self.region.width as f64),
+			("height", self.region.height as f64)
+		]
+	}
+
+	fn set_parameter(&mut self, name: &str, value: f64) -> Result<(), String>{
+		match name {
+			"x" => self.region.x = value as i32,
+			"y" => self.region.y = value as i32,
+			"width" => self.region.width = parse_count(name, value)?,
+			"height" => self.region.height = parse_count(name, value)?,
+			_ => return Err(format!("Stage {} has no parameter {}", self.get_name(), name))
+		}
+		Ok(())
+	}
+}
+
+/// Removes lens distortion of whole frames, see `Undistortion::undistort_frame`.
+pub struct Undistort{
+	pub undistortion: Undistortion
+}
+
+impl ProcessingStage for Undistort {
+	fn get_name(&self) -> &'static str{
+		"undistort"
+	}
+
+	fn process(&mut self, dest: &mut cv::Mat, src: &cv::Mat) -> opencv::Result<()>{
+		self.undistortion.undistort_frame(dest, src)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Adds `amount` to every element, so the order of stages shows in the result.
+	struct Add{
+		name: &'static str,
+		amount: f64
+	}
+
+	impl ProcessingStage for Add {
+		fn get_name(&self) -> &'static str{
+			self.name
+		}
+
+		fn process(&mut self, dest: &mut cv::Mat, src: &cv::Mat) -> opencv::Result<()>{
+			opencv::core::add(src, &opencv::core::Scalar::all(self.amount), dest, &cv::Mat::default(), -1)
+		}
+
+		fn get_parameters(&self) -> Vec<(&'static str, f64)>{
+			vec![("amount", self.amount)]
+		}
+
+		fn set_parameter(&mut self, name: &str, value: f64) -> Result<(), String>{
+			match name {
+				"amount" => self.amount = value,
+				_ => return Err(format!("Stage {} has no parameter {}", self.get_name(), name))
+			}
+			Ok(())
+		}
+	}
+
+	fn frame(rows: i32, cols: i32) -> cv::Mat{
+		cv::Mat::new_rows_cols_with_default(rows, cols, opencv::core::CV_8UC1, opencv::core::Scalar::all(1.)).unwrap()
+	}
+
+	fn value_at(frame: &cv::Mat) -> u8{
+		*frame.at_2d::<u8>(0, 0).unwrap()
+	}
+
+	#[test]
+	fn empty_pipeline_copies_frame(){
+		let mut pipeline = ProcessingPipeline::new();
+		let mut dest = cv::Mat::default();
+		pipeline.process(&mut dest, &frame(2, 3)).unwrap();
+		assert_eq!((dest.rows(), dest.cols()), (2, 3));
+		assert_eq!(value_at(&dest), 1);
+	}
+
+	#[test]
+	fn stages_run_in_order_and_expose_parameters(){
+		let mut pipeline = ProcessingPipeline::new()
+			.with(Add{name: "first", amount: 2.})
+			.with(Add{name: "second", amount: 3.});
+		pipeline.insert(0, Box::new(Add{name: "zeroth", amount: 4.}));
+		let names: Vec<&str> = pipeline.get_stages().iter().map(|stage| stage.get_name()).collect();
+		assert_eq!(names, ["zeroth", "first", "second"]);
+		assert_eq!(pipeline.to_string(), "zeroth(amount=4) -> first(amount=2) -> second(amount=3)");
+
+		pipeline.set_parameter("second", "amount", 10.).unwrap();
+		let mut dest = cv::Mat::default();
+		pipeline.process(&mut dest, &frame(1, 1)).unwrap();
+		assert_eq!(value_at(&dest), 1 + 4 + 2 + 10);
+	}
+
+	#[test]
+	fn unknown_stage_or_parameter_is_error(){
+		let mut pipeline = ProcessingPipeline::new().with(GaussianBlur{kernel_size: 5});
+		assert!(pipeline.set_parameter("threshold", "threshold", 1.).is_err());
+		assert!(pipeline.set_parameter("blur", "sigma", 1.).is_err());
+		assert!(pipeline.set_parameter("blur", "kernel_size", 4.).is_err());
+		pipeline.set_parameter("blur", "kernel_size", 7.).unwrap();
+		assert_eq!(pipeline.get_stages()[0].get_parameters(), [("kernel_size", 7.)]);
+	}
+
+	#[test]
+	fn counts_are_non_negative_integers(){
+		assert_eq!(parse_count("erode", 0.), Ok(0));
+		assert_eq!(parse_count("erode", 3.), Ok(3));
+		assert!(parse_count("erode", -1.).is_err());
+		assert!(parse_count("erode", 1.5).is_err());
+		assert!(parse_count("erode", f64::NAN).is_err());
+	}
+
+	fn crop(region: opencv::core::Rect, src: &cv::Mat) -> cv::Mat{
+		let mut dest = cv::Mat::default();
+		RegionOfInterest{region}.process(&mut dest, src).unwrap();
+		dest
+	}
+
+	#[test]
+	fn region_of_interest_is_clipped_by_frame(){
+		let src = frame(10, 20);
+		let inside = crop(opencv::core::Rect::new(2, 3, 5, 4), &src);
+		assert_eq!((inside.cols(), inside.rows()), (5, 4));
+		let overlapping = crop(opencv::core::Rect::new(-5, 8, 10, 10), &src);
+		assert_eq!((overlapping.cols(), overlapping.rows()), (5, 2));
+		let past_corner = crop(opencv::core::Rect::new(15, 5, 100, 100), &src);
+		assert_eq!((past_corner.cols(), past_corner.rows()), (5, 5));
+	}
+
+	#[test]
+	fn empty_or_outside_region_keeps_whole_frame(){
+		let src = frame(10, 20);
+		for region in [
+			opencv::core::Rect::default(),
+			opencv::core::Rect::new(30, 0, 5, 5),
+			opencv::core::Rect::new(0, -10, 5, 5)
+		] {
+			let dest = crop(region, &src);
+			assert_eq!((dest.cols(), dest.rows()), (20, 10));
+		}
+	}
+}
@@ -1,12 +1,13 @@
 use opencv::{
     prelude as cv,
-    highgui
+    highgui,
+    imgproc
 }; 
 
 
 use bevy::ecs::prelude as ecs;
 use uuid::Uuid;
-use crate::trackers::opencv_trackers::opencv_utilities::frame_component;
+use crate::trackers::opencv_trackers::opencv_utilities::{frame_component, processing_pipeline};
 
 
 
@@ -22,11 +23,46 @@ impl WindowPreviewBuilder{
 	}
 }
 
+/// Region dragged by left mouse button in the window, in image coordinates.
+#[derive(Default)]
+struct RegionSelection{
+	start: Option<opencv::core::Point2i>,
+	current: opencv::core::Point2i,
+	selected: Option<opencv::core::Rect>
+}
+
+impl RegionSelection {
+	fn handle_mouse(&mut self, event: i32, x: i32, y: i32){
+		let point = opencv::core::Point2i::new(x, y);
+		if event == highgui::EVENT_LBUTTONDOWN {
+			self.start = Some(point);
+			self.current = point;
+		}
+		else if event == highgui::EVENT_MOUSEMOVE {
+			self.current = point;
+		}
+		else if event == highgui::EVENT_LBUTTONUP {
+			if let Some(rect) = self.get_dragged() {
+				// ignore clicks
+				if rect.width > 2 && rect.height > 2 {
+					self.selected = Some(rect);
+				}
+			}
+			self.start = None;
+		}
+	}
+
+	fn get_dragged(&self) -> Option<opencv::core::Rect>{
+		self.start.map(|start| opencv::core::Rect::from_points(start, self.current))
+	}
+}
+
 pub struct WindowPreview{
     id_str:	String,
 	title: String,
 	position: opencv::core::Point2i,
 	size: opencv::core::Size2i,
+	region_selection: std::sync::Arc<std::sync::Mutex<RegionSelection>>
 }
 
 impl Default for WindowPreview{
@@ -38,7 +74,17 @@ impl Default for WindowPreview{
 		highgui::named_window(&id, highgui::WINDOW_NORMAL).unwrap();
 		
 
-		let mut window = WindowPreview { id_str: id, title: String::default(), position: opencv::core::Point2i::default(), size: opencv::core::Size2i::default()};
+		let mut window = WindowPreview {
+			id_str: id,
+			title: String::default(),
+			position: opencv::core::Point2i::default(),
+			size: opencv::core::Size2i::default(),
+			region_selection: std::sync::Arc::new(std::sync::Mutex::new(RegionSelection::default()))
+		};
+		let region_selection = window.region_selection.clone();
+		highgui::set_mouse_callback(&window.id_str, Some(Box::new(move |event, x, y, _flags| {
+			region_selection.lock().unwrap().handle_mouse(event, x, y);
+		}))).unwrap();
 		window.set_position(0, 0);
 		window.set_size(640, 480);
 		window.set_title("Window Preview");
@@ -59,6 +105,12 @@ impl  Drop for  WindowPreview {
 impl WindowPreview{
 
 	pub fn display(&self, frame: &opencv::prelude::Mat) -> opencv::Result<()>{
+		// region being dragged is drawn over the frame
+		if let Some(dragged) = self.region_selection.lock().unwrap().get_dragged() {
+			let mut frame = frame.clone();
+			imgproc::rectangle(&mut frame, dragged, opencv::core::Scalar::new(0., 255., 255., 0.), 2, imgproc::LINE_8, 0)?;
+			return highgui::imshow(&self.id_str, &frame);
+		}
 		highgui::imshow(&self.id_str, &*frame)
 	}
 
@@ -103,10 +155,10 @@ impl WindowPreview{
 		&self.size
 	}
 
-	
-
-	fn select_region_block(){
 
+	/// Returns region selected by dragging left mouse button over the window since last call.
+	pub fn take_selected_region(&self) -> Option<opencv::core::Rect>{
+		self.region_selection.lock().unwrap().selected.take()
 	}
 }
 
@@ -117,18 +169,24 @@ pub struct WindowInLayout;
 #[derive(ecs::Component, Default)]
 pub struct WindowPreviewComponent{
 	pub window: WindowPreview,
-	pub processing_function: Option<frame_component::ProcessingFunction>
+	/// applied to the frame before it's displayed, empty pipeline displays the frame as is
+	pub pipeline: std::sync::Mutex<processing_pipeline::ProcessingPipeline>
 }
 
 impl WindowPreviewComponent {
+	pub fn new(window: WindowPreview, pipeline: processing_pipeline::ProcessingPipeline) -> Self{
+		WindowPreviewComponent{window: window, pipeline: std::sync::Mutex::new(pipeline)}
+	}
+
 	pub fn window_display_system(query: ecs::Query<(&WindowPreviewComponent, &frame_component::FrameComponent), ecs::Changed<frame_component::FrameComponent>>){
 		for (window_component, frame_component) in query.iter(){
 			let frame = frame_component.get_frame().lock().unwrap();
+			let mut pipeline = window_component.pipeline.lock().unwrap();
 			#[allow(unused_assignments)]
 			let mut result: opencv::Result<()> = Ok(());
-			if let Some(processing_function) = window_component.processing_function{
+			if !pipeline.is_empty(){
 			    let mut processed_frame = cv::Mat::default();
-				result = match processing_function(&mut processed_frame, &frame){
+				result = match pipeline.process(&mut processed_frame, &frame){
 					Ok(_) => window_component.window.display(&processed_frame),
 					Err(error) => Err(error)
 				};		
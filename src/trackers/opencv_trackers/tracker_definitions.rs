@@ -0,0 +1,44 @@
+use std::path::{Path, PathBuf};
+
+use crate::trackers::opencv_trackers::calibration;
+
+/// Definitions of trackers read from RON file `FILE_NAME` in the config directory, or from file in `ENV_VARIABLE`.
+pub trait DefinitionsFile: serde::de::DeserializeOwned + Default {
+	const FILE_NAME: &'static str;
+	const ENV_VARIABLE: &'static str;
+	/// What is used instead of invalid file, e.g. "IR trackers are disabled".
+	const FALLBACK: &'static str;
+
+	/// Checks values which can't be rejected by deserialisation, `path` of the file is for messages.
+	fn validate(&self, path: &Path) -> Result<(), String>;
+
+	fn path() -> PathBuf{
+		std::env::var_os(Self::ENV_VARIABLE)
+			.map(PathBuf::from)
+			.unwrap_or_else(|| calibration::config_directory().join(Self::FILE_NAME))
+	}
+
+	fn load(path: &Path) -> Result<Self, String>{
+		let content = std::fs::read_to_string(path)
+			.map_err(|error| format!("Unable to read {}: {}", path.display(), error))?;
+		let definitions: Self = ron::from_str(&content)
+			.map_err(|error| format!("Invalid {}: {}", path.display(), error))?;
+		definitions.validate(path)?;
+		Ok(definitions)
+	}
+
+	/// Definitions from the configured file, `Default` when there is no file or it's invalid.
+	fn from_env() -> Self{
+		let path = Self::path();
+		if !path.exists() {
+			return Self::default();
+		}
+		match Self::load(&path) {
+			Ok(definitions) => definitions,
+			Err(error) => {
+				println!("{}, {}", error, Self::FALLBACK);
+				Self::default()
+			}
+		}
+	}
+}
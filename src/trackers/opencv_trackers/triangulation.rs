@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+
+use bevy::ecs::prelude as ecs;
+
+use opencv::{
+	prelude::*,
+	core
+};
+
+use crate::trackers::tracker;
+use crate::trackers::opencv_trackers::calibration::CalibrationCache;
+use crate::trackers::opencv_trackers::calibration::extrinsics::CameraExtrinsics;
+use crate::trackers::opencv_trackers::calibration::intrinsics::CameraIntrinsics;
+
+/// Blob of single tracker seen by single camera.
+pub struct Observation{
+	pub camera_id: String,
+	/// undistorted blob centre in pixels
+	pub pixel: core::Point2f,
+	pub frame_size: core::Size,
+	/// position derived from blob radius, in the camera frame
+	pub radius_position: tracker::Position
+}
+
+struct CalibratedCamera{
+	intrinsics: CameraIntrinsics,
+	extrinsics: CameraExtrinsics
+}
+
+/// Combines observations of one tracker from all cameras into single position.
+///
+/// Cameras with intrinsics and extrinsics (calibrated against the same reference camera) are triangulated by DLT,
+///  views which don't agree with the others (reprojection error above `max_reprojection_error`) are dropped one by one.
+/// When less than two views remain, position from blob radius of the closest remaining view is used instead.
+/// Positions are in the reference camera frame. Without any calibrated camera, only the camera with the lowest id reports
+///  positions in its own frame, so the frame doesn't jump between cameras. Once some camera is calibrated, uncalibrated cameras are ignored.
+#[derive(ecs::Resource)]
+pub struct Triangulator{
+	pub enabled: bool,
+	/// in pixels
+	pub max_reprojection_error: f64,
+	cameras: std::sync::Mutex<CalibrationCache<CalibratedCamera>>
+}
+
+impl Default for Triangulator {
+	fn default() -> Self {
+		Triangulator{
+			enabled: true,
+			max_reprojection_error: 5.,
+			cameras: std::sync::Mutex::new(CalibrationCache::default())
+		}
+	}
+}
+
+impl Triangulator {
+	pub const ENV_VARIABLE: &'static str = "RTRACK_TRIANGULATION";
+	pub const MAX_ERROR_ENV_VARIABLE: &'static str = "RTRACK_TRIANGULATION_MAX_ERROR";
+
+	pub fn from_env() -> Self{
+		let mut triangulator = Triangulator::default();
+		if let Ok(value) = std::env::var(Self::ENV_VARIABLE) {
+			triangulator.enabled = !matches!(value.as_str(), "0" | "off" | "false");
+		}
+		if let Some(error) = std::env::var(Self::MAX_ERROR_ENV_VARIABLE).ok().and_then(|value| value.parse().ok()) {
+			triangulator.max_reprojection_error = error;
+		}
+		triangulator
+	}
+
+	pub fn locate(&self, observations: &[Observation]) -> Option<tracker::Position>{
+		let mut cameras = self.cameras.lock().unwrap();
+		for observation in observations {
+			cameras.load(&observation.camera_id, |camera_id| {
+				match (CameraIntrinsics::load(camera_id), CameraExtrinsics::load(camera_id)) {
+					(Ok(intrinsics), Ok(extrinsics)) => Some(CalibratedCamera{intrinsics: intrinsics, extrinsics: extrinsics}),
+					_ => None
+				}
+			});
+		}
+		let cameras = &*cameras;
+
+		let calibrated: Vec<(&Observation, &CalibratedCamera)> = observations.iter()
+			.filter_map(|observation| cameras.get(&observation.camera_id).map(|camera| (observation, camera)))
+			.collect();
+		if calibrated.is_empty() {
+			if cameras.has_any() {
+				return None;
+			}
+			let stable_camera = cameras.get_camera_ids().min()?;
+			return observations.iter()
+				.find(|observation| observation.camera_id == stable_camera)
+				.map(|observation| {
+					let position = &observation.radius_position;
+					tracker::Position{x: position.x, y: position.y, z: position.z}
+				});
+		}
+
+		// only cameras calibrated against the same reference share coordinate frame, ties are broken by reference id so the frame is stable
+		let mut references: HashMap<&str, usize> = HashMap::new();
+		for (_, camera) in calibrated.iter() {
+			*references.entry(camera.extrinsics.reference_camera.as_str()).or_default() += 1;
+		}
+		let (reference, _) = references.into_iter()
+			.max_by(|(reference_a, count_a), (reference_b, count_b)| count_a.cmp(count_b).then(reference_b.cmp(reference_a)))?;
+		let mut views: Vec<_> = calibrated.iter()
+			.filter(|(_, camera)| camera.extrinsics.reference_camera == reference)
+			.copied()
+			.collect();
+
+		if self.enabled {
+			while views.len() >= 2 {
+				let (point, errors) = match Self::triangulate(&views) {
+					Ok(result) => result,
+					Err(_) => break
+				};
+				let (worst, worst_error) = errors.iter().copied().enumerate()
+					.max_by(|(_, a), (_, b)| a.total_cmp(b))
+					.unwrap();
+				if worst_error <= self.max_reprojection_error {
+					return Some(tracker::Position{x: point[0], y: point[1], z: point[2]});
+				}
+				views.remove(worst);
+			}
+		}
+
+		// single camera fallback from views left after outlier rejection, depth from blob radius
+		// the closest ball has the largest blob, so its radius is the most precise
+		let distance = |observation: &Observation| {
+			let position = &observation.radius_position;
+			position.x * position.x + position.y * position.y + position.z * position.z
+		};
+		views.iter()
+			.min_by(|(a, _), (b, _)| distance(a).total_cmp(&distance(b)))
+			.map(|(observation, camera)| {
+				let position = &observation.radius_position;
+				let point = camera.extrinsics.pose.inverse().transform([position.x, position.y, position.z]);
+				tracker::Position{x: point[0], y: point[1], z: point[2]}
+			})
+	}
+
+	/// Undistorted pixel and radius in pixels of a ball of `real_radius` at `position` (in the reference frame)
+	///  seen by camera `camera_id` in frames of `frame_size`. None when the camera isn't calibrated or the ball is behind it.
+	pub fn project(&self, camera_id: &str, frame_size: core::Size, position: &tracker::Position, real_radius: f64) -> Option<(core::Point2f, f32)>{
+		let cameras = self.cameras.lock().unwrap();
+		let camera = cameras.get(camera_id)?;
+		let projection = Self::projection(camera, frame_size).ok()?;
+		let projected: Vec<f64> = projection.iter()
+			.map(|row| row[0] * position.x + row[1] * position.y + row[2] * position.z + row[3])
+			.collect();
+		// last row of the camera matrix is (0, 0, 1), so this is the depth
+		let depth = projected[2];
+		if !(depth > 0.) {
+			return None;
+		}
+		let (fx, _) = camera.intrinsics.scaled_to(frame_size).and_then(|intrinsics| intrinsics.focal_length()).ok()?;
+		let pixel = core::Point2f::new((projected[0] / depth) as f32, (projected[1] / depth) as f32);
+		Some((pixel, (fx * real_radius / depth) as f32))
+	}
+
+	/// DLT triangulation from all views, returns point in the reference frame and reprojection error of each view in pixels.
+	/// Fails when the point is behind any of the cameras.
+	fn triangulate(views: &[(&Observation, &CalibratedCamera)]) -> opencv::Result<([f64; 3], Vec<f64>)>{
+		let mut projections = vec![];
+		let mut rows = vec![];
+		for (observation, camera) in views {
+			let projection = Self::projection(camera, observation.frame_size)?;
+			let (u, v) = (observation.pixel.x as f64, observation.pixel.y as f64);
+			let mut row_u = [0.; 4];
+			let mut row_v = [0.; 4];
+			for column in 0..4 {
+				row_u[column] = u * projection[2][column] - projection[0][column];
+				row_v[column] = v * projection[2][column] - projection[1][column];
+			}
+			rows.push(row_u);
+			rows.push(row_v);
+			projections.push(projection);
+		}
+
+		let mut solution = core::Mat::default();
+		core::SVD::solve_z(&core::Mat::from_slice_2d(&rows)?, &mut solution)?;
+		let w = *solution.at::<f64>(3)?;
+		let point = [*solution.at::<f64>(0)? / w, *solution.at::<f64>(1)? / w, *solution.at::<f64>(2)? / w];
+
+		let mut errors = vec![];
+		for ((observation, _), projection) in views.iter().zip(projections.iter()) {
+			let projected: Vec<f64> = projection.iter()
+				.map(|row| row[0] * point[0] + row[1] * point[1] + row[2] * point[2] + row[3])
+				.collect();
+			if !(projected[2] > 0.) {
+				return Err(opencv::Error::new(core::StsError, "Triangulated point is behind the camera".to_owned()));
+			}
+			let dx = projected[0] / projected[2] - observation.pixel.x as f64;
+			let dy = projected[1] / projected[2] - observation.pixel.y as f64;
+			errors.push((dx * dx + dy * dy).sqrt());
+		}
+		Ok((point, errors))
+	}
+
+	/// `camera_matrix * [rotation | translation]` for frames of `frame_size`.
+	fn projection(camera: &CalibratedCamera, frame_size: core::Size) -> opencv::Result<[[f64; 4]; 3]>{
+		let intrinsics = camera.intrinsics.scaled_to(frame_size)?;
+		let (fx, fy) = intrinsics.focal_length()?;
+		let (cx, cy) = intrinsics.principal_point()?;
+		let camera_matrix = [[fx, 0., cx], [0., fy, cy], [0., 0., 1.]];
+		let pose = &camera.extrinsics.pose;
+
+		let mut projection = [[0.; 4]; 3];
+		for row in 0..3 {
+			for column in 0..3 {
+				projection[row][column] = (0..3).map(|k| camera_matrix[row][k] * pose.rotation[k][column]).sum();
+			}
+			projection[row][3] = (0..3).map(|k| camera_matrix[row][k] * pose.translation[k]).sum();
+		}
+		Ok(projection)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::trackers::opencv_trackers::calibration::extrinsics::Pose;
+
+	const FRAME_SIZE: core::Size = core::Size{width: 640, height: 480};
+
+	/// Ideal camera at `position` of the reference frame, looking along its z axis.
+	fn camera(position: [f64; 3]) -> CalibratedCamera{
+		CalibratedCamera{
+			intrinsics: CameraIntrinsics::ideal(FRAME_SIZE, 500.).unwrap(),
+			extrinsics: CameraExtrinsics{
+				pose: Pose{translation: [-position[0], -position[1], -position[2]], ..Default::default()},
+				reference_camera: "a".to_owned(),
+				reprojection_error: 0.
+			}
+		}
+	}
+
+	fn triangulator(cameras: Vec<(&str, CalibratedCamera)>) -> Triangulator{
+		let triangulator = Triangulator::default();
+		for (camera_id, camera) in cameras {
+			triangulator.cameras.lock().unwrap().load(camera_id, |_| Some(camera));
+		}
+		triangulator
+	}
+
+	fn observation(triangulator: &Triangulator, camera_id: &str, position: &tracker::Position) -> Observation{
+		let (pixel, _) = triangulator.project(camera_id, FRAME_SIZE, position, 0.02).unwrap();
+		Observation{camera_id: camera_id.to_owned(), pixel: pixel, frame_size: FRAME_SIZE, radius_position: tracker::Position::default()}
+	}
+
+	fn assert_position_near(actual: &tracker::Position, expected: [f64; 3]){
+		let actual = [actual.x, actual.y, actual.z];
+		for axis in 0..3 {
+			assert!((actual[axis] - expected[axis]).abs() < 1e-3, "{:?} != {:?}", actual, expected);
+		}
+	}
+
+	#[test]
+	fn projected_point_is_triangulated_back(){
+		let triangulator = triangulator(vec![("a", camera([0.; 3])), ("b", camera([0.5, 0., 0.]))]);
+		let position = tracker::Position{x: 0.1, y: -0.2, z: 3.};
+		let observations = [observation(&triangulator, "a", &position), observation(&triangulator, "b", &position)];
+
+		let cameras = triangulator.cameras.lock().unwrap();
+		let views: Vec<(&Observation, &CalibratedCamera)> = observations.iter()
+			.map(|observation| (observation, cameras.get(&observation.camera_id).unwrap()))
+			.collect();
+		let (point, errors) = Triangulator::triangulate(&views).unwrap();
+		assert_position_near(&tracker::Position{x: point[0], y: point[1], z: point[2]}, [0.1, -0.2, 3.]);
+		assert!(errors.iter().all(|error| *error < 0.01));
+	}
+
+	#[test]
+	fn view_which_disagrees_is_dropped(){
+		let triangulator = triangulator(vec![("a", camera([0.; 3])), ("b", camera([0.5, 0., 0.])), ("c", camera([0., 0.3, 0.]))]);
+		let position = tracker::Position{x: 0.1, y: -0.2, z: 3.};
+		let mut corrupted = observation(&triangulator, "c", &position);
+		corrupted.pixel.x += 40.;
+		let observations = [observation(&triangulator, "a", &position), observation(&triangulator, "b", &position), corrupted];
+
+		assert_position_near(&triangulator.locate(&observations).unwrap(), [0.1, -0.2, 3.]);
+	}
+
+	#[test]
+	fn single_view_falls_back_to_radius_position(){
+		let triangulator = triangulator(vec![("a", camera([0.; 3])), ("b", camera([0.5, 0., 0.]))]);
+		let mut observation = observation(&triangulator, "b", &tracker::Position{x: 0.5, y: 0., z: 2.});
+		observation.radius_position = tracker::Position{x: 0., y: 0., z: 2.};
+
+		// radius position is in the frame of camera b, result in the reference frame
+		assert_position_near(&triangulator.locate(&[observation]).unwrap(), [0.5, 0., 2.]);
+	}
+}
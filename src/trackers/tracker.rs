@@ -5,13 +5,91 @@ pub struct Position{
 	pub y: f64,
 	pub z: f64
 }
-#[derive(Default)]
-pub struct Rotation(Position);
+/// Unit quaternion, identity by default.
+#[derive(Clone, Copy, Debug)]
+pub struct Rotation{
+	pub x: f64,
+	pub y: f64,
+	pub z: f64,
+	pub w: f64
+}
+
+impl Default for Rotation {
+	fn default() -> Self {
+		Rotation{x: 0., y: 0., z: 0., w: 1.}
+	}
+}
+
+impl Rotation {
+	/// Quaternion of rotation matrix (rows of orthonormal matrix).
+	pub fn from_matrix(m: &[[f64; 3]; 3]) -> Self{
+		// https://www.euclideanspace.com/maths/geometry/rotations/conversions/matrixToQuaternion/
+		let trace = m[0][0] + m[1][1] + m[2][2];
+		if trace > 0. {
+			let s = (trace + 1.).sqrt() * 2.;
+			Rotation{w: s / 4., x: (m[2][1] - m[1][2]) / s, y: (m[0][2] - m[2][0]) / s, z: (m[1][0] - m[0][1]) / s}
+		}
+		else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+			let s = (1. + m[0][0] - m[1][1] - m[2][2]).sqrt() * 2.;
+			Rotation{w: (m[2][1] - m[1][2]) / s, x: s / 4., y: (m[0][1] + m[1][0]) / s, z: (m[0][2] + m[2][0]) / s}
+		}
+		else if m[1][1] > m[2][2] {
+			let s = (1. + m[1][1] - m[0][0] - m[2][2]).sqrt() * 2.;
+			Rotation{w: (m[0][2] - m[2][0]) / s, x: (m[0][1] + m[1][0]) / s, y: s / 4., z: (m[1][2] + m[2][1]) / s}
+		}
+		else {
+			let s = (1. + m[2][2] - m[0][0] - m[1][1]).sqrt() * 2.;
+			Rotation{w: (m[1][0] - m[0][1]) / s, x: (m[0][2] + m[2][0]) / s, y: (m[1][2] + m[2][1]) / s, z: s / 4.}
+		}
+	}
+}
+
+/// Body part or object which the tracker is attached to.
+#[derive(Component, Default, Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum TrackerRole{
+	#[default]
+	Generic,
+	Head,
+	LeftHand,
+	RightHand,
+	Waist,
+	LeftFoot,
+	RightFoot
+}
 
 #[derive(Component, Default)]
 pub struct TrackerData { 
 	pub position: Position,
-	pub rotation: Rotation
+	/// written only by marker trackers and not used by consumers of tracker data yet
+	pub rotation: Rotation,
+	/// 0 (lost) to 1, how much the last position can be trusted
+	pub confidence: f64
+}
+
+impl TrackerData {
+	/// Replaces data of tracker `entity` by `found` once `commands` are applied, lost tracker (None) keeps its last pose
+	///  and only its confidence drops. Transform of the entity (e.g. its debug mesh) follows the position.
+	/// Nothing is written when the tracker is despawned in the meantime.
+	pub fn report(commands: &mut Commands, entity: Entity, found: Option<TrackerData>){
+		commands.add(move |world: &mut World| {
+			let mut entity_mut = match world.get_entity_mut(entity) {
+				Some(entity_mut) => entity_mut,
+				None => return
+			};
+			if let (Some(found), Some(mut transform)) = (&found, entity_mut.get_mut::<bevy::prelude::Transform>()) {
+				let position = &found.position;
+				transform.translation = bevy::prelude::Vec3::new(position.x as f32, position.y as f32, position.z as f32);
+			}
+			if let Some(mut data) = entity_mut.get_mut::<TrackerData>(){
+				match found {
+					Some(found) => *data = found,
+					None => if data.confidence != 0. {
+						data.confidence = 0.;
+					}
+				}
+			}
+		});
+	}
 }
 
 fn print_tracker(tracker: &TrackerData){
@@ -25,3 +103,34 @@ pub fn print_trackers_system(query: Query<&TrackerData, Changed<TrackerData>>){
 	}
 }
 
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Compares quaternions up to sign, `q` and `-q` are the same rotation.
+	fn assert_rotation(rotation: Rotation, [x, y, z, w]: [f64; 4]){
+		let dot = rotation.x * x + rotation.y * y + rotation.z * z + rotation.w * w;
+		assert!((dot.abs() - 1.).abs() < 1e-9, "{:?} is not [{}, {}, {}, {}]", rotation, x, y, z, w);
+	}
+
+	#[test]
+	fn identity_matrix_is_identity_rotation(){
+		let rotation = Rotation::from_matrix(&[[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]]);
+		assert_rotation(rotation, [0., 0., 0., 1.]);
+	}
+
+	#[test]
+	fn quarter_turns_about_each_axis(){
+		let half = std::f64::consts::FRAC_1_SQRT_2;
+		assert_rotation(Rotation::from_matrix(&[[1., 0., 0.], [0., 0., -1.], [0., 1., 0.]]), [half, 0., 0., half]);
+		assert_rotation(Rotation::from_matrix(&[[0., 0., 1.], [0., 1., 0.], [-1., 0., 0.]]), [0., half, 0., half]);
+		assert_rotation(Rotation::from_matrix(&[[0., -1., 0.], [1., 0., 0.], [0., 0., 1.]]), [0., 0., half, half]);
+	}
+
+	#[test]
+	fn half_turns_use_largest_diagonal(){
+		assert_rotation(Rotation::from_matrix(&[[1., 0., 0.], [0., -1., 0.], [0., 0., -1.]]), [1., 0., 0., 0.]);
+		assert_rotation(Rotation::from_matrix(&[[-1., 0., 0.], [0., 1., 0.], [0., 0., -1.]]), [0., 1., 0., 0.]);
+		assert_rotation(Rotation::from_matrix(&[[-1., 0., 0.], [0., -1., 0.], [0., 0., 1.]]), [0., 0., 1., 0.]);
+	}
+}